@@ -9,7 +9,7 @@ use regex::Regex;
 use std::sync::Arc;
 
 pub fn regex_str_parse(regex: &str, array: &dyn Array) -> Result<Vec<Arc<dyn Array>>, ArrowError> {
-    let re = Regex::new(&regex).map_err(|e| {
+    let re = Regex::new(regex).map_err(|e| {
         ArrowError::ComputeError(format!("Regular expression did not compile: {e:?}"))
     })?;
 
@@ -0,0 +1,29 @@
+use datafusion_expr::AggregateUDF;
+
+use std::sync::{Arc, LazyLock};
+
+mod top_hitters;
+
+pub use top_hitters::TopHitters;
+
+/// Return a [`AggregateUDF`] implementation of top_hitters
+pub fn top_hitters_udaf() -> Arc<AggregateUDF> {
+    // Singleton instance of the function
+    static INSTANCE: LazyLock<Arc<AggregateUDF>> = LazyLock::new(|| Arc::new(AggregateUDF::from(TopHitters::new())));
+    Arc::clone(&INSTANCE)
+}
+
+pub mod expr_fn {
+    use datafusion_expr::Expr;
+
+    /// Returns the `k` values with the highest total weight, approximated with a Space-Saving sketch.
+    pub fn top_hitters(value: Expr, weight: Expr, k: Expr) -> Expr {
+        super::top_hitters_udaf().call(vec![value, weight, k])
+    }
+}
+
+pub fn functions() -> Vec<Arc<AggregateUDF>> {
+    vec![
+        top_hitters_udaf()
+    ]
+}
@@ -0,0 +1,257 @@
+use arrow_schema::{DataType, Field, FieldRef, Fields};
+
+use datafusion::arrow::array::{Array, ArrayRef, AsArray, Float64Array, StructArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::Float64Type;
+use datafusion::arrow::row::{RowConverter, Rows, SortField};
+use datafusion::physical_expr::expressions::Literal;
+
+use datafusion_common::utils::SingleRowListArrayBuilder;
+use datafusion_common::{exec_err, plan_err, Result, ScalarValue};
+
+use datafusion_expr::function::{AccumulatorArgs, StateFieldsArgs};
+use datafusion_expr::utils::format_state_name;
+use datafusion_expr::{Accumulator, AggregateUDFImpl, Signature, Volatility};
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Minimal number of counters kept by the sketch, regardless of the number of requested values
+const MIN_CAPACITY: usize = 1000;
+
+/// Number of counters kept for every requested value
+const CAPACITY_FACTOR: usize = 10;
+
+/// Approximate most frequent values, `top_hitters(value, weight, k)` returns a list of
+/// `{value, count}` structs with the `k` values with the highest total weight
+#[derive(Debug)]
+pub struct TopHitters {
+    signature: Signature
+}
+
+impl TopHitters {
+    pub fn new() -> Self {
+        Self {
+            signature: Signature::user_defined(Volatility::Immutable)
+        }
+    }
+}
+
+impl Default for TopHitters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AggregateUDFImpl for TopHitters {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn name(&self) -> &str {
+        "top_hitters"
+    }
+
+    fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    fn coerce_types(&self, arg_types: &[DataType]) -> Result<Vec<DataType>> {
+        let [value, weight, k] = arg_types else {
+            return plan_err!("top_hitters expects 3 arguments, found {}", arg_types.len());
+        };
+        let weight = match weight {
+            t if t.is_integer() || t.is_null() => DataType::Int64,
+            t if t.is_numeric() => DataType::Float64,
+            t => return plan_err!("Weight of top_hitters must be numeric, found {}", t)
+        };
+        if !k.is_integer() {
+            return plan_err!("Number of values of top_hitters must be an integer, found {}", k);
+        }
+        Ok(vec![value.clone(), weight, DataType::Int64])
+    }
+
+    fn return_type(&self, arg_types: &[DataType]) -> Result<DataType> {
+        Ok(DataType::new_list(DataType::Struct(hitter_fields(&arg_types[0], &arg_types[1])), true))
+    }
+
+    fn accumulator(&self, acc_args: AccumulatorArgs) -> Result<Box<dyn Accumulator>> {
+        let k = match acc_args.exprs[2].as_any().downcast_ref::<Literal>().map(|l| l.value()) {
+            Some(ScalarValue::Int64(Some(k))) if *k > 0 => *k as usize,
+            _ => return plan_err!("Number of values of top_hitters must be a positive constant")
+        };
+        let value_type = acc_args.exprs[0].data_type(acc_args.schema)?;
+        Ok(Box::new(TopHittersAccumulator {
+            k,
+            converter: RowConverter::new(vec![SortField::new(value_type.clone())])?,
+            value_type,
+            count_type: acc_args.exprs[1].data_type(acc_args.schema)?,
+            sketch: SpaceSaving::new(MIN_CAPACITY.max(k.saturating_mul(CAPACITY_FACTOR)))
+        }))
+    }
+
+    fn state_fields(&self, args: StateFieldsArgs) -> Result<Vec<FieldRef>> {
+        Ok(vec![
+            Arc::new(Field::new_list(format_state_name(args.name, "values"), Field::new_list_field(args.input_fields[0].data_type().clone(), true), true)),
+            Arc::new(Field::new_list(format_state_name(args.name, "counts"), Field::new_list_field(DataType::Float64, true), true)),
+            Arc::new(Field::new(format_state_name(args.name, "floor"), DataType::Float64, true))
+        ])
+    }
+}
+
+fn hitter_fields(value_type: &DataType, count_type: &DataType) -> Fields {
+    Fields::from(vec![
+        Field::new("value", value_type.clone(), true),
+        Field::new("count", count_type.clone(), true)
+    ])
+}
+
+/// Weighted Space-Saving sketch, pruned back to its capacity when it holds twice as many counters
+///
+/// Values are kept in the row format of a [`RowConverter`], so they are hashed as plain bytes.
+#[derive(Debug)]
+struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<Box<[u8]>, f64>,
+    /// Upper bound of the weight of every value without a counter
+    floor: f64
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            counters: HashMap::new(),
+            floor: 0.0
+        }
+    }
+
+    fn add(&mut self, value: &[u8], weight: f64) {
+        match self.counters.get_mut(value) {
+            Some(count) => *count += weight,
+            None => {
+                self.counters.insert(value.into(), self.floor + weight);
+                if self.counters.len() >= 2 * self.capacity {
+                    self.prune();
+                }
+            }
+        }
+    }
+
+    /// Merges the counters of another sketch, values missing on one side are counted with its floor
+    fn merge(&mut self, mut counters: HashMap<Box<[u8]>, f64>, floor: f64) {
+        for (value, count) in self.counters.iter_mut() {
+            *count += counters.remove(value).unwrap_or(floor);
+        }
+        for (value, count) in counters {
+            self.counters.insert(value, count + self.floor);
+        }
+        self.floor += floor;
+        self.prune();
+    }
+
+    /// Drops the smallest counters, the floor becomes the largest dropped count
+    fn prune(&mut self) {
+        if self.counters.len() <= self.capacity {
+            return;
+        }
+        let mut counts: Vec<f64> = self.counters.values().copied().collect();
+        let (_, threshold, _) = counts.select_nth_unstable_by(self.capacity, |a, b| b.total_cmp(a));
+        let threshold = *threshold;
+        self.counters.retain(|_, c| *c > threshold);
+        self.floor = self.floor.max(threshold);
+    }
+
+    /// Counters with the highest counts, in descending order
+    fn top(&self, k: usize) -> Vec<(&[u8], f64)> {
+        let mut counters: Vec<_> = self.counters.iter().map(|(v, c)| (v.as_ref(), *c)).collect();
+        counters.sort_by(|a, b| b.1.total_cmp(&a.1));
+        counters.truncate(k);
+        counters
+    }
+}
+
+#[derive(Debug)]
+struct TopHittersAccumulator {
+    k: usize,
+    converter: RowConverter,
+    value_type: DataType,
+    count_type: DataType,
+    sketch: SpaceSaving
+}
+
+impl TopHittersAccumulator {
+    fn rows(&self, values: &ArrayRef) -> Result<Rows> {
+        Ok(self.converter.convert_columns(&[Arc::clone(values)])?)
+    }
+
+    fn values_to_array<'a>(&self, values: impl IntoIterator<Item = &'a [u8]>) -> Result<ArrayRef> {
+        let parser = self.converter.parser();
+        let mut arrays = self.converter.convert_rows(values.into_iter().map(|v| parser.parse(v)))?;
+        // dictionaries are decoded to their values
+        Ok(cast(&arrays.remove(0), &self.value_type)?)
+    }
+}
+
+impl Accumulator for TopHittersAccumulator {
+    fn update_batch(&mut self, values: &[ArrayRef]) -> Result<()> {
+        let weights = cast(&values[1], &DataType::Float64)?;
+        let weights = weights.as_primitive::<Float64Type>();
+        let rows = self.rows(&values[0])?;
+        for (row, weight) in rows.iter().zip(weights) {
+            if let Some(weight) = weight {
+                self.sketch.add(row.as_ref(), weight);
+            }
+        }
+        Ok(())
+    }
+
+    fn evaluate(&mut self) -> Result<ScalarValue> {
+        let top = self.sketch.top(self.k);
+        let values = self.values_to_array(top.iter().map(|(v, _)| *v))?;
+        let counts: ArrayRef = Arc::new(Float64Array::from_iter_values(top.iter().map(|(_, c)| *c)));
+        let hitters = StructArray::try_new(
+            hitter_fields(&self.value_type, &self.count_type),
+            vec![values, cast(&counts, &self.count_type)?],
+            None
+        )?;
+        Ok(SingleRowListArrayBuilder::new(Arc::new(hitters)).build_list_scalar())
+    }
+
+    fn size(&self) -> usize {
+        size_of_val(self)
+            + self.converter.size()
+            + self.sketch.counters.capacity() * size_of::<(Box<[u8]>, f64)>()
+            + self.sketch.counters.keys().map(|v| v.len()).sum::<usize>()
+    }
+
+    fn state(&mut self) -> Result<Vec<ScalarValue>> {
+        let values = self.values_to_array(self.sketch.counters.keys().map(|v| v.as_ref()))?;
+        let counts = Float64Array::from_iter_values(self.sketch.counters.values().copied());
+        Ok(vec![
+            SingleRowListArrayBuilder::new(values).build_list_scalar(),
+            SingleRowListArrayBuilder::new(Arc::new(counts)).build_list_scalar(),
+            ScalarValue::Float64(Some(self.sketch.floor))
+        ])
+    }
+
+    fn merge_batch(&mut self, states: &[ArrayRef]) -> Result<()> {
+        let [values, counts, floors] = states else {
+            return exec_err!("top_hitters expects 3 state columns, found {}", states.len());
+        };
+        let (values, counts, floors) = (values.as_list::<i32>(), counts.as_list::<i32>(), floors.as_primitive::<Float64Type>());
+        for i in 0..values.len() {
+            if values.is_null(i) {
+                continue;
+            }
+            let (v, c) = (self.rows(&values.value(i))?, counts.value(i));
+            let counters = v.iter()
+                .map(|r| Box::from(r.as_ref()))
+                .zip(c.as_primitive::<Float64Type>().values().iter().copied())
+                .collect();
+            self.sketch.merge(counters, floors.value(i));
+        }
+        Ok(())
+    }
+}
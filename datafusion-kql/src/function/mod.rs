@@ -1,2 +1,3 @@
+pub mod aggregate;
 pub mod string;
 pub mod math;
@@ -1,6 +1,9 @@
 pub mod function;
 pub mod planner;
+mod materialize;
 mod operators;
+mod sample;
+mod scan;
 mod session;
 
 #[macro_use]
@@ -9,9 +12,16 @@ pub mod macros;
 pub use operators::*;
 pub use session::*;
 
+use async_trait::async_trait;
+
+use datafusion::execution::SessionState;
+use datafusion::execution::context::QueryPlanner;
+use datafusion::physical_plan::ExecutionPlan;
+use datafusion::physical_planner::{DefaultPhysicalPlanner, ExtensionPlanner, PhysicalPlanner};
+
 use datafusion_common::Result;
 use datafusion_execution::FunctionRegistry;
-use datafusion_expr::ScalarUDF;
+use datafusion_expr::{AggregateUDF, LogicalPlan, ScalarUDF};
 
 use log::debug;
 
@@ -20,6 +30,7 @@ use std::sync::Arc;
 /// Fluent-style API for creating `Expr`s
 #[allow(unused)]
 pub mod expr_fn {
+    pub use super::function::aggregate::expr_fn::*;
     pub use super::function::math::expr_fn::*;
     pub use super::function::string::expr_fn::*;
 }
@@ -32,6 +43,30 @@ pub fn all_default_scalar_functions() -> Vec<Arc<ScalarUDF>> {
         .collect::<Vec<_>>()
 }
 
+/// Returns all default aggregate functions
+pub fn all_default_aggregate_functions() -> Vec<Arc<AggregateUDF>> {
+    function::aggregate::functions()
+}
+
+/// Returns the planners of the plan nodes of KQL operators like `scan` and `sample`
+pub fn all_extension_planners() -> Vec<Arc<dyn ExtensionPlanner + Send + Sync>> {
+    vec![Arc::new(scan::ScanPlanner), Arc::new(sample::SamplePlanner)]
+}
+
+/// Query planner with [`all_extension_planners`], set it on the [`SessionState`] of a
+/// session that runs KQL queries
+#[derive(Debug, Default)]
+pub struct KqlQueryPlanner;
+
+#[async_trait]
+impl QueryPlanner for KqlQueryPlanner {
+    async fn create_physical_plan(&self, logical_plan: &LogicalPlan, session_state: &SessionState) -> Result<Arc<dyn ExecutionPlan>> {
+        DefaultPhysicalPlanner::with_extension_planners(all_extension_planners())
+            .create_physical_plan(logical_plan, session_state)
+            .await
+    }
+}
+
 /// Registers all enabled packages with a [`FunctionRegistry`]
 pub fn register_all(registry: &mut dyn FunctionRegistry) -> Result<()> {
     let scalar_functions: Vec<Arc<ScalarUDF>> = all_default_scalar_functions();
@@ -42,5 +77,14 @@ pub fn register_all(registry: &mut dyn FunctionRegistry) -> Result<()> {
         }
         Ok(()) as Result<()>
     })?;
+
+    let aggregate_functions: Vec<Arc<AggregateUDF>> = all_default_aggregate_functions();
+    aggregate_functions.into_iter().try_for_each(|udaf| {
+        let existing_udaf = registry.register_udaf(udaf)?;
+        if let Some(existing_udaf) = existing_udaf {
+            debug!("Overwrite existing UDAF: {}", existing_udaf.name());
+        }
+        Ok(()) as Result<()>
+    })?;
     Ok(())
 }
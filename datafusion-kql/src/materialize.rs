@@ -0,0 +1,57 @@
+use arrow_schema::SchemaRef;
+
+use async_trait::async_trait;
+
+use datafusion::arrow::array::RecordBatch;
+use datafusion::physical_plan::{collect, ExecutionPlan};
+
+use datafusion_catalog::{Session, TableProvider};
+use datafusion_catalog::memory::MemorySourceConfig;
+
+use datafusion_common::Result;
+
+use datafusion_expr::{Expr, LogicalPlan, TableType};
+
+use tokio::sync::OnceCell;
+
+use std::any::Any;
+use std::sync::Arc;
+
+/// Result of a `materialize()` expression, computed on the first scan and reused by later scans
+#[derive(Debug)]
+pub(crate) struct MaterializedTable {
+    plan: LogicalPlan,
+    batches: OnceCell<Vec<RecordBatch>>
+}
+
+impl MaterializedTable {
+    pub fn new(plan: LogicalPlan) -> Self {
+        MaterializedTable {
+            plan,
+            batches: OnceCell::new()
+        }
+    }
+}
+
+#[async_trait]
+impl TableProvider for MaterializedTable {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        Arc::new(self.plan.schema().as_arrow().clone())
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Temporary
+    }
+
+    async fn scan(&self, state: &dyn Session, projection: Option<&Vec<usize>>, _filters: &[Expr], _limit: Option<usize>) -> Result<Arc<dyn ExecutionPlan>> {
+        let batches = self.batches.get_or_try_init(|| async {
+            let plan = state.create_physical_plan(&self.plan).await?;
+            collect(plan, state.task_ctx()).await
+        }).await?;
+        Ok(MemorySourceConfig::try_new_exec(std::slice::from_ref(batches), self.schema(), projection.cloned())?)
+    }
+}
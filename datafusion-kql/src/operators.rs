@@ -1,39 +1,225 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use arrow_schema::{DataType, Field, Fields, TimeUnit};
 
-use datafusion_common::{Column, DFSchema, Result, ScalarValue};
+use datafusion::functions_nested::expr_fn::{array_length, array_slice, make_array, map_keys, map_values, range};
+use datafusion::functions_nested::map::map;
+use datafusion::functions_window::expr_fn::row_number;
 
-use datafusion_expr::{Expr, LogicalPlan, LogicalPlanBuilder, SortExpr, Values};
+use datafusion::arrow::datatypes::IntervalMonthDayNano;
 
+use datafusion_common::{plan_err, Column, DFSchema, JoinType, Result, ScalarValue, TableReference, UnnestOptions};
+
+use datafusion_expr::{cast, ident, lit, when, Expr, ExprFunctionExt, ExprSchemable, Extension, LogicalPlan, LogicalPlanBuilder, SortExpr, Values};
+
+use datafusion_functions::expr_fn::{coalesce, date_bin, get_field, greatest, named_struct};
+
+use datafusion_functions_aggregate::expr_fn::{array_agg, max, min};
 use datafusion_functions_aggregate::count::count_all;
 
+use itertools::Itertools;
+
 use wildmatch::WildMatch;
 
+use crate::expr_fn::top_hitters;
+use crate::sample::SampleNode;
+use crate::scan::ScanNode;
+
+/// How `mv-expand` expands the entries of a property bag
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BagExpansion {
+    /// Every entry becomes a bag with a single key
+    #[default]
+    Bag,
+    /// Every entry becomes a `[key, value]` array
+    Array
+}
+
+/// Level of `top-nested` with its resulting column names
+#[derive(Debug, Clone)]
+pub struct TopNestedLevel {
+    /// Number of values to keep, all values when absent
+    pub count: Option<usize>,
+    pub name: String,
+    pub expr: Expr,
+    /// Value of the bucket with all other values
+    pub others: Option<Expr>,
+    pub aggregation_name: String,
+    pub aggregation: Expr,
+    pub asc: bool
+}
+
+/// Records of a `scan` step that are part of the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Hash)]
+pub enum ScanOutput {
+    /// Every matching record
+    #[default]
+    All,
+    /// Only the last matching record of every sequence
+    Last,
+    None
+}
+
+/// Step of `scan` with expressions over the columns of [`scan_schema`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Hash)]
+pub struct ScanStep {
+    pub name: String,
+    pub output: ScanOutput,
+    pub condition: Expr,
+    /// Values of declared columns when the step matches
+    pub assignments: Vec<(String, Expr)>
+}
+
+/// Schema of the expressions of `scan`, the columns of the current record, followed by
+/// the columns of the state of every step prefixed by the name of the step, like `s1.a`
+pub fn scan_schema(input: &DFSchema, declarations: &[(String, DataType)], steps: &[String]) -> Result<DFSchema> {
+    let row: Vec<_> = input.fields().iter().cloned()
+        .chain(declarations.iter().map(|(n, t)| Arc::new(Field::new(n, t.clone(), true))))
+        .collect();
+    let states = steps.iter().flat_map(|s| row.iter().map(move |f| Arc::new(Field::new(format!("{}.{}", s, f.name()), f.data_type().clone(), true))));
+    DFSchema::new_with_metadata(row.iter().cloned().chain(states).map(|f| (None, f)).collect(), HashMap::new())
+}
+
+/// Unions the tables matched by `find`, preceded by a column with the name of the table
+///
+/// Columns with the same name but different types are renamed to `{name}_{type}`. Without a
+/// projection, the columns of the predicate and the columns of all tables are kept and the
+/// other columns are packed into the `pack_` bag.
+pub fn find_union(tables: Vec<(String, LogicalPlanBuilder)>, source_column: &str, predicate_columns: &[String], projection: Option<&[String]>) -> Result<LogicalPlanBuilder> {
+    let schemas: Vec<_> = tables.iter().map(|(_, b)| b.schema().clone()).collect();
+    let mut types: HashMap<&str, HashSet<&DataType>> = HashMap::new();
+    for f in schemas.iter().flat_map(|s| s.fields()) {
+        types.entry(f.name()).or_default().insert(f.data_type());
+    }
+
+    // columns of every table by their output name, and all output names with their original name
+    let mut names: Vec<(String, &str, &DataType)> = Vec::new();
+    let columns: Vec<HashMap<String, Column>> = schemas.iter()
+        .map(|s| s.iter().map(|(q, f)| {
+            let name = match types[f.name().as_str()].len() {
+                1 => f.name().clone(),
+                _ => format!("{}_{}", f.name(), datatype_to_string(f.data_type()))
+            };
+            if !names.iter().any(|(n, _, _)| *n == name) {
+                names.push((name.clone(), f.name(), f.data_type()));
+            }
+            (name, Column::from((q, f)))
+        }).collect())
+        .collect();
+
+    let explicit: Vec<String> = match projection {
+        Some(p) => p.to_vec(),
+        None => names.iter()
+            .filter(|(_, o, _)| predicate_columns.iter().any(|c| c == o))
+            .chain(names.iter().filter(|(n, _, _)| columns.iter().all(|c| c.contains_key(n))))
+            .map(|(n, _, _)| n.clone())
+            .unique()
+            .collect()
+    };
+    let packed: Vec<_> = match projection {
+        Some(_) => Vec::new(),
+        None => names.iter().filter(|(n, _, _)| !explicit.contains(n)).collect()
+    };
+    let pack_type = DataType::Struct(Fields::from_iter(packed.iter().map(|(n, _, t)| Field::new(n, (*t).clone(), true))));
+
+    tables.into_iter().zip(&columns)
+        .map(|((table, builder), columns)| {
+            // tables without a column have null values
+            let value = |name: &str| -> Result<Expr> {
+                match columns.get(name) {
+                    Some(c) => Ok(Expr::Column(c.clone())),
+                    None => {
+                        let t = names.iter().find(|(n, _, _)| n == name).map_or(&DataType::Null, |(_, _, t)| *t);
+                        Ok(lit(ScalarValue::try_from(t)?))
+                    }
+                }
+            };
+            let mut exprs = vec![lit(table).alias(source_column)];
+            for name in &explicit {
+                exprs.push(value(name)?.alias(name));
+            }
+            if projection.is_none() {
+                let pack = match packed.is_empty() {
+                    true => lit(ScalarValue::Null),
+                    false => cast(named_struct(packed.iter().map(|(n, _, _)| Ok([lit(n.as_str()), value(n)?])).flatten_ok().collect::<Result<_>>()?), pack_type.clone())
+                };
+                exprs.push(pack.alias("pack_"));
+            }
+            builder.project(exprs)
+        })
+        .reduce(|a, b| a?.union(b?.build()?))
+        .unwrap_or_else(|| plan_err!("No tables to find"))
+}
+
+/// Unions the tables of `union`, optionally preceded by a column with the name of the table
+///
+/// An outer union keeps all columns with null values for the tables without them, an inner
+/// union only keeps the columns common to all tables.
+pub fn union_tables(tables: Vec<(String, LogicalPlanBuilder)>, inner: bool, source_column: Option<&str>) -> Result<LogicalPlanBuilder> {
+    let common: Vec<String> = match tables.first() {
+        Some((_, first)) if inner => first.schema().fields().iter()
+            .map(|f| f.name().clone())
+            .filter(|n| tables.iter().all(|(_, b)| b.schema().has_column_with_unqualified_name(n)))
+            .collect(),
+        _ => Vec::new()
+    };
+
+    let mut tables = tables.into_iter().map(|(table, builder)| {
+        let builder = match inner {
+            true => builder.project(common.iter().map(ident))?,
+            false => builder
+        };
+        match source_column {
+            Some(c) => {
+                let columns = builder.schema().columns();
+                builder.project(std::iter::once(lit(table).alias(c)).chain(columns.into_iter().map(Expr::Column)))
+            },
+            None => Ok(builder)
+        }
+    });
+    let first = match tables.next() {
+        Some(b) => b?,
+        None => return plan_err!("No tables in union")
+    };
+    tables.try_fold(first, |acc, b| acc.union_by_name(b?.build()?))
+}
+
 pub trait LogicalPlanBuilderExt {
     fn count(self) -> Result<LogicalPlanBuilder>;
+    fn distinct_columns<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<LogicalPlanBuilder>;
     fn extend<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<LogicalPlanBuilder>;
     fn getschema(self) -> Result<LogicalPlanBuilder>;
+    fn join_keys(self, right: LogicalPlan, join_type: JoinType, keys: Vec<Column>) -> Result<LogicalPlanBuilder>;
     fn project_away<I: IntoIterator<Item = impl AsRef<str>>>(self, columns: I) -> Result<LogicalPlanBuilder>;
     fn project_keep<I: IntoIterator<Item = impl AsRef<str>>>(self, columns: I) -> Result<LogicalPlanBuilder>;
     fn project_rename(self, columns: HashMap<String, String>) -> Result<LogicalPlanBuilder>;
     fn project_with_alias<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<LogicalPlanBuilder>;
-    fn mv_expand(self, column: impl Into<Column>) -> Result<LogicalPlanBuilder>;
+    fn make_series(self, aggregations: Vec<(String, Expr, Expr)>, axis: (String, Expr), limits: (Option<Expr>, Option<Expr>), step: Expr, by: Vec<(String, Expr)>, nonempty: bool) -> Result<LogicalPlanBuilder>;
+    fn mv_expand(self, columns: Vec<(String, Expr, Option<DataType>)>, bag_expansion: BagExpansion, item_index: Option<String>, limit: Option<usize>) -> Result<LogicalPlanBuilder>;
+    fn sample(self, count: u32) -> Result<LogicalPlanBuilder>;
+    fn sample_distinct(self, count: u32, column: Expr) -> Result<LogicalPlanBuilder>;
+    fn scan_steps(self, match_id: Option<String>, declarations: Vec<(String, DataType, Expr)>, steps: Vec<ScanStep>) -> Result<LogicalPlanBuilder>;
     fn serialize<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<LogicalPlanBuilder>;
-    fn summarize<G: IntoIterator<Item = (Option<impl Into<String>>, Expr)>, A: IntoIterator<Item = Expr>>(self, group: G, aggr: A) -> Result<LogicalPlanBuilder>;
+    fn summarize<A: IntoIterator<Item = (Option<impl Into<String>>, Expr)>, G: IntoIterator<Item = Expr>>(self, aggr: A, group: G) -> Result<LogicalPlanBuilder>;
     fn take(self, count: u32) -> Result<LogicalPlanBuilder>;
     fn top(self, count: u32, expr: impl Into<Expr>, asc: bool, nulls_first: bool) -> Result<LogicalPlanBuilder>;
+    fn top_hitters(self, count: u32, value: (String, Expr), weight: Option<(String, Expr)>) -> Result<LogicalPlanBuilder>;
+    fn top_nested(self, levels: Vec<TopNestedLevel>) -> Result<LogicalPlanBuilder>;
 }
 
 impl LogicalPlanBuilderExt for LogicalPlanBuilder {
     fn count(self) -> Result<LogicalPlanBuilder> {
-        self.aggregate(Vec::<Expr>::new(), vec![count_all().alias("count")])
+        self.aggregate(Vec::<Expr>::new(), vec![count_all().unalias().alias("count")])
+    }
+
+    fn distinct_columns<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<Self> {
+        self.project_with_alias(columns)?.distinct()
     }
 
     fn extend<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<Self> {
         let current_schema = self.schema().clone();
-        let current_columns = current_schema.columns().into_iter().map(|f| Expr::Column(f));
+        let current_columns = current_schema.columns().into_iter().map(Expr::Column);
         self.project(current_columns.chain(alias_columns(columns)))
     }
 
@@ -59,12 +245,36 @@ impl LogicalPlanBuilderExt for LogicalPlanBuilder {
         })))
     }
 
+    fn join_keys(self, right: LogicalPlan, join_type: JoinType, keys: Vec<Column>) -> Result<LogicalPlanBuilder> {
+        // the right side is aliased, so a table can be joined with itself
+        let right = LogicalPlanBuilder::from(right).alias("$right")?.build()?;
+        let joined = self.join(right, join_type, (keys.clone(), keys), None)?;
+
+        // duplicate column names get a numeric suffix, like `name1`
+        let current_schema = joined.schema().clone();
+        let mut names = HashSet::new();
+        let columns: Vec<Expr> = current_schema.columns().into_iter()
+            .map(|c| {
+                let mut name = c.name.clone();
+                for i in 1.. {
+                    if names.insert(name.clone()) {
+                        break;
+                    }
+                    name = format!("{}{}", c.name, i);
+                }
+                Expr::Column(c).alias(name)
+            })
+            .collect();
+
+        joined.project(columns)
+    }
+
     fn project_away<I: IntoIterator<Item = impl AsRef<str>>>(self, columns: I) -> Result<LogicalPlanBuilder> {
         let wildcards: Vec<WildMatch> = columns.into_iter().map(|w| WildMatch::new(w.as_ref())).collect();
         let current_schema = self.schema().clone();
         let columns = current_schema.columns().into_iter()
             .filter(|f| wildcards.iter().all(|w| !w.matches(f.name())))
-            .map(|f| Expr::Column(f));
+            .map(Expr::Column);
 
         self.project(columns)
     }
@@ -74,7 +284,7 @@ impl LogicalPlanBuilderExt for LogicalPlanBuilder {
         let current_schema = self.schema().clone();
         let columns = current_schema.columns().into_iter()
             .filter(|f| wildcards.iter().any(|w| w.matches(f.name())))
-            .map(|f| Expr::Column(f));
+            .map(Expr::Column);
 
         self.project(columns)
     }
@@ -94,16 +304,197 @@ impl LogicalPlanBuilderExt for LogicalPlanBuilder {
         self.project(alias_columns(columns))
     }
 
-    fn mv_expand(self, column: impl Into<Column>) -> Result<Self> {
-        self.unnest_column(column.into())
+    fn make_series(self, aggregations: Vec<(String, Expr, Expr)>, axis: (String, Expr), limits: (Option<Expr>, Option<Expr>), step: Expr, by: Vec<(String, Expr)>, nonempty: bool) -> Result<Self> {
+        const BIN: &str = "$bin";
+        const PRESENT: &str = "$present";
+        const START: &str = "$start";
+        const END: &str = "$end";
+        let series_ref = TableReference::bare("$series");
+        let grid_ref = TableReference::bare("$grid");
+        let column = |q: &TableReference, n: &str| Expr::Column(Column::new(Some(q.clone()), n));
+
+        let current_schema = self.schema().clone();
+        let (axis_name, axis) = axis;
+        let (from, to) = limits;
+        let axis_type = match axis.get_type(&current_schema)? {
+            DataType::Timestamp(_, tz) => DataType::Timestamp(TimeUnit::Nanosecond, tz),
+            t if t.is_integer() => DataType::Int64,
+            t => return plan_err!("Axis of make-series must be a datetime or integer, found {}", t)
+        };
+        let timestamp = matches!(axis_type, DataType::Timestamp(..));
+        let step = match (step, timestamp) {
+            (Expr::Literal(ScalarValue::DurationNanosecond(Some(n)), _), true) => lit(ScalarValue::IntervalMonthDayNano(Some(IntervalMonthDayNano::new(0, 0, n)))),
+            (_, true) => return plan_err!("Step of make-series must be a constant timespan"),
+            (step, false) => cast(step, DataType::Int64)
+        };
+        let axis = cast(axis, axis_type.clone());
+        let from = from.map(|f| cast(f, axis_type.clone()));
+        let to = to.map(|t| cast(t, axis_type.clone()));
+
+        // bins are aligned to the start of the series, or else to zero
+        let origin = from.clone().unwrap_or_else(|| match timestamp {
+            true => cast(lit(ScalarValue::TimestampNanosecond(Some(0), None)), axis_type.clone()),
+            false => lit(0i64)
+        });
+        let bin = match timestamp {
+            true => date_bin(step.clone(), axis.clone(), origin),
+            false => origin.clone() + (axis.clone() - origin) / step.clone() * step.clone()
+        };
+
+        let range_filter = from.iter().map(|f| axis.clone().gt_eq(f.clone()))
+            .chain(to.iter().map(|t| axis.clone().lt(t.clone())))
+            .reduce(Expr::and);
+        let filtered = match range_filter {
+            Some(f) => self.filter(f)?,
+            None => self
+        };
+
+        // aggregate every bin with data, the count tells which bins are present after the join
+        let series = filtered
+            .aggregate(
+                by.iter().map(|(n, e)| e.clone().alias(n)).chain([bin.alias(BIN)]),
+                aggregations.iter().map(|(n, e, _)| e.clone().alias(n)).chain([count_all().unalias().alias(PRESENT)])
+            )?
+            .alias(series_ref.clone())?
+            .build()?;
+
+        // every group gets all bins, by default from the first to the last bin with data
+        let bounds = LogicalPlanBuilder::from(series.clone())
+            .aggregate(Vec::<Expr>::new(), vec![min(column(&series_ref, BIN)).alias(START), max(column(&series_ref, BIN)).alias(END)])?;
+        // an empty input has no series, unless a series of default values is requested with `kind=nonempty`
+        let bounds = match nonempty {
+            true => bounds.build()?,
+            false => bounds.filter(Expr::Column(Column::new_unqualified(START)).is_not_null())?.build()?
+        };
+        let groups = match by.is_empty() {
+            true => LogicalPlanBuilder::from(bounds),
+            false => LogicalPlanBuilder::from(series.clone())
+                .aggregate(by.iter().map(|(n, _)| column(&series_ref, n)), Vec::<Expr>::new())?
+                .cross_join(bounds)?
+        };
+        let start = from.unwrap_or_else(|| Expr::Column(Column::new_unqualified(START)));
+        let end = to.unwrap_or_else(|| Expr::Column(Column::new_unqualified(END)) + step.clone());
+        let grid = groups
+            .project(by.iter().map(|(n, _)| Expr::Column(Column::new_unqualified(n))).chain([range(start, end, step).alias(BIN)]))?
+            .unnest_column(BIN)?
+            .filter(Expr::Column(Column::new_unqualified(BIN)).is_not_null())?
+            .alias(grid_ref.clone())?;
+
+        let keys: Vec<&str> = by.iter().map(|(n, _)| n.as_str()).chain([BIN]).collect();
+        let joined = grid.join_detailed(
+            series,
+            JoinType::Left,
+            (keys.iter().map(|k| Column::new(Some(grid_ref.clone()), *k)).collect::<Vec<_>>(), keys.iter().map(|k| Column::new(Some(series_ref.clone()), *k)).collect::<Vec<_>>()),
+            None,
+            true
+        )?;
+
+        // collect the bins of every group into arrays, empty bins get the default value
+        let order = || vec![column(&grid_ref, BIN).sort(true, false)];
+        let values = aggregations.iter()
+            .map(|(n, _, d)| {
+                let value = when(column(&series_ref, PRESENT).is_null(), d.clone()).otherwise(column(&series_ref, n))?;
+                Ok(array_agg(value).order_by(order()).build()?.alias(n))
+            })
+            .chain([Ok(array_agg(column(&grid_ref, BIN)).order_by(order()).build()?.alias(&axis_name))])
+            .collect::<Result<Vec<_>>>()?;
+        let series = joined.aggregate(by.iter().map(|(n, _)| column(&grid_ref, n).alias(n)), values)?;
+        // without groups, the aggregation has a row even without bins
+        match by.is_empty() && !nonempty {
+            true => series.filter(Expr::Column(Column::new_unqualified(axis_name)).is_not_null()),
+            false => Ok(series)
+        }
+    }
+
+    fn mv_expand(self, columns: Vec<(String, Expr, Option<DataType>)>, bag_expansion: BagExpansion, item_index: Option<String>, limit: Option<usize>) -> Result<Self> {
+        let current_schema = self.schema().clone();
+        let key_column = |name: &str| format!("{}$key", name);
+
+        // lists unnested in lockstep, property bags are split in a list of keys and a list of values
+        let mut lists = Vec::new();
+        let mut bags = HashSet::new();
+        for (name, expr, _) in &columns {
+            match expr.get_type(&current_schema)? {
+                DataType::List(_) | DataType::LargeList(_) | DataType::FixedSizeList(..) => lists.push((name.clone(), expr.clone())),
+                DataType::Map(..) => {
+                    lists.push((key_column(name), map_keys(expr.clone())));
+                    lists.push((name.clone(), map_values(expr.clone())));
+                    bags.insert(name.clone());
+                },
+                DataType::Struct(fields) => {
+                    // a null bag is expanded to a single null row
+                    let keys = make_array(fields.iter().map(|f| lit(f.name().as_str())).collect());
+                    let values = make_array(fields.iter().map(|f| get_field(expr.clone(), f.name().as_str())).collect());
+                    lists.push((key_column(name), when(expr.clone().is_null(), lit(ScalarValue::Null)).otherwise(keys)?));
+                    lists.push((name.clone(), when(expr.clone().is_null(), lit(ScalarValue::Null)).otherwise(values)?));
+                    bags.insert(name.clone());
+                },
+                // other values are expanded to a single row
+                _ => lists.push((name.clone(), make_array(vec![expr.clone()])))
+            }
+        }
+        if let Some(limit) = limit {
+            lists = lists.into_iter().map(|(n, l)| (n, array_slice(l, lit(1i64), lit(limit as i64), None))).collect();
+        }
+        if let Some(index) = &item_index {
+            let lengths = lists.iter().map(|(_, l)| coalesce(vec![array_length(l.clone()), lit(0u64)])).collect();
+            lists.push((index.clone(), range(lit(0i64), cast(greatest(lengths), DataType::Int64), lit(1i64))));
+        }
+
+        let names: HashSet<&String> = lists.iter().map(|(n, _)| n).collect();
+        let unchanged = current_schema.columns().into_iter().filter(|c| !names.contains(&c.name));
+        let unnested = lists.iter().map(|(n, _)| Column::new_unqualified(n)).collect();
+        let expanded = self
+            .project(unchanged.map(Expr::Column).chain(lists.iter().map(|(n, l)| l.clone().alias(n))))?
+            .unnest_columns_with_options(unnested, UnnestOptions::default())?;
+
+        // existing columns keep their position, new columns are added at the end
+        let value = |name: &str| {
+            let column = Expr::Column(Column::new_unqualified(name));
+            let key = Expr::Column(Column::new_unqualified(key_column(name)));
+            let value = match (bags.contains(name), bag_expansion) {
+                (true, BagExpansion::Bag) => when(key.clone().is_null(), lit(ScalarValue::Null)).otherwise(map(vec![key], vec![column]))?,
+                (true, BagExpansion::Array) => when(key.clone().is_null(), lit(ScalarValue::Null)).otherwise(make_array(vec![key, cast(column, DataType::Utf8)]))?,
+                (false, _) => column
+            };
+            Ok(match columns.iter().find(|(n, _, _)| n == name) {
+                Some((_, _, Some(t))) => cast(value, t.clone()).alias(name),
+                _ => value.alias(name)
+            })
+        };
+        let existing = current_schema.columns().into_iter()
+            .map(|c| match columns.iter().any(|(n, _, _)| *n == c.name) {
+                true => value(&c.name),
+                false => Ok(Expr::Column(c))
+            });
+        let added = columns.iter()
+            .filter(|(n, _, _)| !current_schema.has_column_with_unqualified_name(n))
+            .map(|(n, _, _)| value(n));
+        let index = item_index.iter().map(|i| Ok(Expr::Column(Column::new_unqualified(i))));
+
+        expanded.project(existing.chain(added).chain(index).collect::<Result<Vec<_>>>()?)
+    }
+
+    fn sample(self, count: u32) -> Result<Self> {
+        let node = SampleNode::new(self.build()?, count as usize);
+        Ok(LogicalPlanBuilder::from(LogicalPlan::Extension(Extension { node: Arc::new(node) })))
+    }
+
+    fn sample_distinct(self, count: u32, column: Expr) -> Result<Self> {
+        self.project(vec![column])?.distinct()?.sample(count)
+    }
+
+    fn scan_steps(self, match_id: Option<String>, declarations: Vec<(String, DataType, Expr)>, steps: Vec<ScanStep>) -> Result<Self> {
+        let node = ScanNode::try_new(self.build()?, match_id, declarations, steps)?;
+        Ok(LogicalPlanBuilder::from(LogicalPlan::Extension(Extension { node: Arc::new(node) })))
     }
 
     fn serialize<I: IntoIterator<Item = (Option<impl Into<String>>, Expr)>>(self, columns: I) -> Result<Self> {
         self.window(alias_columns(columns))
     }
 
-    fn summarize<G: IntoIterator<Item = (Option<impl Into<String>>, Expr)>, A: IntoIterator<Item = Expr>>(self, group: G, aggr: A) -> Result<Self> {
-        self.aggregate(alias_columns(group), aggr)
+    fn summarize<A: IntoIterator<Item = (Option<impl Into<String>>, Expr)>, G: IntoIterator<Item = Expr>>(self, aggr: A, group: G) -> Result<Self> {
+        self.aggregate(group, alias_columns(aggr))
     }
 
     fn take(self, count: u32) -> Result<Self> {
@@ -117,6 +508,121 @@ impl LogicalPlanBuilderExt for LogicalPlanBuilder {
             nulls_first
         }])?.limit(0, Some(count.try_into().unwrap()))
     }
+
+    fn top_hitters(self, count: u32, value: (String, Expr), weight: Option<(String, Expr)>) -> Result<Self> {
+        const HITTERS: &str = "$hitters";
+        let (name, value) = value;
+        let (count_name, weight) = match weight {
+            Some((n, w)) => (format!("approximate_sum_{}", n), w),
+            None => (format!("approximate_count_{}", name), lit(1i64))
+        };
+        let hitters = Expr::Column(Column::new_unqualified(HITTERS));
+
+        self.aggregate(Vec::<Expr>::new(), vec![top_hitters(value, weight, lit(count as i64)).alias(HITTERS)])?
+            .unnest_column(HITTERS)?
+            .project(vec![get_field(hitters.clone(), "value").alias(&name), get_field(hitters, "count").alias(&count_name)])?
+            .sort(vec![Expr::Column(Column::new_unqualified(count_name)).sort(false, false)])
+    }
+
+    fn top_nested(self, levels: Vec<TopNestedLevel>) -> Result<Self> {
+        const AGGREGATE: &str = "$aggregate";
+        const RANK: &str = "$rank";
+        let rows_ref = TableReference::bare("$rows");
+        let ranks_ref = TableReference::bare("$ranks");
+        let nested_ref = TableReference::bare("$nested");
+        let level_ref = TableReference::bare("$level");
+        let key = |i: usize| format!("$key{}", i);
+        let column = |q: &TableReference, n: &str| Expr::Column(Column::new(Some(q.clone()), n));
+        let unqualified = |n: &str| Expr::Column(Column::new_unqualified(n));
+
+        let current_schema = self.schema().clone();
+        let keys = levels.iter().enumerate().map(|(i, l)| l.expr.clone().alias(key(i)));
+        let mut rows = self.project(current_schema.columns().into_iter().map(Expr::Column).chain(keys))?;
+
+        // every level ranks its values within the values of the previous levels,
+        // values outside of the top are dropped or replaced by the others value
+        let mut aggregates = Vec::new();
+        for (i, level) in levels.iter().enumerate() {
+            let keys: Vec<String> = (0..=i).map(key).collect();
+            if let Some(count) = level.count {
+                let rank = row_number()
+                    .partition_by(keys[..i].iter().map(|k| unqualified(k)).collect())
+                    .order_by(vec![unqualified(AGGREGATE).sort(level.asc, false), unqualified(&keys[i]).sort(true, false)])
+                    .build()?
+                    .alias(RANK);
+                let ranks = rows.clone()
+                    .aggregate(keys.iter().map(|k| unqualified(k)), vec![level.aggregation.clone().alias(AGGREGATE)])?
+                    .window(vec![rank])?
+                    .alias(ranks_ref.clone())?
+                    .build()?;
+
+                let joined = rows.alias(rows_ref.clone())?.join_detailed(
+                    ranks,
+                    JoinType::Inner,
+                    (keys.iter().map(|k| Column::new(Some(rows_ref.clone()), k)).collect::<Vec<_>>(), keys.iter().map(|k| Column::new(Some(ranks_ref.clone()), k)).collect::<Vec<_>>()),
+                    None,
+                    true
+                )?;
+                let top = column(&ranks_ref, RANK).lt_eq(lit(count as u64));
+                let joined = match level.others {
+                    Some(_) => joined,
+                    None => joined.filter(top.clone())?
+                };
+
+                let current_schema = joined.schema().clone();
+                let columns = current_schema.iter()
+                    .filter(|(q, _)| *q == Some(&rows_ref))
+                    .map(|(_, f)| {
+                        let value = column(&rows_ref, f.name());
+                        Ok(match &level.others {
+                            Some(o) if *f.name() == keys[i] => when(top.clone(), value).otherwise(cast(o.clone(), f.data_type().clone()))?,
+                            _ => value
+                        }.alias(f.name()))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                rows = joined.project(columns)?;
+            }
+
+            // the aggregation of a level covers all rows kept by the previous levels
+            aggregates.push(rows.clone()
+                .aggregate(keys.iter().map(|k| unqualified(k)), vec![level.aggregation.clone().alias(&level.aggregation_name)])?
+                .build()?);
+        }
+
+        // the results of every level are joined with the values of the previous levels
+        let mut names = Vec::new();
+        let mut nested: Option<LogicalPlanBuilder> = None;
+        for (i, (level, aggregated)) in levels.iter().zip(aggregates).enumerate() {
+            let aggregated = LogicalPlanBuilder::from(aggregated);
+            nested = Some(match nested {
+                None => aggregated,
+                Some(n) => {
+                    let keys: Vec<String> = (0..i).map(key).collect();
+                    n.alias(nested_ref.clone())?
+                        .join_detailed(
+                            aggregated.alias(level_ref.clone())?.build()?,
+                            JoinType::Inner,
+                            (keys.iter().map(|k| Column::new(Some(nested_ref.clone()), k)).collect::<Vec<_>>(), keys.iter().map(|k| Column::new(Some(level_ref.clone()), k)).collect::<Vec<_>>()),
+                            None,
+                            true
+                        )?
+                        .project(names.iter().map(|n: &String| column(&nested_ref, n).alias(n))
+                            .chain([key(i), level.aggregation_name.clone()].iter().map(|n| column(&level_ref, n).alias(n))))?
+                }
+            });
+            names.push(key(i));
+            names.push(level.aggregation_name.clone());
+        }
+
+        let Some(nested) = nested else {
+            return plan_err!("top-nested requires at least one level");
+        };
+        let order = levels.iter().enumerate()
+            .flat_map(|(i, l)| [unqualified(&l.aggregation_name).sort(l.asc, false), unqualified(&key(i)).sort(true, false)])
+            .collect::<Vec<_>>();
+        nested.sort(order)?
+            .project(levels.iter().enumerate().flat_map(|(i, l)| [unqualified(&key(i)).alias(&l.name), unqualified(&l.aggregation_name)]))
+    }
 }
 
 /// Helper function to convert a collection of optional name-expression pairs
@@ -1,73 +1,484 @@
 use arrow_schema::{DataType, Field, TimeUnit};
 
-use datafusion_common::{TableReference, JoinType, Column, DFSchema, ScalarValue};
-use datafusion_common::{DataFusionError, Result};
+use chrono::NaiveDate;
 
-use datafusion_catalog::default_table_source::DefaultTableSource;
+use datafusion_common::{TableReference, JoinType, Column, DFSchema, ScalarValue, Spans};
+use datafusion_common::scalar::ScalarStructBuilder;
+use datafusion_common::tree_node::{Transformed, TreeNode};
+use datafusion_common::{not_impl_err, DataFusionError, Result};
 
-use datafusion_expr::{ExprSchemable, TableScan, Values};
+use datafusion_catalog::default_table_source::{provider_as_source, DefaultTableSource};
+
+use datafusion_expr::{ExprSchemable, Subquery, TableScan, Values};
 use datafusion_expr::expr::{AggregateFunction, ScalarFunction, WindowFunction};
-use datafusion_expr::expr_fn::col;
+use datafusion_expr::expr_fn::{in_subquery, not_in_subquery};
 use datafusion_expr::planner::ContextProvider;
 use datafusion_expr::logical_plan::{LogicalPlan, LogicalPlanBuilder};
 use datafusion_expr::{Expr, Literal, SortExpr};
 
+use datafusion_functions_aggregate::count::count_all;
+use datafusion_functions::expr_fn::{contains, ends_with, lower, now, regexp_like, starts_with};
 use datafusion_functions_table::generate_series;
 
 use itertools::Itertools;
 
-use kqlparser::ast::{Expr as KqlExpr, Operator, TabularExpression, Literal as KqlLiteral, Source, Type};
+use wildmatch::WildMatch;
+
+use log::warn;
+
+use kqlparser::ast::{DateTime, Dynamic, Expr as KqlExpr, ExprKind, ExprList, FindProjection, Function, LetExpression, Operator, OperatorKind, ParameterType, Statement, TabularExpression, Literal as KqlLiteral, OptionLiteral, Options, ScanOutput as KqlScanOutput, Source, SourceKind, Span, Type};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::ops::Not;
 use std::sync::Arc;
 use std::vec;
 
-use crate::LogicalPlanBuilderExt;
+use crate::{find_union, scan_schema, union_tables, BagExpansion, LogicalPlanBuilderExt, ScanOutput, ScanStep, TopNestedLevel};
+use crate::materialize::MaterializedTable;
 
 pub struct KqlToRel<'a, S: ContextProvider> {
     ctx: &'a S,
+    parameters: HashMap<String, ScalarValue>,
+    scalars: HashMap<String, Expr>,
+    functions: HashMap<String, Function>,
+    tables: HashMap<String, LogicalPlan>,
+    /// Tables of the catalog searched by `search` without an explicit list of tables
+    table_names: Vec<String>,
+    /// Arguments and locals of an inlined function, these take precedence over columns
+    arguments: HashMap<String, Expr>,
+    now: Option<Expr>,
+    truncation: Option<usize>,
+    time_zone: Option<String>,
+    case_insensitive: bool,
 }
 
 impl<'a, S: ContextProvider> KqlToRel<'a, S> {
     pub fn new(ctx: &'a S) -> Self {
-        KqlToRel { ctx }
+        KqlToRel {
+            ctx,
+            parameters: HashMap::new(),
+            scalars: HashMap::new(),
+            functions: HashMap::new(),
+            tables: HashMap::new(),
+            table_names: Vec::new(),
+            arguments: HashMap::new(),
+            now: None,
+            truncation: None,
+            time_zone: None,
+            case_insensitive: false
+        }
+    }
+
+    /// Binds values to the names declared by `declare query_parameters`
+    pub fn with_parameters(mut self, parameters: HashMap<String, ScalarValue>) -> Self {
+        self.parameters = parameters;
+        self
+    }
+
+    /// Sets the tables of the catalog that are searched when a query doesn't name them
+    pub fn with_table_names(mut self, table_names: Vec<String>) -> Self {
+        self.table_names = table_names;
+        self
+    }
+
+    fn declare_parameters(&mut self, parameters: &[(String, Type, Option<KqlExpr>)]) -> Result<()> {
+        let schema = &DFSchema::empty();
+        for (name, t, default) in parameters {
+            let value = match (self.parameters.get(name), default, t) {
+                (Some(v), _, Type::Dynamic) => v.clone().lit(),
+                (Some(v), _, t) => v.cast_to(&type_to_datatype(t)?)?.lit(),
+                (None, Some(d), Type::Dynamic) => self.ast_to_expr(d, schema)?,
+                (None, Some(d), t) => self.ast_to_expr(d, schema)?.cast_to(&type_to_datatype(t)?, schema)?,
+                (None, None, _) => return Err(DataFusionError::Plan(format!("No value provided for query parameter '{}'", name)))
+            };
+            self.scalars.insert(name.clone(), value);
+        }
+        Ok(())
+    }
+
+    /// Applies a `set` statement, unknown options are ignored with a warning
+    fn set_option(&mut self, name: &str, value: Option<&KqlExpr>) -> Result<()> {
+        let value = match value {
+            Some(v) => match self.ast_to_expr(v, &DFSchema::empty())? {
+                Expr::Literal(x, _) => x,
+                _ => return Err(DataFusionError::Plan(format!("Value of option '{}' must be a constant at {}", name, v.span)))
+            },
+            None => ScalarValue::Boolean(Some(true))
+        };
+        let invalid = || DataFusionError::Plan(format!("Invalid value for option '{}'", name));
+
+        match name {
+            "query_now" => self.now = Some(value.cast_to(&DataType::Timestamp(TimeUnit::Nanosecond, None))?.lit()),
+            "truncationmaxrecords" => match value.cast_to(&DataType::UInt64)? {
+                ScalarValue::UInt64(Some(n)) => self.truncation = Some(n as usize),
+                _ => return Err(invalid())
+            },
+            "notruncation" => if value == ScalarValue::Boolean(Some(true)) {
+                self.truncation = None
+            },
+            "query_timezone" => match value {
+                ScalarValue::Utf8(Some(tz)) => self.time_zone = Some(tz),
+                _ => return Err(invalid())
+            },
+            "query_case_insensitive" => match value {
+                ScalarValue::Boolean(Some(b)) => self.case_insensitive = b,
+                _ => return Err(invalid())
+            },
+            _ => warn!("Ignoring unknown option '{}'", name)
+        }
+        Ok(())
     }
 
-    fn func_to_expr(&self, name: &str, args: &Vec<KqlExpr>) -> Result<Expr> {
-        let args = args.iter().map(|a| self.ast_to_expr(a)).collect::<Result<Vec<Expr>>>()?;
-        if let Some(f) = self.ctx.get_function_meta(&name) {
+    /// Offset in minutes of the time zone for datetimes without an explicit offset
+    fn time_zone_offset(&self) -> Result<i64> {
+        let time_zone = self.time_zone.as_deref().or(self.ctx.options().execution.time_zone.as_deref());
+        match time_zone {
+            None | Some("UTC" | "Z") => Ok(0),
+            Some(tz) => parse_offset(&tz.replace(':', "")).ok_or_else(|| DataFusionError::NotImplemented(format!("Time zone '{}' not supported", tz)))
+        }
+    }
+
+    /// Resolves a column by name, ignoring case when `query_case_insensitive` is set
+    fn column(&self, name: &str, schema: &DFSchema) -> Expr {
+        if self.case_insensitive && !schema.has_column_with_unqualified_name(name) {
+            if let Some(f) = schema.fields().iter().find(|f| f.name().eq_ignore_ascii_case(name)) {
+                return ident(f.name());
+            }
+        }
+        ident(name)
+    }
+
+    /// Creates the scope in which the body of a user-defined function is inlined
+    ///
+    /// When invoked on a pipe, `input` is bound to the first tabular parameter.
+    fn function_scope(&self, name: &str, function: &Function, args: &[KqlExpr], mut input: Option<LogicalPlan>, span: Span, schema: &DFSchema) -> Result<Self> {
+        let mut scope = KqlToRel {
+            ctx: self.ctx,
+            parameters: self.parameters.clone(),
+            scalars: self.scalars.clone(),
+            functions: self.functions.clone(),
+            tables: self.tables.clone(),
+            table_names: self.table_names.clone(),
+            arguments: HashMap::new(),
+            now: self.now.clone(),
+            truncation: self.truncation,
+            time_zone: self.time_zone.clone(),
+            case_insensitive: self.case_insensitive
+        };
+        // functions can't call themselves
+        scope.functions.remove(name);
+
+        let missing = |p: &str| DataFusionError::Plan(format!("No value provided for parameter '{}' of function '{}' at {}", p, name, span));
+        let mut args = args.iter();
+        for (n, t, default) in &function.parameters {
+            match t {
+                ParameterType::Tabular(columns) => {
+                    let plan = match input.take() {
+                        Some(p) => p,
+                        None => match args.next() {
+                            Some(KqlExpr { kind: ExprKind::Ident(t), .. }) => self.table_to_builder(t)?.build()?,
+                            Some(a) => return Err(DataFusionError::Plan(format!("Tabular argument must be a table name at {}", a.span))),
+                            None => return Err(missing(n))
+                        }
+                    };
+                    for (c, _) in columns.iter().flatten() {
+                        if !plan.schema().has_column_with_unqualified_name(c) {
+                            return Err(DataFusionError::Plan(format!("Tabular argument '{}' has no column '{}' at {}", n, c, span)));
+                        }
+                    }
+                    scope.tables.insert(n.clone(), plan);
+                },
+                ParameterType::Scalar(t) => {
+                    let value = match (args.next(), default) {
+                        (Some(a), _) => self.ast_to_expr(a, schema)?,
+                        (None, Some(d)) => self.ast_to_expr(d, schema)?,
+                        (None, None) => return Err(missing(n))
+                    };
+                    let value = match t {
+                        Type::Dynamic => value,
+                        t => value.cast_to(&type_to_datatype(t)?, schema)?
+                    };
+                    scope.arguments.insert(n.clone(), value);
+                }
+            }
+        }
+        if args.next().is_some() {
+            return Err(DataFusionError::Plan(format!("Function '{}' expects at most {} arguments at {}", name, function.parameters.len(), span)));
+        }
+
+        for (n, e) in &function.statements {
+            match e {
+                LetExpression::Scalar(e) => _ = scope.arguments.insert(n.clone(), scope.ast_to_expr(e, schema)?),
+                LetExpression::Tabular(q) => _ = scope.tables.insert(n.clone(), scope.query_statement_to_plan(q)?),
+                LetExpression::Materialize(q) => _ = scope.tables.insert(n.clone(), scope.materialize(n, q)?),
+                LetExpression::Function(f) => _ = scope.functions.insert(n.clone(), f.clone())
+            }
+        }
+        Ok(scope)
+    }
+
+    fn call_to_builder(&self, name: &str, args: &[KqlExpr], input: Option<LogicalPlan>, span: Span) -> Result<LogicalPlanBuilder> {
+        let function = self.functions.get(name).ok_or_else(|| DataFusionError::Plan(format!("Unknown function '{}' at {}", name, span)))?;
+        let scope = self.function_scope(name, function, args, input, span, &DFSchema::empty())?;
+        match function.body.as_ref() {
+            LetExpression::Tabular(q) => Ok(LogicalPlanBuilder::from(scope.query_statement_to_plan(q)?)),
+            _ => Err(DataFusionError::Plan(format!("Function '{}' does not return a table at {}", name, span)))
+        }
+    }
+
+    /// Plans a scan of a table that caches the result of `query`, so it's computed only once
+    fn materialize(&self, name: &str, query: &TabularExpression) -> Result<LogicalPlan> {
+        let table = MaterializedTable::new(self.query_statement_to_plan(query)?);
+        LogicalPlanBuilder::scan(name, provider_as_source(Arc::new(table)), None)?.build()
+    }
+
+    /// Resolves a bare table name, tabular `let`s and arguments shadow tables of the catalog
+    fn table_to_builder(&self, name: &str) -> Result<LogicalPlanBuilder> {
+        match self.tables.get(name) {
+            Some(plan) => LogicalPlanBuilder::from(plan.clone()).alias(TableReference::bare(name)),
+            None => {
+                let reference = TableReference::bare(name);
+                LogicalPlanBuilder::scan(reference.clone(), self.ctx.get_table_source(reference)?, None)
+            }
+        }
+    }
+
+    fn func_to_expr(&self, name: &str, args: &[KqlExpr], span: Span, schema: &DFSchema) -> Result<Expr> {
+        if let Some(function) = self.functions.get(name) {
+            let scope = self.function_scope(name, function, args, None, span, schema)?;
+            return match function.body.as_ref() {
+                LetExpression::Scalar(e) => scope.ast_to_expr(e, schema),
+                _ => Err(DataFusionError::Plan(format!("Function '{}' does not return a scalar at {}", name, span)))
+            };
+        }
+
+        let args = args.iter().map(|a| self.ast_to_expr(a, schema)).collect::<Result<Vec<Expr>>>()?;
+        let current = || self.now.clone().unwrap_or_else(now);
+        if name == "ago" {
+            let [x] = <[Expr; 1]>::try_from(args).map_err(|_| DataFusionError::Plan(format!("Function 'ago' expects 1 argument at {}", span)))?;
+            Ok(current() - x)
+        } else if name == "now" {
+            match <[Expr; 1]>::try_from(args) {
+                Ok([offset]) => Ok(current() + offset),
+                Err(args) if args.is_empty() => Ok(current()),
+                Err(_) => Err(DataFusionError::Plan(format!("Function 'now' expects at most 1 argument at {}", span)))
+            }
+        } else if name == "count" && args.is_empty() {
+            Ok(count_all().unalias())
+        } else if let Some(f) = self.ctx.get_function_meta(name) {
             Ok(Expr::ScalarFunction(ScalarFunction::new_udf(f, args)))
-        } else if let Some(f) = self.ctx.get_aggregate_meta(&name) {
+        } else if let Some(f) = self.ctx.get_aggregate_meta(name) {
             Ok(Expr::AggregateFunction(AggregateFunction::new_udf(f, args, false, None, None, None)))
-        } else if let Some(f) = self.ctx.get_window_meta(&name) {
+        } else if let Some(f) = self.ctx.get_window_meta(name) {
             Ok(Expr::WindowFunction(Box::new(WindowFunction::new(f, args))))
         } else {
-            return Err(DataFusionError::NotImplemented("Function not implemented".to_string()));
-        }
-    }
-
-    fn ast_to_expr(&self, ast: &KqlExpr) -> Result<Expr> {
-        Ok(match ast {
-            KqlExpr::Equals(x, y) => self.ast_to_expr(&x)?.eq(self.ast_to_expr(&y)?),
-            KqlExpr::NotEquals(x, y) => self.ast_to_expr(&x)?.not_eq(self.ast_to_expr(&y)?),
-            KqlExpr::And(x, y) => self.ast_to_expr(&x)?.and(self.ast_to_expr(&y)?),
-            KqlExpr::Or(x, y) => self.ast_to_expr(&x)?.or(self.ast_to_expr(&y)?),
-            KqlExpr::Add(x, y) => self.ast_to_expr(&x)? + self.ast_to_expr(&y)?,
-            KqlExpr::Substract(x, y) => self.ast_to_expr(&x)? - self.ast_to_expr(&y)?,
-            KqlExpr::Multiply(x, y) => self.ast_to_expr(&x)? * self.ast_to_expr(&y)?,
-            KqlExpr::Divide(x, y) => self.ast_to_expr(&x)? / self.ast_to_expr(&y)?,
-            KqlExpr::Modulo(x, y) => self.ast_to_expr(&x)? % self.ast_to_expr(&y)?,
-            KqlExpr::Less(x, y) => self.ast_to_expr(&x)?.lt(self.ast_to_expr(&y)?),
-            KqlExpr::Greater(x, y) => self.ast_to_expr(&x)?.gt(self.ast_to_expr(&y)?),
-            KqlExpr::LessOrEqual(x, y) => self.ast_to_expr(&x)?.lt_eq(self.ast_to_expr(&y)?),
-            KqlExpr::GreaterOrEqual(x, y) => self.ast_to_expr(&x)?.gt_eq(self.ast_to_expr(&y)?),
-            KqlExpr::Literal(v) => literal_to_expr(v),
-            KqlExpr::Ident(x) => col(x.as_str()),
-            KqlExpr::Func(x, y) => self.func_to_expr(x.as_str(), y)?,
-            _ => return Err(DataFusionError::NotImplemented("Expr not implemented".to_string()))
+            Err(DataFusionError::NotImplemented(format!("Function '{}' not implemented at {}", name, span)))
+        }
+    }
+
+    fn ast_to_expr(&self, ast: &KqlExpr, schema: &DFSchema) -> Result<Expr> {
+        Ok(match &ast.kind {
+            ExprKind::Equals(x, y) => self.ast_to_expr(x, schema)?.eq(self.ast_to_expr(y, schema)?),
+            ExprKind::NotEquals(x, y) => self.ast_to_expr(x, schema)?.not_eq(self.ast_to_expr(y, schema)?),
+            ExprKind::And(x, y) => self.ast_to_expr(x, schema)?.and(self.ast_to_expr(y, schema)?),
+            ExprKind::Or(x, y) => self.ast_to_expr(x, schema)?.or(self.ast_to_expr(y, schema)?),
+            ExprKind::Add(x, y) => self.ast_to_expr(x, schema)? + self.ast_to_expr(y, schema)?,
+            ExprKind::Substract(x, y) => self.ast_to_expr(x, schema)? - self.ast_to_expr(y, schema)?,
+            ExprKind::Multiply(x, y) => self.ast_to_expr(x, schema)? * self.ast_to_expr(y, schema)?,
+            ExprKind::Divide(x, y) => self.ast_to_expr(x, schema)? / self.ast_to_expr(y, schema)?,
+            ExprKind::Modulo(x, y) => self.ast_to_expr(x, schema)? % self.ast_to_expr(y, schema)?,
+            ExprKind::Less(x, y) => self.ast_to_expr(x, schema)?.lt(self.ast_to_expr(y, schema)?),
+            ExprKind::Greater(x, y) => self.ast_to_expr(x, schema)?.gt(self.ast_to_expr(y, schema)?),
+            ExprKind::LessOrEqual(x, y) => self.ast_to_expr(x, schema)?.lt_eq(self.ast_to_expr(y, schema)?),
+            ExprKind::GreaterOrEqual(x, y) => self.ast_to_expr(x, schema)?.gt_eq(self.ast_to_expr(y, schema)?),
+            ExprKind::EqualsCaseInsensitive(x, y) => lower(self.ast_to_expr(x, schema)?).eq(lower(self.ast_to_expr(y, schema)?)),
+            ExprKind::NotEqualsCaseInsensitive(x, y) => lower(self.ast_to_expr(x, schema)?).not_eq(lower(self.ast_to_expr(y, schema)?)),
+            ExprKind::Contains(x, y) => contains(lower(self.ast_to_expr(x, schema)?), lower(self.ast_to_expr(y, schema)?)),
+            ExprKind::NotContains(x, y) => contains(lower(self.ast_to_expr(x, schema)?), lower(self.ast_to_expr(y, schema)?)).not(),
+            ExprKind::ContainsCs(x, y) => contains(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?),
+            ExprKind::NotContainsCs(x, y) => contains(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?).not(),
+            ExprKind::Has(x, y) => self.has_to_expr(x, y, true, true, false, schema)?,
+            ExprKind::NotHas(x, y) => self.has_to_expr(x, y, true, true, false, schema)?.not(),
+            ExprKind::HasCs(x, y) => self.has_to_expr(x, y, true, true, true, schema)?,
+            ExprKind::NotHasCs(x, y) => self.has_to_expr(x, y, true, true, true, schema)?.not(),
+            ExprKind::HasPrefix(x, y) => self.has_to_expr(x, y, true, false, false, schema)?,
+            ExprKind::NotHasPrefix(x, y) => self.has_to_expr(x, y, true, false, false, schema)?.not(),
+            ExprKind::HasPrefixCs(x, y) => self.has_to_expr(x, y, true, false, true, schema)?,
+            ExprKind::NotHasPrefixCs(x, y) => self.has_to_expr(x, y, true, false, true, schema)?.not(),
+            ExprKind::HasSuffix(x, y) => self.has_to_expr(x, y, false, true, false, schema)?,
+            ExprKind::NotHasSuffix(x, y) => self.has_to_expr(x, y, false, true, false, schema)?.not(),
+            ExprKind::HasSuffixCs(x, y) => self.has_to_expr(x, y, false, true, true, schema)?,
+            ExprKind::NotHasSuffixCs(x, y) => self.has_to_expr(x, y, false, true, true, schema)?.not(),
+            ExprKind::StartsWith(x, y) => starts_with(lower(self.ast_to_expr(x, schema)?), lower(self.ast_to_expr(y, schema)?)),
+            ExprKind::NotStartsWith(x, y) => starts_with(lower(self.ast_to_expr(x, schema)?), lower(self.ast_to_expr(y, schema)?)).not(),
+            ExprKind::StartsWithCs(x, y) => starts_with(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?),
+            ExprKind::NotStartsWithCs(x, y) => starts_with(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?).not(),
+            ExprKind::EndsWith(x, y) => ends_with(lower(self.ast_to_expr(x, schema)?), lower(self.ast_to_expr(y, schema)?)),
+            ExprKind::NotEndsWith(x, y) => ends_with(lower(self.ast_to_expr(x, schema)?), lower(self.ast_to_expr(y, schema)?)).not(),
+            ExprKind::EndsWithCs(x, y) => ends_with(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?),
+            ExprKind::NotEndsWithCs(x, y) => ends_with(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?).not(),
+            ExprKind::MatchesRegex(x, y) => regexp_like(self.ast_to_expr(x, schema)?, self.ast_to_expr(y, schema)?, None),
+            ExprKind::In(x, y) => self.in_to_expr(x, y, false, true, schema)?,
+            ExprKind::NotIn(x, y) => self.in_to_expr(x, y, true, true, schema)?,
+            ExprKind::InCaseInsensitive(x, y) => self.in_to_expr(x, y, false, false, schema)?,
+            ExprKind::NotInCaseInsensitive(x, y) => self.in_to_expr(x, y, true, false, schema)?,
+            ExprKind::HasAny(x, y) => {
+                let terms = self.list_to_terms(y, ast.span, schema)?;
+                match terms.is_empty() {
+                    true => false.lit(),
+                    false => regexp_like(self.ast_to_expr(x, schema)?, terms_pattern(&terms, true, true, false).lit(), None)
+                }
+            },
+            ExprKind::HasAll(x, y) => {
+                let x = self.ast_to_expr(x, schema)?;
+                self.list_to_terms(y, ast.span, schema)?.iter()
+                    .map(|t| regexp_like(x.clone(), terms_pattern(std::slice::from_ref(t), true, true, false).lit(), None))
+                    .reduce(Expr::and)
+                    .unwrap_or_else(|| true.lit())
+            },
+            ExprKind::Between(x, y, z) => {
+                let (low, high) = self.range_to_exprs(y, z, schema)?;
+                self.ast_to_expr(x, schema)?.between(low, high)
+            },
+            ExprKind::NotBetween(x, y, z) => {
+                let (low, high) = self.range_to_exprs(y, z, schema)?;
+                self.ast_to_expr(x, schema)?.not_between(low, high)
+            },
+            ExprKind::Not(x) => self.ast_to_expr(x, schema)?.not(),
+            ExprKind::Negate(x) => -self.ast_to_expr(x, schema)?,
+            ExprKind::Literal(v @ (KqlLiteral::DateTime(_) | KqlLiteral::Dynamic(_))) => literal_to_expr(v, self.time_zone_offset()?).ok_or_else(|| DataFusionError::NotImplemented(format!("Literal not supported at {}", ast.span)))?,
+            ExprKind::Literal(v) => literal_to_expr(v, 0).ok_or_else(|| DataFusionError::NotImplemented(format!("Literal not supported at {}", ast.span)))?,
+            ExprKind::Ident(x) => match (self.arguments.get(x), self.scalars.get(x)) {
+                (Some(e), _) => e.clone(),
+                (None, Some(e)) if !schema.has_column_with_unqualified_name(x) => e.clone(),
+                _ => self.column(x, schema)
+            },
+            ExprKind::Func(x, y) => self.func_to_expr(x.as_str(), y, ast.span, schema)?,
+            ExprKind::ToScalar(q) => {
+                let plan = self.query_statement_to_plan(q)?;
+                let column = plan.schema().columns().into_iter().next()
+                    .ok_or_else(|| DataFusionError::Plan(format!("Subquery has no columns at {}", q.span)))?;
+                let plan = LogicalPlanBuilder::from(plan).project(vec![Expr::Column(column)])?.limit(0, Some(1))?.build()?;
+                Expr::ScalarSubquery(Subquery {
+                    subquery: Arc::new(plan),
+                    outer_ref_columns: vec![],
+                    spans: Spans::new()
+                })
+            },
+            // columns of another relation, or of the state of a step in `scan`
+            ExprKind::Index(t, c) => match (&t.kind, &c.kind) {
+                (ExprKind::Ident(t), ExprKind::Ident(c)) if schema.has_column(&Column::new(Some(t.as_str()), c)) => Expr::Column(Column::new(Some(t.as_str()), c)),
+                (ExprKind::Ident(t), ExprKind::Ident(c)) if schema.has_column_with_unqualified_name(&format!("{}.{}", t, c)) => ident(&format!("{}.{}", t, c)),
+                _ => return Err(DataFusionError::NotImplemented(format!("Expr not implemented at {}", ast.span)))
+            }
+        })
+    }
+
+    /// Plans the `has` family of operators, which match whole terms of alphanumeric characters
+    fn has_to_expr(&self, x: &KqlExpr, y: &KqlExpr, prefix: bool, suffix: bool, case_sensitive: bool, schema: &DFSchema) -> Result<Expr> {
+        let term = self.constant_term(y, schema)?;
+        Ok(regexp_like(self.ast_to_expr(x, schema)?, terms_pattern(&[term], prefix, suffix, case_sensitive).lit(), None))
+    }
+
+    /// Plans the predicate of `search`, string terms match terms of any string column
+    fn search_to_expr(&self, ast: &KqlExpr, case_sensitive: bool, schema: &DFSchema) -> Result<Expr> {
+        Ok(match &ast.kind {
+            ExprKind::And(x, y) => self.search_to_expr(x, case_sensitive, schema)?.and(self.search_to_expr(y, case_sensitive, schema)?),
+            ExprKind::Or(x, y) => self.search_to_expr(x, case_sensitive, schema)?.or(self.search_to_expr(y, case_sensitive, schema)?),
+            ExprKind::Not(x) => self.search_to_expr(x, case_sensitive, schema)?.not(),
+            ExprKind::Literal(KqlLiteral::String(term, _)) => {
+                let pattern = search_pattern(term, case_sensitive);
+                schema.fields().iter()
+                    .filter(|f| matches!(f.data_type(), DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View))
+                    .map(|f| regexp_like(ident(f.name()), pattern.clone().lit(), None))
+                    .reduce(Expr::or)
+                    .unwrap_or(false.lit())
+            },
+            kind => {
+                let expr = match kind {
+                    ExprKind::Has(x, y) => match (&x.kind, &y.kind) {
+                        (ExprKind::Ident(c), ExprKind::Literal(KqlLiteral::String(term, _))) => regexp_like(self.column(c, schema), search_pattern(term, case_sensitive).lit(), None),
+                        _ => self.ast_to_expr(ast, schema)?
+                    },
+                    _ => self.ast_to_expr(ast, schema)?
+                };
+                // tables without the referenced columns don't match
+                match expr.column_refs().into_iter().all(|c| schema.has_column(c)) {
+                    true => expr,
+                    false => false.lit()
+                }
+            }
+        })
+    }
+
+    fn constant_term(&self, y: &KqlExpr, schema: &DFSchema) -> Result<String> {
+        match self.ast_to_expr(y, schema)? {
+            Expr::Literal(ScalarValue::Utf8(Some(term)), _) => Ok(term),
+            _ => Err(DataFusionError::NotImplemented(format!("Term must be a constant string at {}", y.span)))
+        }
+    }
+
+    /// Plans the values of a set operator, dynamic arrays are expanded to their elements
+    fn list_to_exprs(&self, values: &[KqlExpr], schema: &DFSchema) -> Result<Vec<Expr>> {
+        let mut exprs = Vec::with_capacity(values.len());
+        for v in values {
+            match &v.kind {
+                ExprKind::Literal(KqlLiteral::Dynamic(Some(Dynamic::Array(a)))) => for d in a {
+                    let offset = self.time_zone_offset()?;
+                    exprs.push(dynamic_to_scalar(d.as_ref(), offset).ok_or_else(|| DataFusionError::NotImplemented(format!("Nested dynamic value not supported at {}", v.span)))?.lit());
+                },
+                _ => exprs.push(self.ast_to_expr(v, schema)?)
+            }
+        }
+        Ok(exprs)
+    }
+
+    fn list_to_terms(&self, list: &ExprList, span: Span, schema: &DFSchema) -> Result<Vec<String>> {
+        let values = match list {
+            ExprList::Values(v) => v,
+            ExprList::Tabular(_) => return Err(DataFusionError::NotImplemented(format!("Tabular terms not supported at {}", span)))
+        };
+        self.list_to_exprs(values, schema)?.into_iter().map(|e| match e {
+            Expr::Literal(ScalarValue::Utf8(Some(term)), _) => Ok(term),
+            _ => Err(DataFusionError::NotImplemented(format!("Terms must be constant strings at {}", span)))
+        }).collect()
+    }
+
+    /// Plans the bounds of a `between` range, a timespan upper bound is relative to a datetime lower bound
+    fn range_to_exprs(&self, low: &KqlExpr, high: &KqlExpr, schema: &DFSchema) -> Result<(Expr, Expr)> {
+        let low = self.ast_to_expr(low, schema)?;
+        let high = self.ast_to_expr(high, schema)?;
+        Ok(match (low.get_type(schema)?, high.get_type(schema)?) {
+            (DataType::Timestamp(_, _), DataType::Duration(_)) => (low.clone(), low + high),
+            _ => (low, high)
+        })
+    }
+
+    /// Plans `in` either as a list of values or as a subquery on the first column of a tabular expression
+    fn in_to_expr(&self, x: &KqlExpr, list: &ExprList, negated: bool, case_sensitive: bool, schema: &DFSchema) -> Result<Expr> {
+        let normalize = |e: Expr| if case_sensitive { e } else { lower(e) };
+        let x = normalize(self.ast_to_expr(x, schema)?);
+        let (plan, span) = match list {
+            // `x in (t)` with a table or tabular `let` is parsed as a list with a single value
+            ExprList::Values(v) => match v.as_slice() {
+                [KqlExpr { kind: ExprKind::Ident(t), span }] if !schema.has_column_with_unqualified_name(t) && (self.tables.contains_key(t) || self.ctx.get_table_source(TableReference::bare(t.as_str())).is_ok()) => (self.table_to_builder(t)?.build()?, *span),
+                _ => return Ok(x.in_list(self.list_to_exprs(v, schema)?.into_iter().map(normalize).collect(), negated))
+            },
+            ExprList::Tabular(q) => (self.query_statement_to_plan(q)?, q.span)
+        };
+        let column = plan.schema().columns().into_iter().next()
+            .ok_or_else(|| DataFusionError::Plan(format!("Subquery has no columns at {}", span)))?;
+        let plan = Arc::new(LogicalPlanBuilder::from(plan).project(vec![normalize(Expr::Column(column))])?.build()?);
+        Ok(match negated {
+            true => not_in_subquery(x, plan),
+            false => in_subquery(x, plan)
         })
     }
 
+    fn named_exprs(&self, exprs: &[(Option<String>, KqlExpr)], schema: &DFSchema) -> Result<Vec<(Option<String>, Expr)>> {
+        exprs.iter().map(|(n, e)| Ok((n.clone(), self.ast_to_expr(e, schema)?))).collect()
+    }
+
     fn query_statement_to_plan(&self, query: &TabularExpression) -> Result<LogicalPlan> {
         let mut builder = self.source_to_builder(&query.source)?;
         for op in query.operators.iter() {
@@ -78,39 +489,38 @@ impl<'a, S: ContextProvider> KqlToRel<'a, S> {
     }
 
     fn source_to_builder(&self, source: &Source) -> Result<LogicalPlanBuilder> {
-        Ok(match source {
-            Source::Print(v) => {
-                let values = v.iter()
-                    .map(|(_, v)| self.ast_to_expr(v))
-                    .collect::<Result<Vec<Expr>>>()?;
-
+        let schema = &DFSchema::empty();
+        Ok(match &source.kind {
+            SourceKind::Call(n, a) => self.call_to_builder(n, a, None, source.span)?,
+            SourceKind::Print(v) => {
                 let mut print_idx = 0;
-                let schema = DFSchema::empty();
-                let fields = values.iter()
-                    .zip(v)
-                    .map(|(v, (n, _))| {
+                let values = v.iter()
+                    .map(|(n, v)| {
                         let name = n.clone().unwrap_or_else(|| {
                             let name = format!("print_{}", print_idx);
                             print_idx += 1;
                             name
                         });
-                        Ok((None, Arc::new(Field::new(name, v.get_type(&schema)?, true))))
+                        Ok(self.ast_to_expr(v, schema)?.alias(name))
                     })
-                    .collect::<Result<Vec<(Option<TableReference>, Arc<Field>)>>>()?;
+                    .collect::<Result<Vec<Expr>>>()?;
 
+                // a projection of a single row, unlike values this allows subqueries like `toscalar()`
+                LogicalPlanBuilder::empty(true).project(values)?
+            }
+            SourceKind::Datatable(s, d) => {
+                let fields = s.iter()
+                    .map(|(n, t)| Ok((None::<TableReference>, Arc::new(Field::new(n, type_to_datatype(t)?, true)))))
+                    .collect::<Result<Vec<_>>>()?;
                 LogicalPlanBuilder::from(LogicalPlan::Values(Values {
                     schema: Arc::new(DFSchema::new_with_metadata(fields, HashMap::default())?),
-                    values: vec![values]
+                    values: d.iter().chunks(s.len()).into_iter().map(|chunk| chunk.map(|r| self.ast_to_expr(r, schema)).collect()).collect::<Result<_>>()?
                 }))
-            }
-            Source::Datatable(s, d) => LogicalPlanBuilder::from(LogicalPlan::Values(Values {
-                schema: Arc::new(DFSchema::new_with_metadata(s.iter().map(|(n, t)| (None::<TableReference>, Arc::new(Field::new(n, type_to_datatype(t), true)))).collect(), HashMap::default()).unwrap()),
-                values: d.iter().chunks(s.len()).into_iter().map(|chunk| chunk.map(|r| self.ast_to_expr(r).unwrap()).collect()).collect()
-            })),
-            Source::Range(c, b, e, s) => {
-                let start = self.ast_to_expr(b)?;
-                let end = self.ast_to_expr(e)?;
-                let step = self.ast_to_expr(s)?;
+            },
+            SourceKind::Range(c, b, e, s) => {
+                let start = self.ast_to_expr(b, schema)?;
+                let end = self.ast_to_expr(e, schema)?;
+                let step = self.ast_to_expr(s, schema)?;
                 let provider = generate_series().create_table_provider(&[start, end, step])?;
                 let table_scan = TableScan::try_new(
                     "range",
@@ -123,77 +533,495 @@ impl<'a, S: ContextProvider> KqlToRel<'a, S> {
                 LogicalPlanBuilder::from(LogicalPlan::TableScan(table_scan))
                     .project_rename(HashMap::from([("value".to_string(), c.to_string())]))?
             },
-            Source::Reference(c, s, t) => {
+            SourceKind::Reference(None, None, t) => self.table_to_builder(t)?,
+            SourceKind::Reference(c, s, t) => {
                 let reference = match (c, s, t) {
                     (Some(c), Some(s), t) => TableReference::full(c.as_str(), s.as_str(), t.as_str()),
                     (None, Some(s), t) => TableReference::partial(s.as_str(), t.as_str()),
-                    (None, None, t) => TableReference::bare(t.as_str()),
                     _ => Err(DataFusionError::NotImplemented("Invalid table reference".to_string()))?
                 };
                 LogicalPlanBuilder::scan(reference.clone(), self.ctx.get_table_source(reference)?, None)?
             },
-            Source::Union(_, s) => s.iter()
-                .map(|src| self.source_to_builder(src))
-                .collect::<Result<Vec<LogicalPlanBuilder>>>()?
-                .into_iter()
-                .reduce(|a, b| a.union(b.build().unwrap()).unwrap())
-                .ok_or(DataFusionError::Internal("No sources in union".to_string()))?,
-            _ => return Err(DataFusionError::NotImplemented("Source not implemented".to_string())),
+            SourceKind::Union(o, s) => self.union_to_builder(o, None, s, source.span)?,
+            SourceKind::Find(o, s, e, p) => {
+                let source_column = match o.get("withsource") {
+                    None => "source_",
+                    Some(OptionLiteral::String(s)) => s.as_str(),
+                    Some(_) => return Err(DataFusionError::Plan(format!("Invalid value for option 'withsource' at {}", source.span)))
+                };
+                let resolved = self.resolve_tables(s.as_deref())?;
+                if resolved.is_empty() {
+                    return Err(DataFusionError::Plan(format!("No tables to find at {}", source.span)));
+                }
+                let predicates = resolved.into_iter()
+                    .map(|(name, builder)| Ok((name, self.ast_to_expr(e, builder.schema())?, builder)))
+                    .collect::<Result<Vec<_>>>()?;
+                // columns of the predicate with their type in the first table that has them
+                let mut predicate_columns: Vec<(String, DataType)> = Vec::new();
+                for (_, predicate, builder) in &predicates {
+                    for c in predicate.column_refs() {
+                        if let Ok((_, f)) = builder.schema().qualified_field_from_column(c) {
+                            if !predicate_columns.iter().any(|(n, _)| *n == c.name) {
+                                predicate_columns.push((c.name.clone(), f.data_type().clone()));
+                            }
+                        }
+                    }
+                }
+                // columns missing in a table are null in its records
+                let tables = predicates.into_iter()
+                    .map(|(name, predicate, builder)| {
+                        let predicate = predicate.transform(|x| match x {
+                            Expr::Column(c) if !builder.schema().has_column(&c) => match predicate_columns.iter().find(|(n, _)| *n == c.name) {
+                                Some((_, t)) => Ok(Transformed::yes(ScalarValue::try_from(t)?.lit())),
+                                None => Err(DataFusionError::Plan(format!("Column '{}' not found in any table at {}", c.name, e.span)))
+                            },
+                            x => Ok(Transformed::no(x))
+                        })?.data;
+                        Ok((name, builder.filter(predicate)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let predicate_columns: Vec<_> = predicate_columns.into_iter().map(|(n, _)| n).collect();
+                let projection = match p {
+                    FindProjection::ProjectSmart => None,
+                    FindProjection::Project(c) => Some(c.as_slice())
+                };
+                find_union(tables, source_column, &predicate_columns, projection)?
+            },
+            SourceKind::Search(o, s, e) => {
+                let case_sensitive = search_case_sensitive(o, source.span)?;
+                self.resolve_tables(s.as_deref())?.into_iter()
+                    .map(|(name, builder)| {
+                        let schema = builder.schema().clone();
+                        let predicate = self.search_to_expr(e, case_sensitive, &schema)?;
+                        builder.filter(predicate)?.project(
+                            std::iter::once(name.lit().alias("$table")).chain(schema.columns().into_iter().map(Expr::Column))
+                        )
+                    })
+                    .reduce(|a, b| a?.union_by_name(b?.build()?))
+                    .ok_or_else(|| DataFusionError::Plan(format!("No tables to search at {}", source.span)))??
+            },
+            _ => return Err(DataFusionError::NotImplemented(format!("Source not implemented at {}", source.span))),
         })
     }
 
+    /// Plans `union` of the sources, preceded by the input of the operator if any
+    fn union_to_builder(&self, options: &Options, input: Option<LogicalPlanBuilder>, sources: &[TabularExpression], span: Span) -> Result<LogicalPlanBuilder> {
+        let mut inner = false;
+        let mut source_column = None;
+        for (name, value) in options {
+            match (name.as_str(), value) {
+                ("kind", OptionLiteral::String(k)) if k == "inner" || k == "outer" => inner = k == "inner",
+                ("withsource" | "source", OptionLiteral::String(c)) => source_column = Some(c.as_str()),
+                ("isfuzzy", OptionLiteral::Bool(false)) => {},
+                ("kind" | "withsource" | "source", _) => return Err(DataFusionError::Plan(format!("Invalid value for option '{}' at {}", name, span))),
+                _ => return Err(DataFusionError::NotImplemented(format!("Option '{}' of union not implemented at {}", name, span)))
+            }
+        }
+
+        // tables are named like in Kusto, other sources by their position
+        let name = |i: usize, builder: &LogicalPlanBuilder| match builder.plan() {
+            LogicalPlan::TableScan(t) => t.table_name.table().to_string(),
+            _ => format!("union_arg{}", i)
+        };
+        let tables = input.into_iter().map(Ok)
+            .chain(sources.iter().map(|q| self.query_statement_to_plan(q).map(LogicalPlanBuilder::from)))
+            .enumerate()
+            .map(|(i, b)| b.map(|b| (name(i, &b), b)))
+            .collect::<Result<Vec<_>>>()?;
+        union_tables(tables, inner, source_column)
+    }
+
+    /// Resolves the tables of `find` and `search`, names with wildcards match the tables of the catalog
+    ///
+    /// Without a list of tables, all tables of the catalog are used.
+    fn resolve_tables(&self, sources: Option<&[Source]>) -> Result<Vec<(String, LogicalPlanBuilder)>> {
+        let Some(sources) = sources else {
+            return self.table_names.iter().map(|t| Ok((t.clone(), self.table_to_builder(t)?))).collect();
+        };
+        let mut tables: Vec<(String, LogicalPlanBuilder)> = Vec::new();
+        for source in sources {
+            match &source.kind {
+                SourceKind::Reference(None, None, t) if t.contains('*') => {
+                    let wildcard = WildMatch::new(t);
+                    // tables matched by several wildcards are only used once
+                    for name in self.table_names.iter().filter(|n| wildcard.matches(n)) {
+                        if !tables.iter().any(|(n, _)| n == name) {
+                            tables.push((name.clone(), self.table_to_builder(name)?));
+                        }
+                    }
+                },
+                SourceKind::Reference(_, _, t) => tables.push((t.clone(), self.source_to_builder(source)?)),
+                _ => return Err(DataFusionError::NotImplemented(format!("Source must be a table at {}", source.span)))
+            }
+        }
+        Ok(tables)
+    }
+
     fn apply_operator(&self, builder: LogicalPlanBuilder, operator: &Operator) -> Result<LogicalPlanBuilder> {
-        Ok(match operator {
-            Operator::As(_, y) => builder.alias(TableReference::bare(y.as_str()))?,
-            Operator::Count => builder.count()?,
-            Operator::MvExpand(x) => builder.mv_expand(Column::from(x))?,
-            Operator::Extend(x) => builder.extend(x.iter().map(|(a, b)| (a.clone(), self.ast_to_expr(b).unwrap())))?,
-            Operator::Getschema => builder.getschema()?,
-            Operator::Join(_, x, y) => {
-                let keys: Vec<&str> = y.iter().map(|s| s.as_ref()).collect();
-                builder.join(self.query_statement_to_plan(&x)?, JoinType::Inner, (keys.clone(), keys), Option::None)?
-            },
-            Operator::Project(x) => builder.project_with_alias(x.iter().map(|(a, b)| (a.clone(), self.ast_to_expr(b).unwrap())))?,
-            Operator::ProjectAway(x) => builder.project_away(x)?,
-            Operator::ProjectKeep(x) => builder.project_keep(x)?,
-            Operator::ProjectRename(x) => builder.project_rename(x.iter().cloned().collect())?,
-            Operator::Where(x) => builder.filter(self.ast_to_expr(&x)?)?,
-            Operator::Serialize(x) => builder.serialize(x.iter().map(|(a, e)| (a.clone(), self.ast_to_expr(e).unwrap())))?,
-            Operator::Summarize(x, y) => builder.summarize(x.iter().map(|(a, b)| (a.clone(), self.ast_to_expr(b).unwrap())), y.iter().map(|x| self.ast_to_expr(x).unwrap()))?,
-            Operator::Sort(o) => builder.sort(o.iter().map(|c| SortExpr::new(col(c), false, false)))?,
-            Operator::Take(x) => builder.take(*x)?,
-            Operator::Top(n, e, s, o) => builder.top(*n, self.ast_to_expr(e)?, *s, *o)?,
-            _ => return Err(DataFusionError::NotImplemented("Operator not implemented".to_string())),
+        let schema = &builder.schema().clone();
+        Ok(match &operator.kind {
+            OperatorKind::As(_, y) => builder.alias(TableReference::bare(y.as_str()))?,
+            OperatorKind::Count => builder.count()?,
+            OperatorKind::Distinct(None) => builder.distinct()?,
+            OperatorKind::Distinct(Some(x)) => builder.distinct_columns(self.named_exprs(x, schema)?)?,
+            OperatorKind::MakeSeries(o, m) => {
+                let nonempty = match o.get("kind") {
+                    None => false,
+                    Some(OptionLiteral::String(k)) if k == "default" => false,
+                    Some(OptionLiteral::String(k)) if k == "nonempty" => true,
+                    Some(_) => return Err(DataFusionError::Plan(format!("Invalid value for option 'kind' at {}", operator.span)))
+                };
+                if nonempty && !m.by.is_empty() {
+                    return Err(DataFusionError::NotImplemented(format!("Option 'kind=nonempty' of make-series with by not implemented at {}", operator.span)));
+                }
+                let name = |n: &Option<String>, e: &Expr| n.clone().unwrap_or_else(|| e.schema_name().to_string());
+                let aggregations = m.aggregations.iter()
+                    .map(|(n, e, d)| {
+                        let e = self.ast_to_expr(e, schema)?;
+                        // empty bins are zero by default
+                        let d = d.as_ref().map_or_else(|| Ok(0i64.lit()), |d| self.ast_to_expr(d, schema))?;
+                        Ok((name(n, &e), e, d))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let axis = self.ast_to_expr(&m.on, schema)?;
+                let axis_name = match &m.on.kind {
+                    ExprKind::Ident(n) => n.clone(),
+                    _ => axis.schema_name().to_string()
+                };
+                let from = m.from.as_ref().map(|e| self.ast_to_expr(e, schema)).transpose()?;
+                let to = m.to.as_ref().map(|e| self.ast_to_expr(e, schema)).transpose()?;
+                let by = m.by.iter()
+                    .map(|(n, e)| {
+                        let e = self.ast_to_expr(e, schema)?;
+                        Ok((name(n, &e), e))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                builder.make_series(aggregations, (axis_name, axis), (from, to), self.ast_to_expr(&m.step, schema)?, by, nonempty)?
+            },
+            OperatorKind::MvExpand(o, x, l) => {
+                let bag_expansion = match o.get("bagexpansion") {
+                    None => BagExpansion::Bag,
+                    Some(OptionLiteral::String(s)) if s == "bag" => BagExpansion::Bag,
+                    Some(OptionLiteral::String(s)) if s == "array" => BagExpansion::Array,
+                    Some(_) => return Err(DataFusionError::Plan(format!("Invalid value for option 'bagexpansion' at {}", operator.span)))
+                };
+                let item_index = match o.get("with_itemindex") {
+                    None => None,
+                    Some(OptionLiteral::String(s)) => Some(s.clone()),
+                    Some(_) => return Err(DataFusionError::Plan(format!("Invalid value for option 'with_itemindex' at {}", operator.span)))
+                };
+                let columns = x.iter()
+                    .map(|(n, e, t)| {
+                        let name = match (n, &e.kind) {
+                            (Some(n), _) | (None, ExprKind::Ident(n)) => n.clone(),
+                            _ => return Err(DataFusionError::Plan(format!("Expanded expression requires a name at {}", e.span)))
+                        };
+                        // dynamic values keep their own type
+                        let data_type = match t {
+                            None | Some(Type::Dynamic) => None,
+                            Some(t) => Some(type_to_datatype(t)?)
+                        };
+                        Ok((name, self.ast_to_expr(e, schema)?, data_type))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                builder.mv_expand(columns, bag_expansion, item_index, l.map(|l| l as usize))?
+            },
+            OperatorKind::Extend(x) => builder.extend(self.named_exprs(x, schema)?)?,
+            OperatorKind::Getschema => builder.getschema()?,
+            OperatorKind::Invoke(n, a) => self.call_to_builder(n, a, Some(builder.build()?), operator.span)?,
+            OperatorKind::Join(_, x, y) => {
+                let keys: Vec<Column> = y.iter().map(Column::new_unqualified).collect();
+                builder.join_keys(self.query_statement_to_plan(x)?, JoinType::Inner, keys)?
+            },
+            OperatorKind::Project(x) => builder.project_with_alias(self.named_exprs(x, schema)?)?,
+            OperatorKind::ProjectAway(x) => builder.project_away(x)?,
+            OperatorKind::ProjectKeep(x) => builder.project_keep(x)?,
+            OperatorKind::ProjectRename(x) => builder.project_rename(x.iter().map(|(n, o)| (o.clone(), n.clone())).collect())?,
+            OperatorKind::Where(x) => {
+                let predicate = self.ast_to_expr(x, schema)?;
+                // scalar subqueries are rewritten to joins, the projection removes their columns again
+                let has_subquery = predicate.exists(|e| Ok(matches!(e, Expr::ScalarSubquery(_))))?;
+                let builder = builder.filter(predicate)?;
+                match has_subquery {
+                    true => builder.project(schema.columns().into_iter().map(Expr::Column))?,
+                    false => builder
+                }
+            },
+            OperatorKind::Sample(n) => builder.sample(*n)?,
+            OperatorKind::SampleDistinct(n, c) => builder.sample_distinct(*n, self.column(c, schema))?,
+            OperatorKind::Search(o, x) => {
+                let case_sensitive = search_case_sensitive(o, operator.span)?;
+                builder.filter(self.search_to_expr(x, case_sensitive, schema)?)?
+            },
+            OperatorKind::Scan(o, d, s) => {
+                let match_id = match o.get("with_match_id") {
+                    None => None,
+                    Some(OptionLiteral::String(s)) => Some(s.clone()),
+                    Some(_) => return Err(DataFusionError::Plan(format!("Invalid value for option 'with_match_id' at {}", operator.span)))
+                };
+                let declarations = d.iter()
+                    .map(|(n, t, _)| match t {
+                        Type::Dynamic => Err(DataFusionError::NotImplemented(format!("Declared column '{}' of type dynamic not supported at {}", n, operator.span))),
+                        t => Ok((n.clone(), type_to_datatype(t)?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let names: Vec<_> = s.iter().map(|s| s.name.clone()).collect();
+                let scan_schema = scan_schema(schema, &declarations, &names)?;
+
+                let defaults = d.iter().zip(&declarations)
+                    .map(|((_, _, e), (n, t))| {
+                        let value = match e {
+                            Some(e) => self.ast_to_expr(e, &DFSchema::empty())?,
+                            None => ScalarValue::try_from(t)?.lit()
+                        };
+                        Ok((n.clone(), t.clone(), value.cast_to(t, &DFSchema::empty())?))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let steps = s.iter()
+                    .map(|s| {
+                        let condition = self.ast_to_expr(&s.condition, &scan_schema)?;
+                        if !matches!(condition.get_type(&scan_schema)?, DataType::Boolean | DataType::Null) {
+                            return Err(DataFusionError::Plan(format!("Condition of step '{}' must be a bool at {}", s.name, s.condition.span)));
+                        }
+                        Ok(ScanStep {
+                            name: s.name.clone(),
+                            output: match s.output {
+                                KqlScanOutput::All => ScanOutput::All,
+                                KqlScanOutput::Last => ScanOutput::Last,
+                                KqlScanOutput::None => ScanOutput::None
+                            },
+                            condition,
+                            assignments: s.assignments.iter()
+                                .map(|(n, e)| match declarations.iter().find(|(d, _)| d == n) {
+                                    Some((_, t)) => Ok((n.clone(), self.ast_to_expr(e, &scan_schema)?.cast_to(t, &scan_schema)?)),
+                                    None => Err(DataFusionError::Plan(format!("Column '{}' is not declared at {}", n, e.span)))
+                                })
+                                .collect::<Result<Vec<_>>>()?
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                builder.scan_steps(match_id, defaults, steps)?
+            },
+            OperatorKind::Serialize(x) => builder.serialize(self.named_exprs(x, schema)?)?,
+            OperatorKind::Summarize(x, y) => builder.summarize(self.named_exprs(x, schema)?, y.iter().map(|x| self.ast_to_expr(x, schema)).collect::<Result<Vec<_>>>()?)?,
+            OperatorKind::Sort(o) => builder.sort(o.iter()
+                .map(|(e, asc, nulls_first)| Ok(SortExpr::new(self.ast_to_expr(e, schema)?, *asc, *nulls_first)))
+                .collect::<Result<Vec<_>>>()?)?,
+            OperatorKind::Take(x) => builder.take(*x)?,
+            OperatorKind::Top(n, e, s, o) => builder.top(*n, self.ast_to_expr(e, schema)?, *s, *o)?,
+            OperatorKind::TopHitters(n, (name, e), by) => {
+                let value = self.ast_to_expr(e, schema)?;
+                let name = name.clone().unwrap_or_else(|| match &e.kind {
+                    ExprKind::Ident(n) => n.clone(),
+                    _ => value.schema_name().to_string()
+                });
+                let weight = match by {
+                    Some(b) => {
+                        let weight = self.ast_to_expr(b, schema)?;
+                        let name = match &b.kind {
+                            ExprKind::Ident(n) => n.clone(),
+                            _ => weight.schema_name().to_string()
+                        };
+                        Some((name, weight))
+                    },
+                    None => None
+                };
+                builder.top_hitters(*n, (name, value), weight)?
+            },
+            OperatorKind::TopNested(l) => {
+                let levels = l.iter()
+                    .map(|l| {
+                        let expr = self.ast_to_expr(&l.expr, schema)?;
+                        let name = l.name.clone().unwrap_or_else(|| match &l.expr.kind {
+                            ExprKind::Ident(n) => n.clone(),
+                            _ => expr.schema_name().to_string()
+                        });
+                        Ok(TopNestedLevel {
+                            count: l.count.map(|n| n as usize),
+                            aggregation_name: l.aggregation_name.clone().unwrap_or_else(|| format!("aggregated_{}", name)),
+                            name,
+                            expr,
+                            others: l.others.as_ref().map(|o| self.ast_to_expr(o, schema)).transpose()?,
+                            aggregation: self.ast_to_expr(&l.aggregation, schema)?,
+                            asc: l.asc
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                builder.top_nested(levels)?
+            },
+            OperatorKind::Union(o, s) => self.union_to_builder(o, Some(builder), s, operator.span)?,
+            _ => return Err(DataFusionError::NotImplemented(format!("Operator not implemented at {}", operator.span))),
         })
     }
 
+    /// Binds a top-level `let`, scalars are substituted where no column of the same name exists
+    fn declare_let(&mut self, name: &str, e: &LetExpression) -> Result<()> {
+        match e {
+            // `let t = T` is parsed as a scalar, but binds a table when one exists by that name
+            LetExpression::Scalar(KqlExpr { kind: ExprKind::Ident(t), .. }) if !self.scalars.contains_key(t) && self.table_to_builder(t).is_ok() =>
+                _ = self.tables.insert(name.to_string(), self.table_to_builder(t)?.build()?),
+            LetExpression::Scalar(e) => _ = self.scalars.insert(name.to_string(), self.ast_to_expr(e, &DFSchema::empty())?),
+            LetExpression::Tabular(q) => _ = self.tables.insert(name.to_string(), self.query_statement_to_plan(q)?),
+            LetExpression::Materialize(q) => _ = self.tables.insert(name.to_string(), self.materialize(name, q)?),
+            LetExpression::Function(f) => _ = self.functions.insert(name.to_string(), f.clone())
+        }
+        Ok(())
+    }
+
     pub fn query_to_plan(&self, query: &TabularExpression) -> Result<LogicalPlan> {
         self.query_statement_to_plan(query)
     }
+
+    /// Plans a script of statements, the last tabular expression produces the result
+    pub fn statements_to_plan(&mut self, statements: &[Statement]) -> Result<LogicalPlan> {
+        let mut plan = None;
+        for statement in statements {
+            match statement {
+                Statement::QueryParameters(p) => self.declare_parameters(p)?,
+                Statement::Set(n, v) => self.set_option(n, v.as_ref())?,
+                Statement::TabularExpression(q) => plan = Some(self.query_statement_to_plan(q)?),
+                Statement::Let(n, e) => self.declare_let(n, e)?,
+            }
+        }
+        let plan = plan.ok_or_else(|| DataFusionError::Plan("No tabular expression in query".to_string()))?;
+        match self.truncation {
+            Some(n) => LogicalPlanBuilder::from(plan).limit(0, Some(n))?.build(),
+            None => Ok(plan)
+        }
+    }
 }
 
-fn type_to_datatype(t: &Type) -> DataType {
-    match t {
+/// Refers to a column by its exact name, unlike `col()` which parses and normalizes the name
+fn ident(name: &str) -> Expr {
+    Expr::Column(Column::new_unqualified(name))
+}
+
+/// Data type of a scalar type, dynamic values have no single data type
+fn type_to_datatype(t: &Type) -> Result<DataType> {
+    Ok(match t {
         Type::Bool => DataType::Boolean,
+        Type::DateTime => DataType::Timestamp(TimeUnit::Nanosecond, None),
         Type::Decimal => DataType::Float64,
         Type::Int => DataType::Int32,
         Type::Long => DataType::Int64,
         Type::Real => DataType::Float32,
         Type::String => DataType::Utf8,
         Type::Timespan => DataType::Duration(TimeUnit::Nanosecond),
-        _ => panic!("Not supported")
+        Type::Dynamic => return not_impl_err!("Columns of type dynamic not supported")
+    })
+}
+
+fn regex_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
+}
+
+fn terms_pattern(terms: &[String], prefix: bool, suffix: bool, case_sensitive: bool) -> String {
+    format!(
+        "{}{}({}){}",
+        if case_sensitive { "" } else { "(?i)" },
+        if prefix { "(^|[^[:alnum:]])" } else { "" },
+        terms.iter().map(|t| regex_escape(t)).join("|"),
+        if suffix { "($|[^[:alnum:]])" } else { "" }
+    )
+}
+
+/// Pattern of a term of `search`, `*` matches any characters and removes the term boundary at the ends
+fn search_pattern(term: &str, case_sensitive: bool) -> String {
+    format!(
+        "{}{}({}){}",
+        if case_sensitive { "" } else { "(?i)" },
+        if term.starts_with('*') { "" } else { "(^|[^[:alnum:]])" },
+        term.trim_matches('*').split('*').map(regex_escape).join(".*"),
+        if term.ends_with('*') { "" } else { "($|[^[:alnum:]])" }
+    )
 }
 
-fn literal_to_expr(val: &KqlLiteral) -> Expr {
-    match val {
+fn search_case_sensitive(options: &Options, span: Span) -> Result<bool> {
+    match options.get("kind") {
+        None => Ok(false),
+        Some(OptionLiteral::String(s)) if s == "case_insensitive" => Ok(false),
+        Some(OptionLiteral::String(s)) if s == "case_sensitive" => Ok(true),
+        Some(_) => Err(DataFusionError::Plan(format!("Invalid value for option 'kind' at {}", span)))
+    }
+}
+
+fn dynamic_to_scalar(val: Option<&Dynamic>, offset: i64) -> Option<ScalarValue> {
+    Some(match val {
+        None => ScalarValue::Null,
+        Some(Dynamic::Bool(x)) => ScalarValue::from(*x),
+        Some(Dynamic::DateTime(x)) => x.as_ref().map_or(Some(ScalarValue::TimestampNanosecond(None, None)), |x| datetime_to_scalar(x, offset))?,
+        Some(Dynamic::Decimal(x)) => ScalarValue::from(*x),
+        Some(Dynamic::Int(x)) => ScalarValue::from(*x),
+        Some(Dynamic::Long(x)) => ScalarValue::from(*x),
+        Some(Dynamic::Real(x)) => ScalarValue::from(*x),
+        Some(Dynamic::String(x)) => ScalarValue::from(x.clone()),
+        Some(Dynamic::Timespan(x)) => ScalarValue::DurationNanosecond(*x),
+        Some(Dynamic::Array(a)) => {
+            let values = a.iter().map(|v| dynamic_to_scalar(v.as_ref(), offset)).collect::<Option<Vec<_>>>()?;
+            // arrow lists are typed, so all elements must have the same type
+            let types: HashSet<DataType> = values.iter().filter(|v| !v.is_null()).map(|v| v.data_type()).collect();
+            let t = match types.len() {
+                0 => DataType::Null,
+                1 => types.into_iter().next()?,
+                _ => return None
+            };
+            let values = values.into_iter()
+                .map(|v| if v.is_null() { ScalarValue::try_from(&t).ok() } else { Some(v) })
+                .collect::<Option<Vec<_>>>()?;
+            ScalarValue::List(ScalarValue::new_list_nullable(&values, &t))
+        },
+        Some(Dynamic::Dictionary(d)) if !d.is_empty() => d.iter()
+            .try_fold(ScalarStructBuilder::new(), |b, (k, v)| {
+                let v = dynamic_to_scalar(v.as_ref(), offset)?;
+                Some(b.with_scalar(Field::new(k, v.data_type(), true), v))
+            })?
+            .build().ok()?,
+        _ => return None
+    })
+}
+
+/// Parses an offset like `+0100` or `-5` into minutes
+fn parse_offset(tz: &str) -> Option<i64> {
+    let (sign, digits) = tz.split_at(tz.find(|c: char| c.is_ascii_digit())?);
+    let value = digits.parse::<i64>().ok()?;
+    let minutes = if digits.len() > 2 { value / 100 * 60 + value % 100 } else { value * 60 };
+    match sign {
+        "-" => Some(-minutes),
+        "+" | "" => Some(minutes),
+        _ => None
+    }
+}
+
+/// Converts a datetime to UTC, `offset` is used in minutes when the datetime has no explicit offset
+fn datetime_to_scalar(val: &DateTime, offset: i64) -> Option<ScalarValue> {
+    let offset = match val.timezone.as_deref() {
+        None => offset,
+        Some(tz) => parse_offset(tz)?
+    };
+    let datetime = NaiveDate::from_ymd_opt(val.year as i32, val.month, val.day)?
+        .and_hms_opt(val.hour, val.minute, val.second)?
+        .and_utc() - chrono::Duration::minutes(offset);
+    Some(ScalarValue::TimestampNanosecond(Some(datetime.timestamp_nanos_opt()?), None))
+}
+
+fn literal_to_expr(val: &KqlLiteral, offset: i64) -> Option<Expr> {
+    Some(match val {
         KqlLiteral::Bool(x) => ScalarValue::from(*x).lit(),
+        KqlLiteral::DateTime(Some(x)) => datetime_to_scalar(x, offset)?.lit(),
+        KqlLiteral::DateTime(None) => ScalarValue::TimestampNanosecond(None, None).lit(),
         KqlLiteral::Decimal(x) => ScalarValue::from(*x).lit(),
+        KqlLiteral::Dynamic(x) => dynamic_to_scalar(x.as_ref(), offset)?.lit(),
         KqlLiteral::Int(x) => ScalarValue::from(*x).lit(),
         KqlLiteral::Long(x) => ScalarValue::from(*x).lit(),
         KqlLiteral::Real(x) => ScalarValue::from(*x).lit(),
-        KqlLiteral::String(x) => ScalarValue::from(x.clone()).lit(),
-        KqlLiteral::Timespan(x) => ScalarValue::DurationNanosecond(*x).lit(),
-        _ => panic!("Not supported")
-    }
+        KqlLiteral::String(x, _) => ScalarValue::from(x.clone()).lit(),
+        KqlLiteral::Timespan(x) => ScalarValue::DurationNanosecond(*x).lit()
+    })
 }
@@ -0,0 +1,311 @@
+use arrow_schema::SchemaRef;
+
+use async_trait::async_trait;
+
+use datafusion::arrow::array::{RecordBatch, UInt32Array};
+use datafusion::arrow::compute::{interleave_record_batch, take_record_batch};
+use datafusion::physical_expr::EquivalenceProperties;
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::execution::SessionState;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, Distribution, ExecutionPlan, ExecutionPlanProperties, Partitioning, PlanProperties, RecordBatchStream, SendableRecordBatchStream};
+
+use datafusion::physical_planner::{ExtensionPlanner, PhysicalPlanner};
+
+use datafusion_common::{internal_err, DFSchemaRef, Result};
+
+use datafusion_execution::TaskContext;
+
+use datafusion_expr::{Expr, LogicalPlan, UserDefinedLogicalNode, UserDefinedLogicalNodeCore};
+
+use futures::{Stream, StreamExt};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use std::any::Any;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Rows of the input of `sample`, chosen uniformly at random by [`SampleExec`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Hash)]
+pub(crate) struct SampleNode {
+    input: LogicalPlan,
+    count: usize
+}
+
+impl SampleNode {
+    pub fn new(input: LogicalPlan, count: usize) -> Self {
+        SampleNode {
+            input,
+            count
+        }
+    }
+}
+
+impl UserDefinedLogicalNodeCore for SampleNode {
+    fn name(&self) -> &str {
+        "Sample"
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![&self.input]
+    }
+
+    fn schema(&self) -> &DFSchemaRef {
+        self.input.schema()
+    }
+
+    fn expressions(&self) -> Vec<Expr> {
+        vec![]
+    }
+
+    fn fmt_for_explain(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Sample: count={}", self.count)
+    }
+
+    fn with_exprs_and_inputs(&self, _exprs: Vec<Expr>, inputs: Vec<LogicalPlan>) -> Result<Self> {
+        match <[_; 1]>::try_from(inputs) {
+            Ok([input]) => Ok(SampleNode::new(input, self.count)),
+            Err(_) => internal_err!("Sample expects a single input")
+        }
+    }
+}
+
+/// Plans [`SampleNode`] as [`SampleExec`]
+#[derive(Debug)]
+pub(crate) struct SamplePlanner;
+
+#[async_trait]
+impl ExtensionPlanner for SamplePlanner {
+    async fn plan_extension(&self, _planner: &dyn PhysicalPlanner, node: &dyn UserDefinedLogicalNode, _logical_inputs: &[&LogicalPlan], physical_inputs: &[Arc<dyn ExecutionPlan>], _state: &SessionState) -> Result<Option<Arc<dyn ExecutionPlan>>> {
+        let Some(node) = node.as_any().downcast_ref::<SampleNode>() else {
+            return Ok(None);
+        };
+        let input = match physical_inputs {
+            [input] => input.clone(),
+            _ => return internal_err!("Sample expects a single input")
+        };
+        let input: Arc<dyn ExecutionPlan> = match input.output_partitioning().partition_count() {
+            1 => input,
+            _ => Arc::new(CoalescePartitionsExec::new(input))
+        };
+        let schema = Arc::new(node.input.schema().as_arrow().clone());
+        Ok(Some(Arc::new(SampleExec::new(input, node.count, schema))))
+    }
+}
+
+/// Samples a fixed number of rows of its single input partition with a reservoir,
+/// so memory only depends on the number of sampled rows
+#[derive(Debug)]
+pub(crate) struct SampleExec {
+    input: Arc<dyn ExecutionPlan>,
+    count: usize,
+    schema: SchemaRef,
+    properties: PlanProperties
+}
+
+impl SampleExec {
+    fn new(input: Arc<dyn ExecutionPlan>, count: usize, schema: SchemaRef) -> Self {
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(schema.clone()),
+            Partitioning::UnknownPartitioning(1),
+            EmissionType::Final,
+            Boundedness::Bounded
+        );
+        SampleExec {
+            input,
+            count,
+            schema,
+            properties
+        }
+    }
+}
+
+impl DisplayAs for SampleExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SampleExec: count={}", self.count)
+    }
+}
+
+impl ExecutionPlan for SampleExec {
+    fn name(&self) -> &str {
+        "SampleExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn required_input_distribution(&self) -> Vec<Distribution> {
+        vec![Distribution::SinglePartition]
+    }
+
+    fn benefits_from_input_partitioning(&self) -> Vec<bool> {
+        vec![false]
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(self: Arc<Self>, children: Vec<Arc<dyn ExecutionPlan>>) -> Result<Arc<dyn ExecutionPlan>> {
+        match <[_; 1]>::try_from(children) {
+            Ok([input]) => Ok(Arc::new(SampleExec::new(input, self.count, self.schema.clone()))),
+            Err(_) => internal_err!("SampleExec expects a single input")
+        }
+    }
+
+    fn execute(&self, partition: usize, context: Arc<TaskContext>) -> Result<SendableRecordBatchStream> {
+        if partition != 0 {
+            return internal_err!("SampleExec has a single partition, found {}", partition);
+        }
+        Ok(Box::pin(SampleStream {
+            input: self.input.execute(0, context)?,
+            reservoir: Reservoir::new(self.count),
+            schema: self.schema.clone(),
+            done: false
+        }))
+    }
+}
+
+/// Uniform sample of the rows seen so far, rows that don't enter the reservoir are skipped
+/// without drawing a random number for each of them (Li's Algorithm L)
+struct Reservoir {
+    capacity: usize,
+    /// Batches with the sampled rows
+    batches: Vec<RecordBatch>,
+    /// Sampled rows as the index of a batch and a row in that batch
+    rows: Vec<(usize, usize)>,
+    /// Number of rows seen so far
+    seen: u64,
+    /// Index of the next row that enters the reservoir
+    next: u64,
+    w: f64,
+    rng: StdRng
+}
+
+impl Reservoir {
+    fn new(capacity: usize) -> Self {
+        Reservoir {
+            capacity,
+            batches: Vec::new(),
+            rows: Vec::with_capacity(capacity),
+            seen: 0,
+            next: 0,
+            w: 1.0,
+            rng: StdRng::from_rng(&mut rand::rng())
+        }
+    }
+
+    /// Uniform random number in `(0, 1]`, so its logarithm is finite
+    fn random(&mut self) -> f64 {
+        1.0 - self.rng.random::<f64>()
+    }
+
+    /// Moves to the next row that replaces a sampled row
+    fn skip(&mut self) {
+        self.w *= (self.random().ln() / self.capacity as f64).exp();
+        let skipped = (self.random().ln() / (1.0 - self.w).ln()).floor() as u64;
+        self.next = self.next.saturating_add(skipped).saturating_add(1);
+    }
+
+    fn add(&mut self, batch: &RecordBatch) -> Result<()> {
+        let end = self.seen + batch.num_rows() as u64;
+        // rows of the batch with the sampled row they replace, if the reservoir is full
+        let mut selected: Vec<(u32, Option<usize>)> = Vec::new();
+        let mut len = self.rows.len();
+        while self.capacity > 0 && self.next < end {
+            let row = (self.next - self.seen) as u32;
+            if len < self.capacity {
+                selected.push((row, None));
+                len += 1;
+                match len == self.capacity {
+                    true => self.skip(),
+                    false => self.next += 1
+                }
+            } else {
+                selected.push((row, Some(self.rng.random_range(0..self.capacity))));
+                self.skip();
+            }
+        }
+        self.seen = end;
+        if selected.is_empty() {
+            return Ok(());
+        }
+
+        let indices = UInt32Array::from_iter_values(selected.iter().map(|(r, _)| *r));
+        self.batches.push(take_record_batch(batch, &indices)?);
+        let b = self.batches.len() - 1;
+        for (i, (_, replaced)) in selected.into_iter().enumerate() {
+            match replaced {
+                Some(r) => self.rows[r] = (b, i),
+                None => self.rows.push((b, i))
+            }
+        }
+
+        // replaced rows are dropped once they take as much memory as the sampled rows
+        if self.batches.iter().map(|b| b.num_rows()).sum::<usize>() > 2 * self.capacity {
+            self.compact()?;
+        }
+        Ok(())
+    }
+
+    /// Copies the sampled rows into a single batch
+    fn compact(&mut self) -> Result<()> {
+        let batch = interleave_record_batch(&self.batches.iter().collect::<Vec<_>>(), &self.rows)?;
+        self.rows = (0..batch.num_rows()).map(|i| (0, i)).collect();
+        self.batches = vec![batch];
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<Option<RecordBatch>> {
+        if self.rows.is_empty() {
+            return Ok(None);
+        }
+        self.compact()?;
+        Ok(self.batches.pop())
+    }
+}
+
+struct SampleStream {
+    input: SendableRecordBatchStream,
+    reservoir: Reservoir,
+    schema: SchemaRef,
+    done: bool
+}
+
+impl Stream for SampleStream {
+    type Item = Result<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            match self.input.poll_next_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(batch))) => if let Err(e) = self.reservoir.add(&batch) {
+                    return Poll::Ready(Some(Err(e)));
+                },
+                Poll::Ready(None) => {
+                    self.done = true;
+                    return Poll::Ready(self.reservoir.finish().transpose());
+                }
+            }
+        }
+    }
+}
+
+impl RecordBatchStream for SampleStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
@@ -0,0 +1,525 @@
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+
+use async_trait::async_trait;
+
+use datafusion::arrow::array::{new_null_array, Array, ArrayRef, AsArray, BooleanArray, Int64Array, RecordBatch, RecordBatchOptions};
+use datafusion::arrow::compute::{filter_record_batch, interleave};
+use datafusion::physical_expr::utils::collect_columns;
+use datafusion::physical_expr::{EquivalenceProperties, LexRequirement, PhysicalExpr};
+use datafusion::physical_plan::coalesce_partitions::CoalescePartitionsExec;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::physical_plan::sorts::sort_preserving_merge::SortPreservingMergeExec;
+use datafusion::execution::SessionState;
+use datafusion::physical_plan::{DisplayAs, DisplayFormatType, Distribution, ExecutionPlan, ExecutionPlanProperties, Partitioning, PlanProperties, RecordBatchStream, SendableRecordBatchStream};
+
+use datafusion::physical_planner::{ExtensionPlanner, PhysicalPlanner};
+
+use datafusion_common::{exec_err, internal_err, DFSchema, DFSchemaRef, Result, ScalarValue};
+
+use datafusion_execution::TaskContext;
+
+use datafusion_expr::{Expr, LogicalPlan, UserDefinedLogicalNode, UserDefinedLogicalNodeCore};
+
+use futures::{Stream, StreamExt};
+
+use itertools::Itertools;
+
+use std::any::Any;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use crate::{scan_schema, ScanOutput, ScanStep};
+
+/// `scan` over its serialized input, the steps are matched by [`ScanExec`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct ScanNode {
+    input: LogicalPlan,
+    match_id: Option<String>,
+    declarations: Vec<(String, DataType, Expr)>,
+    steps: Vec<ScanStep>,
+    schema: DFSchemaRef
+}
+
+impl ScanNode {
+    pub fn try_new(input: LogicalPlan, match_id: Option<String>, declarations: Vec<(String, DataType, Expr)>, steps: Vec<ScanStep>) -> Result<Self> {
+        let fields = input.schema().iter().map(|(q, f)| (q.cloned(), f.clone()))
+            .chain(declarations.iter().map(|(n, t, _)| (None, Arc::new(Field::new(n, t.clone(), true)))))
+            .chain(match_id.iter().map(|n| (None, Arc::new(Field::new(n, DataType::Int64, false)))));
+        let schema = Arc::new(DFSchema::new_with_metadata(fields.collect(), HashMap::new())?);
+        Ok(ScanNode {
+            input,
+            match_id,
+            declarations,
+            steps,
+            schema
+        })
+    }
+}
+
+impl PartialOrd for ScanNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (&self.input, &self.match_id, &self.declarations, &self.steps)
+            .partial_cmp(&(&other.input, &other.match_id, &other.declarations, &other.steps))
+    }
+}
+
+impl UserDefinedLogicalNodeCore for ScanNode {
+    fn name(&self) -> &str {
+        "Scan"
+    }
+
+    fn inputs(&self) -> Vec<&LogicalPlan> {
+        vec![&self.input]
+    }
+
+    fn schema(&self) -> &DFSchemaRef {
+        &self.schema
+    }
+
+    /// The expressions of the steps are over [`scan_schema`] instead of the input schema,
+    /// so they are hidden from the optimizer
+    fn expressions(&self) -> Vec<Expr> {
+        vec![]
+    }
+
+    fn fmt_for_explain(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Scan: steps=[{}]", self.steps.iter().map(|s| &s.name).join(", "))
+    }
+
+    fn with_exprs_and_inputs(&self, _exprs: Vec<Expr>, inputs: Vec<LogicalPlan>) -> Result<Self> {
+        match <[_; 1]>::try_from(inputs) {
+            Ok([input]) => ScanNode::try_new(input, self.match_id.clone(), self.declarations.clone(), self.steps.clone()),
+            Err(_) => internal_err!("Scan expects a single input")
+        }
+    }
+}
+
+/// Plans [`ScanNode`] as [`ScanExec`]
+#[derive(Debug)]
+pub(crate) struct ScanPlanner;
+
+#[async_trait]
+impl ExtensionPlanner for ScanPlanner {
+    async fn plan_extension(&self, _planner: &dyn PhysicalPlanner, node: &dyn UserDefinedLogicalNode, logical_inputs: &[&LogicalPlan], physical_inputs: &[Arc<dyn ExecutionPlan>], state: &SessionState) -> Result<Option<Arc<dyn ExecutionPlan>>> {
+        let Some(node) = node.as_any().downcast_ref::<ScanNode>() else {
+            return Ok(None);
+        };
+        let (input, physical_input) = match (logical_inputs, physical_inputs) {
+            ([input], [physical_input]) => (input, physical_input.clone()),
+            _ => return internal_err!("Scan expects a single input")
+        };
+        // partitions are merged in order, so the input stays serialized
+        let physical_input: Arc<dyn ExecutionPlan> = match (physical_input.output_partitioning().partition_count(), physical_input.output_ordering()) {
+            (1, _) => physical_input,
+            (_, Some(ordering)) => Arc::new(SortPreservingMergeExec::new(ordering.clone(), physical_input)),
+            (_, None) => Arc::new(CoalescePartitionsExec::new(physical_input))
+        };
+
+        let declarations: Vec<_> = node.declarations.iter().map(|(n, t, _)| (n.clone(), t.clone())).collect();
+        let names: Vec<_> = node.steps.iter().map(|s| s.name.clone()).collect();
+        let schema = scan_schema(input.schema(), &declarations, &names)?;
+        let program = ScanProgram {
+            defaults: node.declarations.iter().map(|(_, _, d)| state.create_physical_expr(d.clone(), &schema)).collect::<Result<_>>()?,
+            steps: node.steps.iter()
+                .map(|s| Ok(PhysicalStep {
+                    output: s.output,
+                    condition: state.create_physical_expr(s.condition.clone(), &schema)?,
+                    assignments: s.assignments.iter()
+                        .map(|(n, e)| match declarations.iter().position(|(d, _)| d == n) {
+                            Some(i) => Ok((i, state.create_physical_expr(e.clone(), &schema)?)),
+                            None => internal_err!("Column '{}' is not declared in scan", n)
+                        })
+                        .collect::<Result<_>>()?
+                }))
+                .collect::<Result<_>>()?,
+            schema: Arc::new(schema.as_arrow().clone()),
+            inputs: input.schema().fields().len(),
+            match_id: node.match_id.is_some()
+        };
+        Ok(Some(Arc::new(ScanExec::new(physical_input, Arc::new(program), Arc::new(node.schema.as_arrow().clone())))))
+    }
+}
+
+#[derive(Debug)]
+struct PhysicalStep {
+    output: ScanOutput,
+    condition: Arc<dyn PhysicalExpr>,
+    /// Index of the declared column with its value
+    assignments: Vec<(usize, Arc<dyn PhysicalExpr>)>
+}
+
+/// Steps with expressions over the schema of a record followed by the state of every step
+#[derive(Debug)]
+struct ScanProgram {
+    defaults: Vec<Arc<dyn PhysicalExpr>>,
+    steps: Vec<PhysicalStep>,
+    schema: SchemaRef,
+    /// Number of input columns, followed by the declared columns in a record
+    inputs: usize,
+    match_id: bool
+}
+
+/// Matches the steps of a `scan` against the records of its single input partition
+#[derive(Debug)]
+pub(crate) struct ScanExec {
+    input: Arc<dyn ExecutionPlan>,
+    program: Arc<ScanProgram>,
+    schema: SchemaRef,
+    /// Order of the serialized input, which has to be kept by the optimizer
+    ordering: Option<LexRequirement>,
+    properties: PlanProperties
+}
+
+impl ScanExec {
+    fn new(input: Arc<dyn ExecutionPlan>, program: Arc<ScanProgram>, schema: SchemaRef) -> Self {
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(schema.clone()),
+            Partitioning::UnknownPartitioning(1),
+            EmissionType::Incremental,
+            Boundedness::Bounded
+        );
+        ScanExec {
+            ordering: input.output_ordering().cloned().map(LexRequirement::from),
+            input,
+            program,
+            schema,
+            properties
+        }
+    }
+}
+
+impl DisplayAs for ScanExec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ScanExec: steps={}", self.program.steps.len())
+    }
+}
+
+impl ExecutionPlan for ScanExec {
+    fn name(&self) -> &str {
+        "ScanExec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn required_input_distribution(&self) -> Vec<Distribution> {
+        vec![Distribution::SinglePartition]
+    }
+
+    fn required_input_ordering(&self) -> Vec<Option<LexRequirement>> {
+        vec![self.ordering.clone()]
+    }
+
+    fn maintains_input_order(&self) -> Vec<bool> {
+        vec![true]
+    }
+
+    fn benefits_from_input_partitioning(&self) -> Vec<bool> {
+        vec![false]
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        vec![&self.input]
+    }
+
+    fn with_new_children(self: Arc<Self>, children: Vec<Arc<dyn ExecutionPlan>>) -> Result<Arc<dyn ExecutionPlan>> {
+        match <[_; 1]>::try_from(children) {
+            Ok([input]) => Ok(Arc::new(ScanExec::new(input, self.program.clone(), self.schema.clone()))),
+            Err(_) => internal_err!("ScanExec expects a single input")
+        }
+    }
+
+    fn execute(&self, partition: usize, context: Arc<TaskContext>) -> Result<SendableRecordBatchStream> {
+        if partition != 0 {
+            return internal_err!("ScanExec has a single partition, found {}", partition);
+        }
+        Ok(Box::pin(ScanStream {
+            input: self.input.execute(0, context)?,
+            scanner: Scanner::try_new(self.program.clone())?,
+            schema: self.schema.clone(),
+            done: false
+        }))
+    }
+}
+
+/// Records of a row, a single row array for every input and declared column
+type Row = Vec<ArrayRef>;
+
+#[derive(Debug)]
+struct State {
+    /// Last record of every step reached by the sequence, referred to as `step.column`
+    rows: Vec<Row>,
+    match_id: i64
+}
+
+impl State {
+    fn last(&self) -> &Row {
+        &self.rows[self.rows.len() - 1]
+    }
+
+    fn into_last(mut self) -> Row {
+        self.rows.pop().unwrap_or_default()
+    }
+}
+
+/// Matches of the first step for new sequences in a batch, evaluated at once when the step
+/// doesn't depend on the state of any step
+struct FirstStep {
+    matched: BooleanArray,
+    /// Declared columns assigned by the step, for the matching rows only
+    assigned: Vec<(usize, ArrayRef)>,
+    /// Index of the next matching row in the assigned columns
+    next: usize
+}
+
+impl FirstStep {
+    /// Record of the `r`-th row of the batch with the assignments of the step, when it matches
+    fn row(&mut self, r: usize, record: &[ArrayRef], defaults: &[ArrayRef], inputs: usize) -> Option<Row> {
+        if !(self.matched.is_valid(r) && self.matched.value(r)) {
+            return None;
+        }
+        let mut row: Row = record.iter().chain(defaults).cloned().collect();
+        for (i, values) in &self.assigned {
+            row[inputs + i] = values.slice(self.next, 1);
+        }
+        self.next += 1;
+        Some(row)
+    }
+}
+
+/// State machine of the steps, collecting the matched records
+struct Scanner {
+    program: Arc<ScanProgram>,
+    defaults: Row,
+    /// Row of an empty state, without input values and with the default of every declared column
+    empty: Row,
+    states: Vec<Option<State>>,
+    next_match_id: i64,
+    /// Matches of the first step in the current batch
+    first: Option<FirstStep>,
+    output: Vec<(Row, i64)>
+}
+
+impl Scanner {
+    fn try_new(program: Arc<ScanProgram>) -> Result<Self> {
+        // defaults are constant, so they are evaluated without any columns
+        let batch = RecordBatch::try_new_with_options(Arc::new(Schema::empty()), vec![], &RecordBatchOptions::new().with_row_count(Some(1)))?;
+        let defaults: Row = program.defaults.iter().map(|d| d.evaluate(&batch)?.into_array(1)).collect::<Result<_>>()?;
+        let empty = program.schema.fields()[..program.inputs].iter()
+            .map(|f| new_null_array(f.data_type(), 1))
+            .chain(defaults.iter().cloned())
+            .collect();
+        Ok(Scanner {
+            states: program.steps.iter().map(|_| None).collect(),
+            program,
+            defaults,
+            empty,
+            next_match_id: 0,
+            first: None,
+            output: Vec::new()
+        })
+    }
+
+    /// Evaluates the first step against all rows of a batch, unless it refers to the state of a step
+    fn start_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        let program = &self.program;
+        let step = &program.steps[0];
+        let states = program.inputs + self.defaults.len();
+        self.first = None;
+        if std::iter::once(&step.condition).chain(step.assignments.iter().map(|(_, e)| e)).any(|e| collect_columns(e).iter().any(|c| c.index() >= states)) {
+            return Ok(());
+        }
+
+        let n = batch.num_rows();
+        let columns = batch.columns().iter().cloned()
+            .map(Ok)
+            .chain(self.defaults.iter().map(|d| ScalarValue::try_from_array(d, 0)?.to_array_of_size(n)))
+            .chain(program.schema.fields()[states..].iter().map(|f| Ok(new_null_array(f.data_type(), n))))
+            .collect::<Result<Vec<_>>>()?;
+        let batch = RecordBatch::try_new_with_options(program.schema.clone(), columns, &RecordBatchOptions::new().with_row_count(Some(n)))?;
+        let matched = step.condition.evaluate(&batch)?.into_array(n)?;
+        let Some(matched) = matched.as_boolean_opt() else {
+            return exec_err!("Condition of a scan step must be a bool, found {}", matched.data_type());
+        };
+        // assignments are only evaluated for the matching rows, like for a single record
+        let batch = filter_record_batch(&batch, matched)?;
+        let assigned = step.assignments.iter()
+            .map(|(i, e)| Ok((*i, e.evaluate(&batch)?.into_array(batch.num_rows())?)))
+            .collect::<Result<_>>()?;
+        self.first = Some(FirstStep { matched: matched.clone(), assigned, next: 0 });
+        Ok(())
+    }
+
+    fn emit(&mut self, row: Row, match_id: i64) {
+        self.output.push((row, match_id));
+    }
+
+    /// Evaluates a step against a record with the values of its declared columns and the records
+    /// of the sequence, returns the record with the assignments of the step when it matches
+    fn try_step(&self, k: usize, candidate: Row, sequence: &[Row]) -> Result<Option<Row>> {
+        let states = (0..self.states.len()).flat_map(|j| sequence.get(j).unwrap_or(&self.empty));
+        let batch = RecordBatch::try_new(self.program.schema.clone(), candidate.iter().chain(states).cloned().collect())?;
+        let step = &self.program.steps[k];
+        let matched = step.condition.evaluate(&batch)?.into_array(1)?;
+        let Some(matched) = matched.as_boolean_opt() else {
+            return exec_err!("Condition of a scan step must be a bool, found {}", matched.data_type());
+        };
+        if !(matched.is_valid(0) && matched.value(0)) {
+            return Ok(None);
+        }
+
+        let mut row = candidate;
+        for (i, e) in &step.assignments {
+            row[self.program.inputs + i] = e.evaluate(&batch)?.into_array(1)?;
+        }
+        Ok(Some(row))
+    }
+
+    /// Evaluates the steps from the last to the first step against the `r`-th record of the batch
+    fn process(&mut self, r: usize, record: &[ArrayRef]) -> Result<()> {
+        let inputs = self.program.inputs;
+        for k in (0..self.states.len()).rev() {
+            let output = self.program.steps[k].output;
+
+            // a sequence in the previous step advances to this step
+            if let Some(previous) = k.checked_sub(1).and_then(|p| self.states[p].as_ref()) {
+                let candidate = record.iter().chain(&previous.last()[inputs..]).cloned().collect();
+                if let Some(row) = self.try_step(k, candidate, &previous.rows)? {
+                    let Some(previous) = self.states[k - 1].take() else {
+                        return internal_err!("State of step {} is empty", k - 1);
+                    };
+                    if self.program.steps[k - 1].output == ScanOutput::Last {
+                        self.emit(previous.last().clone(), previous.match_id);
+                    }
+                    if let (ScanOutput::Last, Some(old)) = (output, self.states[k].take()) {
+                        let match_id = old.match_id;
+                        self.emit(old.into_last(), match_id);
+                    }
+                    let State { mut rows, match_id } = previous;
+                    if output == ScanOutput::All {
+                        self.emit(row.clone(), match_id);
+                    }
+                    rows.push(row);
+                    self.states[k] = Some(State { rows, match_id });
+                    continue;
+                }
+            }
+
+            // a sequence stays in this step, or a new sequence starts at the first step
+            let matched = match (&self.states[k], k, &mut self.first) {
+                (_, 0, Some(first)) => first.row(r, record, &self.defaults, inputs),
+                (s, 0, None) => self.try_step(k, record.iter().chain(&self.defaults).cloned().collect(), s.as_ref().map_or(&[], |s| &s.rows))?,
+                (Some(s), _, _) => self.try_step(k, record.iter().chain(&s.last()[inputs..]).cloned().collect(), &s.rows)?,
+                (None, _, _) => continue
+            };
+            if let Some(row) = matched {
+                let (mut rows, match_id) = match self.states[k].take() {
+                    Some(s) if k > 0 => (s.rows, s.match_id),
+                    old => {
+                        // the last record of a step is only replaced by a new sequence
+                        if let (ScanOutput::Last, Some(old)) = (output, old) {
+                            let match_id = old.match_id;
+                            self.emit(old.into_last(), match_id);
+                        }
+                        self.next_match_id += 1;
+                        (Vec::new(), self.next_match_id - 1)
+                    }
+                };
+                rows.truncate(k);
+                if output == ScanOutput::All {
+                    self.emit(row.clone(), match_id);
+                }
+                rows.push(row);
+                self.states[k] = Some(State { rows, match_id });
+            }
+        }
+        Ok(())
+    }
+
+    /// Emits the last records of steps that only output their last record
+    fn finish(&mut self) {
+        for k in 0..self.states.len() {
+            if self.program.steps[k].output == ScanOutput::Last {
+                if let Some(state) = self.states[k].take() {
+                    let match_id = state.match_id;
+                    self.emit(state.into_last(), match_id);
+                }
+            }
+        }
+    }
+
+    /// Collects the emitted records in a batch
+    fn take_output(&mut self, schema: SchemaRef) -> Result<Option<RecordBatch>> {
+        if self.output.is_empty() {
+            return Ok(None);
+        }
+        let output = std::mem::take(&mut self.output);
+        let indices: Vec<_> = (0..output.len()).map(|i| (i, 0)).collect();
+        let mut columns = (0..self.empty.len())
+            .map(|c| interleave(&output.iter().map(|(r, _)| r[c].as_ref()).collect::<Vec<_>>(), &indices))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if self.program.match_id {
+            columns.push(Arc::new(Int64Array::from_iter_values(output.iter().map(|(_, m)| *m))));
+        }
+        Ok(Some(RecordBatch::try_new(schema, columns)?))
+    }
+}
+
+struct ScanStream {
+    input: SendableRecordBatchStream,
+    scanner: Scanner,
+    schema: SchemaRef,
+    done: bool
+}
+
+impl ScanStream {
+    fn process(&mut self, batch: &RecordBatch) -> Result<Option<RecordBatch>> {
+        self.scanner.start_batch(batch)?;
+        for r in 0..batch.num_rows() {
+            let record: Row = batch.columns().iter().map(|c| c.slice(r, 1)).collect();
+            self.scanner.process(r, &record)?;
+        }
+        self.scanner.take_output(self.schema.clone())
+    }
+}
+
+impl Stream for ScanStream {
+    type Item = Result<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if self.done {
+                return Poll::Ready(None);
+            }
+            match self.input.poll_next_unpin(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(Some(Ok(batch))) => match self.process(&batch) {
+                    Ok(None) => continue,
+                    result => return Poll::Ready(result.transpose())
+                },
+                Poll::Ready(None) => {
+                    self.done = true;
+                    self.scanner.finish();
+                    let schema = self.schema.clone();
+                    return Poll::Ready(self.scanner.take_output(schema).transpose());
+                }
+            }
+        }
+    }
+}
+
+impl RecordBatchStream for ScanStream {
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+}
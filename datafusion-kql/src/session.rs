@@ -5,15 +5,18 @@ use datafusion::datasource::DefaultTableSource;
 use datafusion::execution::SessionState;
 use datafusion::execution::context::SessionContext;
 
-use datafusion_common::{not_impl_err, plan_datafusion_err, DataFusionError, ResolvedTableReference, Result, TableReference};
+use datafusion_common::{plan_datafusion_err, plan_err, DataFusionError, Diagnostic, Location, ResolvedTableReference, Result, ScalarValue, Span, TableReference};
 
 use datafusion_expr::{AggregateUDF, LogicalPlan, ScalarUDF, TableSource, WindowUDF};
 use datafusion_expr::planner::ContextProvider;
 use datafusion_expr::registry::FunctionRegistry;
 
 use kqlparser::ast::Statement;
+use kqlparser::error::ParseError;
 use kqlparser::parser::parse;
 
+use itertools::Itertools;
+
 use std::collections::HashMap;
 use std::sync::Arc;
 
@@ -22,43 +25,63 @@ use crate::planner::KqlToRel;
 #[allow(async_fn_in_trait)]
 pub trait SessionContextExt {
     async fn kql(&self, sql: &str) -> Result<DataFrame>;
+    async fn kql_with_params(&self, kql: &str, params: HashMap<String, ScalarValue>) -> Result<DataFrame>;
 }
 
 #[allow(async_fn_in_trait)]
 pub trait SessionStateExt {
     async fn create_logical_plan_kql(&self, kql: &str) -> Result<LogicalPlan>;
+    async fn create_logical_plan_kql_with_params(&self, kql: &str, params: HashMap<String, ScalarValue>) -> Result<LogicalPlan>;
     fn kql_to_statement(&self, kql: &str) -> Result<Statement>;
+    fn kql_to_statements(&self, kql: &str) -> Result<Vec<Statement>>;
     async fn kql_statement_to_plan(&self, statement: Statement) -> Result<LogicalPlan>;
+    async fn kql_statements_to_plan(&self, statements: Vec<Statement>, params: HashMap<String, ScalarValue>) -> Result<LogicalPlan>;
 }
 
 impl SessionContextExt for SessionContext {
     async fn kql(&self, kql: &str) -> Result<DataFrame> {
-        let plan = self.state().create_logical_plan_kql(kql).await?;
+        self.kql_with_params(kql, HashMap::new()).await
+    }
+
+    /// Plans and executes a query, binding `params` to the names declared by `declare query_parameters`
+    async fn kql_with_params(&self, kql: &str, params: HashMap<String, ScalarValue>) -> Result<DataFrame> {
+        let plan = self.state().create_logical_plan_kql_with_params(kql, params).await?;
         self.execute_logical_plan(plan).await
     }
 }
 
 impl SessionStateExt for SessionState {
     async fn create_logical_plan_kql(&self, kql: &str) -> Result<LogicalPlan> {
-        //let dialect = self.config.options().sql_parser.dialect.as_str();
-        let statement = self.kql_to_statement(kql)?;
-        let plan = self.kql_statement_to_plan(statement).await?;
-        Ok(plan)
+        self.create_logical_plan_kql_with_params(kql, HashMap::new()).await
+    }
+
+    async fn create_logical_plan_kql_with_params(&self, kql: &str, params: HashMap<String, ScalarValue>) -> Result<LogicalPlan> {
+        let statements = self.kql_to_statements(kql)?;
+        self.kql_statements_to_plan(statements, params).await
     }
     
+    /// Parses a query consisting of a single statement, use `kql_to_statements` for queries with `let` statements
     fn kql_to_statement(&self, kql: &str) -> Result<Statement> {
-        let mut statements = parse(kql).unwrap().1;
+        let mut statements = self.kql_to_statements(kql)?;
         if statements.len() > 1 {
-            return not_impl_err!(
-                "The context currently only supports a single KQL statement"
+            return plan_err!(
+                "Expected a single KQL statement but found {}, use kql_to_statements instead", statements.len()
             )
         }
-        Ok(statements.pop().ok_or_else(|| {
+        statements.pop().ok_or_else(|| {
             plan_datafusion_err!("No KQL statements were provided in the query string")
-        })?)
+        })
     }
-    
+
+    fn kql_to_statements(&self, kql: &str) -> Result<Vec<Statement>> {
+        parse(kql).map_err(parse_error_to_datafusion)
+    }
+
     async fn kql_statement_to_plan(&self, statement: Statement) -> Result<LogicalPlan> {
+        self.kql_statements_to_plan(vec![statement], HashMap::new()).await
+    }
+
+    async fn kql_statements_to_plan(&self, statements: Vec<Statement>, params: HashMap<String, ScalarValue>) -> Result<LogicalPlan> {
         let mut provider = SessionContextProvider {
             state: self,
             tables: HashMap::with_capacity(10),
@@ -82,14 +105,29 @@ impl SessionStateExt for SessionState {
             }
         }
 
-        let kql = KqlToRel::new(&provider);
-        match statement {
-            Statement::TabularExpression(query) => kql.query_to_plan(&query),
-            _ => not_impl_err!("Statement type not supported")
-        }
+        // tables of the default schema are searched when a query doesn't name them
+        let options = self.config_options();
+        let table_names = catalog_list.catalog(&options.catalog.default_catalog)
+            .and_then(|c| c.schema(&options.catalog.default_schema))
+            .map(|s| s.table_names().into_iter().sorted().collect())
+            .unwrap_or_default();
+
+        KqlToRel::new(&provider)
+            .with_parameters(params)
+            .with_table_names(table_names)
+            .statements_to_plan(&statements)
     }
 }
 
+fn parse_error_to_datafusion(e: ParseError) -> DataFusionError {
+    let location = Location {
+        line: e.line as u64,
+        column: e.column as u64
+    };
+    let diagnostic = Diagnostic::new_error(e.message.clone(), Some(Span::new(location, location)));
+    plan_datafusion_err!("{}", e).with_diagnostic(diagnostic)
+}
+
 struct SessionContextProvider<'a> {
     state: &'a SessionState,
     tables: HashMap<String, Arc<dyn TableSource>>,
@@ -119,7 +157,7 @@ impl<'a> ContextProvider for SessionContextProvider<'a> {
     }
     
     fn options(&self) -> &ConfigOptions {
-        !unimplemented!()
+        self.state.config_options()
     }
     
     fn udf_names(&self) -> Vec<String> {
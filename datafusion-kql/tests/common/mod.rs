@@ -0,0 +1,23 @@
+use datafusion::arrow::array::RecordBatch;
+use datafusion::datasource::MemTable;
+use datafusion::execution::SessionStateBuilder;
+use datafusion::execution::context::SessionContext;
+
+use datafusion_kql::{register_all, KqlQueryPlanner};
+
+use std::sync::Arc;
+
+/// Registers tables with a partition for every batch
+pub fn context(tables: Vec<(&str, Vec<RecordBatch>)>) -> SessionContext {
+    let state = SessionStateBuilder::new()
+        .with_default_features()
+        .with_query_planner(Arc::new(KqlQueryPlanner))
+        .build();
+    let mut ctx = SessionContext::new_with_state(state);
+    register_all(&mut ctx).unwrap();
+    for (name, batches) in tables {
+        let table = MemTable::try_new(batches[0].schema(), batches.into_iter().map(|b| vec![b]).collect()).unwrap();
+        ctx.register_table(name, Arc::new(table)).unwrap();
+    }
+    ctx
+}
@@ -0,0 +1,516 @@
+use datafusion::arrow::array::{ArrayRef, AsArray, Int64Array, RecordBatch, StringArray};
+use datafusion::arrow::datatypes::Int64Type;
+use datafusion::{assert_batches_eq, assert_batches_sorted_eq};
+use datafusion::execution::context::SessionContext;
+
+use datafusion_kql::SessionContextExt;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+mod common;
+use common::context;
+
+fn strings(values: &[&str]) -> ArrayRef {
+    Arc::new(StringArray::from(values.to_vec()))
+}
+
+fn longs(values: &[i64]) -> ArrayRef {
+    Arc::new(Int64Array::from(values.to_vec()))
+}
+
+fn batch(columns: Vec<(&str, ArrayRef)>) -> RecordBatch {
+    RecordBatch::try_from_iter(columns).unwrap()
+}
+
+async fn query(ctx: &SessionContext, kql: &str) -> Vec<RecordBatch> {
+    ctx.kql(kql).await.unwrap().collect().await.unwrap()
+}
+
+fn traffic() -> SessionContext {
+    context(vec![("traffic", vec![batch(vec![
+        ("City", strings(&["A", "A", "B", "C", "C"])),
+        ("Host", strings(&["h1", "h2", "h1", "h3", "h1"])),
+        ("Bytes", longs(&[10, 5, 7, 1, 2]))
+    ])])])
+}
+
+#[tokio::test]
+async fn top_nested_others() {
+    let ctx = traffic();
+    let batches = query(&ctx, r#"traffic | top-nested 2 of City by sum(Bytes) with others = "Other""#).await;
+    assert_batches_sorted_eq!([
+        "+-------+-----------------+",
+        "| City  | aggregated_City |",
+        "+-------+-----------------+",
+        "| A     | 15              |",
+        "| B     | 7               |",
+        "| Other | 3               |",
+        "+-------+-----------------+",
+    ], &batches);
+
+    let batches = query(&ctx, r#"traffic | top-nested 1 of City with others = "Other" by s = sum(Bytes), top-nested 1 of Host by count() with others = "x""#).await;
+    assert_batches_sorted_eq!([
+        "+-------+----+------+-----------------+",
+        "| City  | s  | Host | aggregated_Host |",
+        "+-------+----+------+-----------------+",
+        "| A     | 15 | h1   | 1               |",
+        "| A     | 15 | x    | 1               |",
+        "| Other | 10 | h1   | 2               |",
+        "| Other | 10 | x    | 1               |",
+        "+-------+----+------+-----------------+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn top_hitters() {
+    // partial sketches of both partitions are merged
+    let ctx = context(vec![("urls", vec![
+        batch(vec![("Url", strings(&["a", "b", "a", "c"])), ("Bytes", longs(&[1, 10, 1, 3]))]),
+        batch(vec![("Url", strings(&["c", "a", "d", "a"])), ("Bytes", longs(&[3, 1, 2, 1]))])
+    ])]);
+    let batches = query(&ctx, "urls | top-hitters 2 of Url").await;
+    assert_batches_sorted_eq!([
+        "+-----+-----------------------+",
+        "| Url | approximate_count_Url |",
+        "+-----+-----------------------+",
+        "| a   | 4                     |",
+        "| c   | 2                     |",
+        "+-----+-----------------------+",
+    ], &batches);
+
+    let batches = query(&ctx, "urls | top-hitters 2 of Url by Bytes").await;
+    assert_batches_sorted_eq!([
+        "+-----+-----------------------+",
+        "| Url | approximate_sum_Bytes |",
+        "+-----+-----------------------+",
+        "| b   | 10                    |",
+        "| c   | 6                     |",
+        "+-----+-----------------------+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn find() {
+    // tables without a column of the predicate match on the other columns
+    let ctx = context(vec![
+        ("ev", vec![batch(vec![("bytes", longs(&[50, 70])), ("host", strings(&["x", "y"]))])]),
+        ("t1", vec![batch(vec![("a", longs(&[1, 5])), ("name", strings(&["p", "q"]))])]),
+        ("t2", vec![batch(vec![("a", longs(&[4, 2, 0])), ("bytes", longs(&[10, 90, 1]))])])
+    ]);
+    let batches = query(&ctx, "find in (ev, t*) where bytes > 60 or a > 3").await;
+    assert_batches_sorted_eq!([
+        "+---------+-------+---+-------------------+",
+        "| source_ | bytes | a | pack_             |",
+        "+---------+-------+---+-------------------+",
+        "| ev      | 70    |   | {host: y, name: } |",
+        "| t1      |       | 5 | {host: , name: q} |",
+        "| t2      | 10    | 4 | {host: , name: }  |",
+        "| t2      | 90    | 2 | {host: , name: }  |",
+        "+---------+-------+---+-------------------+",
+    ], &batches);
+
+    let batches = query(&ctx, "find withsource=T in (ev, t*) where bytes > 60 or a > 3 project a, bytes").await;
+    assert_batches_sorted_eq!([
+        "+----+---+-------+",
+        "| T  | a | bytes |",
+        "+----+---+-------+",
+        "| ev |   | 70    |",
+        "| t1 | 5 |       |",
+        "| t2 | 2 | 90    |",
+        "| t2 | 4 | 10    |",
+        "+----+---+-------+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn scan() {
+    // partitions of the sorted input are merged in order
+    let ctx = context(vec![("seq", vec![
+        batch(vec![("x", longs(&[4, 1]))]),
+        batch(vec![("x", longs(&[2, 5, 3]))])
+    ])]);
+    let batches = query(&ctx, "seq | sort by x asc | scan declare (cumulative_x: long = 0) with (step s1: true => cumulative_x = x + s1.cumulative_x;)").await;
+    assert_batches_eq!([
+        "+---+--------------+",
+        "| x | cumulative_x |",
+        "+---+--------------+",
+        "| 1 | 1            |",
+        "| 2 | 3            |",
+        "| 3 | 6            |",
+        "| 4 | 10           |",
+        "| 5 | 15           |",
+        "+---+--------------+",
+    ], &batches);
+
+    // a sequence restarts at the first step once the last step matched
+    let batches = query(&ctx, r#"datatable (Ts: timespan, Event: string) [0m, "A", 1m, "Start", 2m, "B", 3m, "D", 4m, "Stop", 6m, "C", 8m, "Start", 11m, "E", 12m, "Stop"]
+        | sort by Ts asc
+        | scan with_match_id = m_id with (
+            step s1: Event == "Start";
+            step s2: Event != "Start" and Event != "Stop" and Ts - s1.Ts <= 5m;
+            step s3: Event == "Stop" and Ts - s1.Ts <= 5m;
+        )
+        | project Event, m_id"#).await;
+    assert_batches_eq!([
+        "+-------+------+",
+        "| Event | m_id |",
+        "+-------+------+",
+        "| Start | 0    |",
+        "| B     | 0    |",
+        "| D     | 0    |",
+        "| Stop  | 0    |",
+        "| Start | 1    |",
+        "| E     | 1    |",
+        "| Stop  | 1    |",
+        "+-------+------+",
+    ], &batches);
+}
+
+/// Values of a long column of all batches
+fn column(batches: &[RecordBatch], name: &str) -> Vec<i64> {
+    batches.iter()
+        .flat_map(|b| b.column_by_name(name).unwrap().as_primitive::<Int64Type>().values().to_vec())
+        .collect()
+}
+
+#[tokio::test]
+async fn sample() {
+    let ctx = context(vec![("numbers", vec![
+        batch(vec![("x", longs(&(0..100).collect::<Vec<_>>())), ("y", longs(&(0..100).map(|x| x % 7).collect::<Vec<_>>()))]),
+        batch(vec![("x", longs(&(100..250).collect::<Vec<_>>())), ("y", longs(&(100..250).map(|x| x % 7).collect::<Vec<_>>()))])
+    ])]);
+
+    let mut values = column(&query(&ctx, "numbers | distinct y").await, "y");
+    values.sort();
+    assert_eq!(values, (0..7).collect::<Vec<_>>());
+
+    let values = column(&query(&ctx, "numbers | sample 10").await, "x");
+    assert_eq!(values.len(), 10);
+    assert_eq!(values.iter().collect::<HashSet<_>>().len(), 10);
+    assert!(values.iter().all(|x| (0..250).contains(x)));
+
+    // the whole input is returned when it has fewer rows than requested
+    let mut values = column(&query(&ctx, "numbers | sample 1000").await, "x");
+    values.sort();
+    assert_eq!(values, (0..250).collect::<Vec<_>>());
+
+    let values = column(&query(&ctx, "numbers | sample-distinct 3 of y").await, "y");
+    assert_eq!(values.len(), 3);
+    assert_eq!(values.iter().collect::<HashSet<_>>().len(), 3);
+    assert!(values.iter().all(|y| (0..7).contains(y)));
+
+    let mut values = column(&query(&ctx, "numbers | sample-distinct 10 of y").await, "y");
+    values.sort();
+    assert_eq!(values, (0..7).collect::<Vec<_>>());
+}
+
+#[tokio::test]
+async fn make_series() {
+    let ctx = context(vec![]);
+    let data = r#"datatable (ts: datetime, host: string, v: long) [
+        datetime(2024-01-01 00:10), "a", 1,
+        datetime(2024-01-01 00:20), "a", 2,
+        datetime(2024-01-01 02:05), "a", 3,
+        datetime(2024-01-01 01:30), "b", 4
+    ]"#;
+
+    // bins without records are filled with the default
+    let batches = query(&ctx, &format!("{data} | make-series total = sum(v) default = 0 on ts from datetime(2024-01-01) to datetime(2024-01-01 04:00) step 1h by host")).await;
+    assert_batches_sorted_eq!([
+        "+------+--------------+--------------------------------------------------------------------------------------+",
+        "| host | total        | ts                                                                                   |",
+        "+------+--------------+--------------------------------------------------------------------------------------+",
+        "| a    | [3, 0, 3, 0] | [2024-01-01T00:00:00, 2024-01-01T01:00:00, 2024-01-01T02:00:00, 2024-01-01T03:00:00] |",
+        "| b    | [0, 4, 0, 0] | [2024-01-01T00:00:00, 2024-01-01T01:00:00, 2024-01-01T02:00:00, 2024-01-01T03:00:00] |",
+        "+------+--------------+--------------------------------------------------------------------------------------+",
+    ], &batches);
+
+    // without a range, the bins span the records of all groups
+    let batches = query(&ctx, &format!("{data} | make-series c = count(), m = max(v) default = -1 on ts step 1h by host")).await;
+    assert_batches_sorted_eq!([
+        "+------+-----------+-------------+-----------------------------------------------------------------+",
+        "| host | c         | m           | ts                                                              |",
+        "+------+-----------+-------------+-----------------------------------------------------------------+",
+        "| a    | [2, 0, 1] | [2, -1, 3]  | [2024-01-01T00:00:00, 2024-01-01T01:00:00, 2024-01-01T02:00:00] |",
+        "| b    | [0, 1, 0] | [-1, 4, -1] | [2024-01-01T00:00:00, 2024-01-01T01:00:00, 2024-01-01T02:00:00] |",
+        "+------+-----------+-------------+-----------------------------------------------------------------+",
+    ], &batches);
+
+    // an empty input has no series, unless one of defaults is requested
+    let empty = format!("{data} | where v > 10 | make-series c = count() on ts from datetime(2024-01-01) to datetime(2024-01-01 02:00) step 1h");
+    assert!(query(&ctx, &empty).await.iter().all(|b| b.num_rows() == 0));
+    let batches = query(&ctx, &empty.replace("make-series", "make-series kind=nonempty")).await;
+    assert_batches_eq!([
+        "+--------+--------------------------------------------+",
+        "| c      | ts                                         |",
+        "+--------+--------------------------------------------+",
+        "| [0, 0] | [2024-01-01T00:00:00, 2024-01-01T01:00:00] |",
+        "+--------+--------------------------------------------+",
+    ], &batches);
+
+    assert!(ctx.kql(&format!("{data} | make-series kind=nonempty c = count() on ts step 1h by host")).await.is_err());
+    assert!(ctx.kql(&format!("{data} | make-series kind=other c = count() on ts step 1h")).await.is_err());
+}
+
+#[tokio::test]
+async fn search() {
+    let ctx = context(vec![
+        ("ev", vec![batch(vec![("bytes", longs(&[50, 70])), ("host", strings(&["x", "y"]))])]),
+        ("t1", vec![batch(vec![("a", longs(&[1, 5])), ("name", strings(&["p", "q"]))])])
+    ]);
+    let batches = query(&ctx, r#"ev | search "Y""#).await;
+    assert_batches_sorted_eq!([
+        "+-------+------+",
+        "| bytes | host |",
+        "+-------+------+",
+        "| 70    | y    |",
+        "+-------+------+",
+    ], &batches);
+
+    let batches = query(&ctx, r#"ev | search kind=case_sensitive "Y" | count"#).await;
+    assert_batches_sorted_eq!([
+        "+-------+",
+        "| count |",
+        "+-------+",
+        "| 0     |",
+        "+-------+",
+    ], &batches);
+
+    let batches = query(&ctx, r#"ev | search host:"x""#).await;
+    assert_batches_sorted_eq!([
+        "+-------+------+",
+        "| bytes | host |",
+        "+-------+------+",
+        "| 50    | x    |",
+        "+-------+------+",
+    ], &batches);
+
+    // records of several tables are unioned with the name of their table
+    let batches = query(&ctx, r#"search in (ev, t1) bytes > 60 or name == "p""#).await;
+    assert_batches_sorted_eq!([
+        "+--------+-------+------+---+------+",
+        "| $table | bytes | host | a | name |",
+        "+--------+-------+------+---+------+",
+        "| ev     | 70    | y    |   |      |",
+        "| t1     |       |      | 1 | p    |",
+        "+--------+-------+------+---+------+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn mv_expand() {
+    let ctx = context(vec![]);
+    let batches = query(&ctx, "range a from 1 to 2 step 1 | extend b = dynamic([10, 20, 30]) | mv-expand with_itemindex=i b").await;
+    assert_batches_sorted_eq!([
+        "+---+----+---+",
+        "| a | b  | i |",
+        "+---+----+---+",
+        "| 1 | 10 | 0 |",
+        "| 1 | 20 | 1 |",
+        "| 1 | 30 | 2 |",
+        "| 2 | 10 | 0 |",
+        "| 2 | 20 | 1 |",
+        "| 2 | 30 | 2 |",
+        "+---+----+---+",
+    ], &batches);
+
+    // the limit applies to the records of every expanded row
+    let batches = query(&ctx, "range a from 1 to 2 step 1 | extend b = dynamic([10, 20, 30]) | mv-expand b limit 2").await;
+    assert_batches_sorted_eq!([
+        "+---+----+",
+        "| a | b  |",
+        "+---+----+",
+        "| 1 | 10 |",
+        "| 1 | 20 |",
+        "| 2 | 10 |",
+        "| 2 | 20 |",
+        "+---+----+",
+    ], &batches);
+
+    let batches = query(&ctx, r#"range a from 1 to 1 step 1 | extend b = dynamic({"x": 1, "y": 2}) | mv-expand bagexpansion=array b"#).await;
+    assert_batches_sorted_eq!([
+        "+---+--------+",
+        "| a | b      |",
+        "+---+--------+",
+        "| 1 | [x, 1] |",
+        "| 1 | [y, 2] |",
+        "+---+--------+",
+    ], &batches);
+
+    // shorter arrays are padded with nulls
+    let batches = query(&ctx, r#"range a from 1 to 1 step 1 | extend b = dynamic([1, 2]), c = dynamic(["p", "q", "r"]) | mv-expand b, c to typeof(string)"#).await;
+    assert_batches_sorted_eq!([
+        "+---+---+---+",
+        "| a | b | c |",
+        "+---+---+---+",
+        "| 1 |   | r |",
+        "| 1 | 1 | p |",
+        "| 1 | 2 | q |",
+        "+---+---+---+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn string_predicates() {
+    let ctx = context(vec![("messages", vec![batch(vec![
+        ("id", longs(&[1, 2, 3, 4, 5])),
+        ("m", strings(&["Error in disk", "no errors", "ERROR", "disk-error.log", "Warning"]))
+    ])])]);
+
+    // `has` matches whole terms and `contains` any substring, both ignore case unless `_cs`
+    for (predicate, ids) in [
+        (r#"m has "error""#, vec![1, 3, 4]),
+        (r#"m !has "error""#, vec![2, 5]),
+        (r#"m has_cs "error""#, vec![4]),
+        (r#"m hasprefix "err""#, vec![1, 2, 3, 4]),
+        (r#"m contains "ERR""#, vec![1, 2, 3, 4]),
+        (r#"m contains_cs "ERR""#, vec![3]),
+        (r#"m !contains "err""#, vec![5]),
+        (r#"m startswith "err""#, vec![1, 3]),
+        (r#"m endswith_cs ".log""#, vec![4]),
+        (r#"m =~ "error""#, vec![3]),
+        (r#"m matches regex "^[A-Z]+$""#, vec![3])
+    ] {
+        assert_eq!(column(&query(&ctx, &format!("messages | where {predicate} | project id")).await, "id"), ids, "{predicate}");
+    }
+}
+
+#[tokio::test]
+async fn set_membership() {
+    let ctx = context(vec![
+        ("commands", vec![batch(vec![
+            ("id", longs(&[1, 2, 3, 4])),
+            ("m", strings(&["curl http", "wget ftp", "Curl wget", "ssh"]))
+        ])]),
+        ("ids", vec![batch(vec![("k", longs(&[2, 4]))])])
+    ]);
+
+    for (predicate, ids) in [
+        ("id in (1, 3)", vec![1, 3]),
+        ("id !in (1, 3)", vec![2, 4]),
+        (r#"m in ("ssh", "CURL HTTP")"#, vec![4]),
+        (r#"m in~ ("ssh", "CURL HTTP")"#, vec![1, 4]),
+        (r#"m has_any (dynamic(["curl", "ftp"]))"#, vec![1, 2, 3]),
+        (r#"m has_all ("curl", "wget")"#, vec![3]),
+        // the right side can be a tabular subquery
+        ("id in (ids)", vec![2, 4]),
+        ("id in (ids | where k > 2)", vec![4]),
+        ("id !in (ids)", vec![1, 3])
+    ] {
+        assert_eq!(column(&query(&ctx, &format!("commands | where {predicate} | project id")).await, "id"), ids, "{predicate}");
+    }
+}
+
+#[tokio::test]
+async fn between() {
+    let ctx = context(vec![]);
+    let data = r#"datatable (id: long, ts: datetime) [
+        1, datetime(2024-01-01),
+        2, datetime(2024-01-01 23:59),
+        3, datetime(2024-01-02),
+        4, datetime(2024-01-02 00:00:01)
+    ]"#;
+
+    // both bounds are inclusive, a timespan as upper bound is relative to the lower one
+    for (predicate, ids) in [
+        ("id between (2 .. 3)", vec![2, 3]),
+        ("id !between (2 .. 3)", vec![1, 4]),
+        ("ts between (datetime(2024-01-01 12:00) .. datetime(2024-01-02))", vec![2, 3]),
+        ("ts between (datetime(2024-01-01) .. 1d)", vec![1, 2, 3]),
+        ("ts !between (datetime(2024-01-01) .. 1d)", vec![4])
+    ] {
+        assert_eq!(column(&query(&ctx, &format!("{data} | where {predicate} | project id")).await, "id"), ids, "{predicate}");
+    }
+}
+
+#[tokio::test]
+async fn bracket_identifiers() {
+    let ctx = context(vec![("connections", vec![batch(vec![
+        ("Source IP", strings(&["10.0.0.1", "10.0.0.2"])),
+        ("user.name", strings(&["alice", "bob"])),
+        ("User.Name", strings(&["A", "B"]))
+    ])])]);
+
+    // names are resolved exactly, without splitting on dots or normalizing case
+    let batches = query(&ctx, r#"connections | where ['Source IP'] == "10.0.0.2" | project ['user.name'], ["User.Name"], ['by'] = 1, n = strlen(['user.name'])"#).await;
+    assert_batches_eq!([
+        "+-----------+-----------+----+---+",
+        "| user.name | User.Name | by | n |",
+        "+-----------+-----------+----+---+",
+        "| bob       | B         | 1  | 3 |",
+        "+-----------+-----------+----+---+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn functions() {
+    let ctx = context(vec![]);
+
+    // scalar and tabular functions are inlined, missing arguments take their default
+    let batches = query(&ctx, r#"
+        let f = (a: long, b: long = 10) { a * b };
+        let g = (T: (x: long), n: long = 1) { T | where x > n };
+        range x from 1 to 3 step 1 | invoke g() | extend y = f(x), z = f(x, 2)
+    "#).await;
+    assert_batches_eq!([
+        "+---+----+---+",
+        "| x | y  | z |",
+        "+---+----+---+",
+        "| 2 | 20 | 4 |",
+        "| 3 | 30 | 6 |",
+        "+---+----+---+",
+    ], &batches);
+
+    let batches = query(&ctx, "let g = (n: long = 1) { range x from 1 to 3 step 1 | where x > n }; g(2)").await;
+    assert_batches_eq!([
+        "+---+",
+        "| x |",
+        "+---+",
+        "| 3 |",
+        "+---+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn sort() {
+    let ctx = context(vec![]);
+    let data = "datatable (x: long) [2, long(null), 3, 1]";
+
+    // descending with nulls last by default, ascending with nulls first
+    let batches = query(&ctx, &format!("{data} | sort by x")).await;
+    assert_batches_eq!([
+        "+---+",
+        "| x |",
+        "+---+",
+        "| 3 |",
+        "| 2 |",
+        "| 1 |",
+        "|   |",
+        "+---+",
+    ], &batches);
+
+    let batches = query(&ctx, &format!("{data} | order by x asc")).await;
+    assert_batches_eq!([
+        "+---+",
+        "| x |",
+        "+---+",
+        "|   |",
+        "| 1 |",
+        "| 2 |",
+        "| 3 |",
+        "+---+",
+    ], &batches);
+
+    let batches = query(&ctx, &format!("{data} | sort by x desc nulls first")).await;
+    assert_batches_eq!([
+        "+---+",
+        "| x |",
+        "+---+",
+        "|   |",
+        "| 3 |",
+        "| 2 |",
+        "| 1 |",
+        "+---+",
+    ], &batches);
+}
@@ -0,0 +1,152 @@
+use datafusion::arrow::array::RecordBatch;
+use datafusion::{assert_batches_eq, assert_batches_sorted_eq};
+use datafusion::common::ScalarValue;
+use datafusion::execution::context::SessionContext;
+
+use datafusion_kql::SessionContextExt;
+
+use std::collections::HashMap;
+
+mod common;
+use common::context;
+
+async fn query(ctx: &SessionContext, kql: &str, params: &[(&str, ScalarValue)]) -> Vec<RecordBatch> {
+    let params: HashMap<_, _> = params.iter().map(|(n, v)| (n.to_string(), v.clone())).collect();
+    ctx.kql_with_params(kql, params).await.unwrap().collect().await.unwrap()
+}
+
+#[tokio::test]
+async fn query_parameters() {
+    let ctx = context(vec![]);
+    let kql = r#"declare query_parameters (n: long = 2, s: string); range x from 1 to n step 1 | extend y = s"#;
+
+    // values are cast to the declared type, missing values take the default
+    let batches = query(&ctx, kql, &[("s", ScalarValue::from("a"))]).await;
+    assert_batches_eq!([
+        "+---+---+",
+        "| x | y |",
+        "+---+---+",
+        "| 1 | a |",
+        "| 2 | a |",
+        "+---+---+",
+    ], &batches);
+
+    let batches = query(&ctx, kql, &[("n", ScalarValue::from("3")), ("s", ScalarValue::from("b"))]).await;
+    assert_batches_eq!([
+        "+---+---+",
+        "| x | y |",
+        "+---+---+",
+        "| 1 | b |",
+        "| 2 | b |",
+        "| 3 | b |",
+        "+---+---+",
+    ], &batches);
+
+    let error = ctx.kql(kql).await.unwrap_err();
+    assert_eq!(error.strip_backtrace(), "Error during planning: No value provided for query parameter 's'");
+}
+
+#[tokio::test]
+async fn set() {
+    let ctx = context(vec![]);
+    let batches = query(&ctx, "set truncationmaxrecords = 2; range x from 1 to 5 step 1", &[]).await;
+    assert_batches_eq!([
+        "+---+",
+        "| x |",
+        "+---+",
+        "| 1 |",
+        "| 2 |",
+        "+---+",
+    ], &batches);
+
+    let batches = query(&ctx, "set query_now = datetime(2024-01-01); print t = now()", &[]).await;
+    assert_batches_eq!([
+        "+---------------------+",
+        "| t                   |",
+        "+---------------------+",
+        "| 2024-01-01T00:00:00 |",
+        "+---------------------+",
+    ], &batches);
+
+    // datetimes without an offset are in the time zone of the query
+    let batches = query(&ctx, r#"set query_timezone = "+02:00"; print t = datetime(2024-01-01 02:00)"#, &[]).await;
+    assert_batches_eq!([
+        "+---------------------+",
+        "| t                   |",
+        "+---------------------+",
+        "| 2024-01-01T00:00:00 |",
+        "+---------------------+",
+    ], &batches);
+
+    let batches = query(&ctx, "set query_case_insensitive; range X from 1 to 2 step 1 | project x", &[]).await;
+    assert_batches_eq!([
+        "+---+",
+        "| X |",
+        "+---+",
+        "| 1 |",
+        "| 2 |",
+        "+---+",
+    ], &batches);
+
+    // unknown options are ignored
+    let batches = query(&ctx, "set querytrace; print x = 1", &[]).await;
+    assert_batches_eq!([
+        "+---+",
+        "| x |",
+        "+---+",
+        "| 1 |",
+        "+---+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn materialize() {
+    let ctx = context(vec![]);
+    // the materialized records are computed once, so both sides of the join see the same random values
+    let batches = query(&ctx, "let t = materialize(range x from 1 to 3 step 1 | extend r = rand()); t | join kind=inner (t) on x | where r != r1 | count", &[]).await;
+    assert_batches_eq!([
+        "+-------+",
+        "| count |",
+        "+-------+",
+        "| 0     |",
+        "+-------+",
+    ], &batches);
+
+    let batches = query(&ctx, "let t = materialize(range x from 1 to 3 step 1); print c = toscalar(t | count)", &[]).await;
+    assert_batches_eq!([
+        "+---+",
+        "| c |",
+        "+---+",
+        "| 3 |",
+        "+---+",
+    ], &batches);
+}
+
+#[tokio::test]
+async fn let_union() {
+    let ctx = context(vec![]);
+    let batches = query(&ctx, "let n = 1; let T = range x from 1 to 3 step 1; let U = T | extend y = x * 10; T | union withsource=src (U | where x > n), (print x = 9)", &[]).await;
+    assert_batches_sorted_eq!([
+        "+------------+---+----+",
+        "| src        | x | y  |",
+        "+------------+---+----+",
+        "| union_arg0 | 1 |    |",
+        "| union_arg0 | 2 |    |",
+        "| union_arg0 | 3 |    |",
+        "| union_arg1 | 2 | 20 |",
+        "| union_arg1 | 3 | 30 |",
+        "| union_arg2 | 9 |    |",
+        "+------------+---+----+",
+    ], &batches);
+
+    let batches = query(&ctx, "let T = range x from 1 to 3 step 1; union (T | where x > 1), (T) | summarize c = count() by x", &[]).await;
+    assert_batches_sorted_eq!([
+        "+---+---+",
+        "| x | c |",
+        "+---+---+",
+        "| 1 | 1 |",
+        "| 2 | 2 |",
+        "| 3 | 2 |",
+        "+---+---+",
+    ], &batches);
+}
@@ -2,23 +2,37 @@ use clap::Parser;
 
 use datafusion::arrow::record_batch::RecordBatch;
 use datafusion::arrow::util::pretty;
+use datafusion::common::ScalarValue;
+use datafusion::execution::SessionStateBuilder;
 use datafusion::execution::context::SessionContext;
-use datafusion_kql::{register_all, SessionStateExt};
+use datafusion_kql::{register_all, KqlQueryPlanner, SessionStateExt};
 
 use std::error::Error;
 use std::ffi::OsStr;
 use std::path::PathBuf;
+use std::sync::Arc;
 
 #[derive(Parser)]
 struct Cli {
     #[arg(short, long)]
     file: Vec<PathBuf>,
+    /// Value of a declared query parameter as `name=value`
+    #[arg(short, long, value_parser = parse_param)]
+    param: Vec<(String, String)>,
     query: Option<String>
 }
 
-async fn execute(ctx: &SessionContext, query: &str) -> Result<(), Box<dyn Error>> {
+fn parse_param(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(n, v)| (n.to_string(), v.to_string()))
+        .ok_or_else(|| format!("Parameter '{}' should be formatted as name=value", s))
+}
+
+async fn execute(ctx: &SessionContext, query: &str, params: &[(String, String)]) -> Result<(), Box<dyn Error>> {
     let state = ctx.state();
-    let plan = state.create_logical_plan_kql(query).await?;
+    // Values are passed as strings and cast to the declared type of the parameter
+    let params = params.iter().map(|(n, v)| (n.clone(), ScalarValue::from(v.as_str()))).collect();
+    let plan = state.create_logical_plan_kql_with_params(query, params).await?;
     let results: Vec<RecordBatch> = ctx.execute_logical_plan(plan).await?.collect().await?;
     pretty::print_batches(&results)?;
     Ok(())
@@ -28,7 +42,11 @@ async fn execute(ctx: &SessionContext, query: &str) -> Result<(), Box<dyn Error>
 async fn main() -> Result<(), Box<dyn Error>> {
     let args = Cli::parse();
 
-    let mut ctx = SessionContext::new();
+    let state = SessionStateBuilder::new()
+        .with_default_features()
+        .with_query_planner(Arc::new(KqlQueryPlanner))
+        .build();
+    let mut ctx = SessionContext::new_with_state(state);
     register_all(&mut ctx)?;
     for file in &args.file {
         let base = file.file_stem().unwrap().to_str().unwrap();
@@ -40,7 +58,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             Some("parquet") => ctx.register_parquet(base, file.as_os_str().to_str().unwrap(), Default::default()).await?,
             Some("kql") => {
                 let query = std::fs::read_to_string(file)?;
-                execute(&ctx, &query).await?
+                execute(&ctx, &query, &args.param).await?
             },
             Some(ext) => return Err(format!("File extension '{}' not supported", ext).into()),
             None => return Err("File without extension not supported".into()),
@@ -48,7 +66,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     }
 
     if let Some(query) = &args.query {
-        execute(&ctx, query).await?;
+        execute(&ctx, query, &args.param).await?;
         return Ok(());
     }
     Ok(())
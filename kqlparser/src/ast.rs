@@ -1,45 +1,129 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
+use std::fmt;
 
-pub type Options = HashMap<String, OptionLiteral>;
+mod visit;
+pub use visit::*;
 
-#[derive(Debug, PartialEq)]
+// Ordered maps keep serialization and formatting of the AST deterministic
+pub type Options = BTreeMap<String, OptionLiteral>;
+
+/// Location of a node in the query text as byte offsets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Statement {
     TabularExpression(TabularExpression),
-    Let(String, LetExpression)
+    Let(String, LetExpression),
+    QueryParameters(Vec<(String, Type, Option<Expr>)>),
+    Set(String, Option<Expr>)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TabularExpression {
     pub source: Source,
-    pub operators: Vec<Operator>
+    pub operators: Vec<Operator>,
+    pub span: Span
+}
+
+// Spans are ignored when comparing nodes, so equal queries with different formatting compare equal
+impl PartialEq for TabularExpression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source && self.operators == other.operators
+    }
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Source {
+    pub kind: SourceKind,
+    pub span: Span
+}
+
+impl Source {
+    pub fn new(kind: SourceKind, span: Span) -> Self {
+        Source { kind, span }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Source {
+impl PartialEq for Source {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SourceKind {
+    Call(String, Vec<Expr>),
     Datatable(Vec<(String, Type)>, Vec<Expr>),
     Externaldata(Vec<(String, Type)>, Vec<String>),
     Find(Options, Option<Vec<Source>>, Expr, FindProjection),
     Print(Vec<(Option<String>, Expr)>),
     Range(String, Expr, Expr, Expr),
     Reference(Option<String>, Option<String>, String),
-    Union(Options, Vec<Source>)
+    /// Options, the searched tables and the predicate
+    Search(Options, Option<Vec<Source>>, Expr),
+    Union(Options, Vec<TabularExpression>)
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Operator {
+    pub kind: OperatorKind,
+    pub span: Span
+}
+
+impl Operator {
+    pub fn new(kind: OperatorKind, span: Span) -> Self {
+        Operator { kind, span }
+    }
 }
 
-#[derive(Debug, PartialEq)]
-pub enum Operator {
+impl PartialEq for Operator {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OperatorKind {
     As(Options, String),
     Consume(Options),
     Count,
-    Distinct(Vec<String>),
+    /// Optionally named columns, or all columns for `distinct *`
+    Distinct(Option<Vec<(Option<String>, Expr)>>),
     Evaluate(Options, String, Vec<Expr>),
     Extend(Vec<(Option<String>, Expr)>),
     Facet(Vec<String>, Vec<Operator>),
     Fork(Vec<(Option<String>, Vec<Operator>)>),
     Getschema,
+    Invoke(String, Vec<Expr>),
     Join(Options, TabularExpression, Vec<String>),
     Lookup(Options, TabularExpression, Vec<String>),
+    MakeSeries(Options, MakeSeries),
     MvApply(Vec<((String, String), Option<Type>)>, Vec<Operator>),
-    MvExpand(String),
+    /// Options, optionally named columns with a target type and the row limit
+    MvExpand(Options, Vec<(Option<String>, Expr, Option<Type>)>, Option<u32>),
     Parse(Options, Expr, Vec<PatternToken>),
     ParseWhere(Options, Expr, Vec<PatternToken>),
     ParseKV(Expr, Vec<(String, Type)>, Options),
@@ -53,17 +137,44 @@ pub enum Operator {
     Render(String, Option<Options>),
     Sample(u32),
     SampleDistinct(u32, String),
+    /// Options, declared columns with their default value and the steps
+    Scan(Options, Vec<(String, Type, Option<Expr>)>, Vec<ScanStep>),
+    Search(Options, Expr),
     Serialize(Vec<(Option<String>, Expr)>),
     Summarize(Vec<(Option<String>, Expr)>, Vec<Expr>),
-    Sort(Vec<String>),
+    /// Sort keys with their direction and whether nulls come first
+    Sort(Vec<(Expr, bool, bool)>),
     Take(u32),
     Top(u32, Expr, bool, bool),
-    Union(Options, Vec<Source>),
+    /// Number of values, the value and the optional weight
+    TopHitters(u32, (Option<String>, Expr), Option<Expr>),
+    TopNested(Vec<TopNested>),
+    Union(Options, Vec<TabularExpression>),
     Where(Expr)
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExprKind {
     Ident(String),
     Index(Box<Expr>, Box<Expr>),
     Literal(Literal),
@@ -80,10 +191,58 @@ pub enum Expr {
     Greater(Box<Expr>, Box<Expr>),
     LessOrEqual(Box<Expr>, Box<Expr>),
     GreaterOrEqual(Box<Expr>, Box<Expr>),
-    Func(String, Vec<Expr>)
+    EqualsCaseInsensitive(Box<Expr>, Box<Expr>),
+    NotEqualsCaseInsensitive(Box<Expr>, Box<Expr>),
+    Contains(Box<Expr>, Box<Expr>),
+    NotContains(Box<Expr>, Box<Expr>),
+    ContainsCs(Box<Expr>, Box<Expr>),
+    NotContainsCs(Box<Expr>, Box<Expr>),
+    Has(Box<Expr>, Box<Expr>),
+    NotHas(Box<Expr>, Box<Expr>),
+    HasCs(Box<Expr>, Box<Expr>),
+    NotHasCs(Box<Expr>, Box<Expr>),
+    HasPrefix(Box<Expr>, Box<Expr>),
+    NotHasPrefix(Box<Expr>, Box<Expr>),
+    HasPrefixCs(Box<Expr>, Box<Expr>),
+    NotHasPrefixCs(Box<Expr>, Box<Expr>),
+    HasSuffix(Box<Expr>, Box<Expr>),
+    NotHasSuffix(Box<Expr>, Box<Expr>),
+    HasSuffixCs(Box<Expr>, Box<Expr>),
+    NotHasSuffixCs(Box<Expr>, Box<Expr>),
+    StartsWith(Box<Expr>, Box<Expr>),
+    NotStartsWith(Box<Expr>, Box<Expr>),
+    StartsWithCs(Box<Expr>, Box<Expr>),
+    NotStartsWithCs(Box<Expr>, Box<Expr>),
+    EndsWith(Box<Expr>, Box<Expr>),
+    NotEndsWith(Box<Expr>, Box<Expr>),
+    EndsWithCs(Box<Expr>, Box<Expr>),
+    NotEndsWithCs(Box<Expr>, Box<Expr>),
+    MatchesRegex(Box<Expr>, Box<Expr>),
+    In(Box<Expr>, ExprList),
+    NotIn(Box<Expr>, ExprList),
+    InCaseInsensitive(Box<Expr>, ExprList),
+    NotInCaseInsensitive(Box<Expr>, ExprList),
+    HasAny(Box<Expr>, ExprList),
+    HasAll(Box<Expr>, ExprList),
+    Between(Box<Expr>, Box<Expr>, Box<Expr>),
+    NotBetween(Box<Expr>, Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Func(String, Vec<Expr>),
+    /// Value of the first column of the first row, like `toscalar(T | count)`
+    ToScalar(Box<TabularExpression>)
 }
 
+/// Right-hand side of set operators like `in` and `has_any`
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExprList {
+    Values(Vec<Expr>),
+    Tabular(Box<TabularExpression>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Bool,
     DateTime,
@@ -97,6 +256,7 @@ pub enum Type {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Literal {
     Bool(Option<bool>),
     DateTime(Option<DateTime>),
@@ -105,11 +265,13 @@ pub enum Literal {
     Int(Option<i32>),
     Long(Option<i64>),
     Real(Option<f32>),
-    String(String),
+    /// Value and whether it is obfuscated with a `h` prefix
+    String(String, bool),
     Timespan(Option<i64>)
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum OptionLiteral {
     Bool(bool),
     Long(i64),
@@ -118,6 +280,7 @@ pub enum OptionLiteral {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DateTime {
     pub year: u32,
     pub month: u32,
@@ -129,12 +292,13 @@ pub struct DateTime {
 }
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Dynamic {
     Array(Vec<Option<Dynamic>>),
     Bool(Option<bool>),
     DateTime(Option<DateTime>),
     Decimal(Option<f64>),
-    Dictionary(HashMap<String, Option<Dynamic>>),
+    Dictionary(BTreeMap<String, Option<Dynamic>>),
     Int(Option<i32>),
     Long(Option<i64>),
     Real(Option<f32>),
@@ -142,21 +306,97 @@ pub enum Dynamic {
     Timespan(Option<i64>)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FindProjection {
     ProjectSmart,
     Project(Vec<String>)
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PatternToken {
     Wildcard,
     String(String),
     Column(String, Option<Type>),
 }
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LetExpression {
     Tabular(TabularExpression),
-    Scalar(Expr)
+    Scalar(Expr),
+    Function(Function),
+    /// Tabular expression that is computed once, like `materialize(T | summarize count() by a)`
+    Materialize(TabularExpression)
+}
+
+/// Aggregations of `make-series` over the bins of an axis
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MakeSeries {
+    /// Optionally named aggregations with the value of empty bins
+    pub aggregations: Vec<(Option<String>, Expr, Option<Expr>)>,
+    pub on: Expr,
+    pub from: Option<Expr>,
+    pub to: Option<Expr>,
+    pub step: Expr,
+    pub by: Vec<(Option<String>, Expr)>
+}
+
+/// Level of `top-nested`, like `top-nested 3 of a with others = 'x' by count() desc`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TopNested {
+    /// Number of values to keep, all values when absent
+    pub count: Option<u32>,
+    pub name: Option<String>,
+    pub expr: Expr,
+    /// Value of the bucket with all other values
+    pub others: Option<Expr>,
+    pub aggregation_name: Option<String>,
+    pub aggregation: Expr,
+    pub asc: bool
+}
+
+/// Step of `scan`, like `step s2 output=last: a > s1.a => x = a`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScanStep {
+    pub name: String,
+    pub output: ScanOutput,
+    pub condition: Expr,
+    /// Assignments of declared columns when the step matches
+    pub assignments: Vec<(String, Expr)>
+}
+
+/// Records of a `scan` step that are part of the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ScanOutput {
+    #[default]
+    All,
+    Last,
+    None
+}
+
+/// User-defined function like `let f = (a:long, T:(x:string)) { T | where x == a }`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Function {
+    pub parameters: Vec<(String, ParameterType, Option<Expr>)>,
+    /// Let statements preceding the body
+    pub statements: Vec<(String, LetExpression)>,
+    /// Resulting scalar or tabular expression
+    pub body: Box<LetExpression>,
+    pub view: bool
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ParameterType {
+    Scalar(Type),
+    /// Table with at least the given columns, or any columns for `(*)`
+    Tabular(Option<Vec<(String, Type)>>)
 }
@@ -0,0 +1,329 @@
+//! Traversal of the AST, each `visit_*` method defaults to walking into the children of the node
+
+use super::*;
+
+/// Visits the nodes of an AST by reference
+pub trait Visitor {
+    fn visit_statement(&mut self, s: &Statement) {
+        walk_statement(self, s)
+    }
+
+    fn visit_tabular_expression(&mut self, t: &TabularExpression) {
+        walk_tabular_expression(self, t)
+    }
+
+    fn visit_source(&mut self, s: &Source) {
+        walk_source(self, s)
+    }
+
+    fn visit_operator(&mut self, o: &Operator) {
+        walk_operator(self, o)
+    }
+
+    fn visit_expr(&mut self, e: &Expr) {
+        walk_expr(self, e)
+    }
+
+    fn visit_literal(&mut self, _l: &Literal) {}
+}
+
+/// Visits the nodes of an AST by mutable reference, allowing them to be rewritten in place
+pub trait VisitorMut {
+    fn visit_statement_mut(&mut self, s: &mut Statement) {
+        walk_statement_mut(self, s)
+    }
+
+    fn visit_tabular_expression_mut(&mut self, t: &mut TabularExpression) {
+        walk_tabular_expression_mut(self, t)
+    }
+
+    fn visit_source_mut(&mut self, s: &mut Source) {
+        walk_source_mut(self, s)
+    }
+
+    fn visit_operator_mut(&mut self, o: &mut Operator) {
+        walk_operator_mut(self, o)
+    }
+
+    fn visit_expr_mut(&mut self, e: &mut Expr) {
+        walk_expr_mut(self, e)
+    }
+
+    fn visit_literal_mut(&mut self, _l: &mut Literal) {}
+}
+
+pub fn walk_statement<V: Visitor + ?Sized>(v: &mut V, s: &Statement) {
+    match s {
+        Statement::TabularExpression(t) => v.visit_tabular_expression(t),
+        Statement::Let(_, e) => walk_let_expression(v, e),
+        Statement::QueryParameters(p) => p.iter().flat_map(|(_, _, d)| d).for_each(|d| v.visit_expr(d)),
+        Statement::Set(_, x) => x.iter().for_each(|e| v.visit_expr(e))
+    }
+}
+
+pub fn walk_let_expression<V: Visitor + ?Sized>(v: &mut V, e: &LetExpression) {
+    match e {
+        LetExpression::Tabular(t) | LetExpression::Materialize(t) => v.visit_tabular_expression(t),
+        LetExpression::Scalar(e) => v.visit_expr(e),
+        LetExpression::Function(f) => {
+            f.parameters.iter().flat_map(|(_, _, d)| d).for_each(|d| v.visit_expr(d));
+            f.statements.iter().for_each(|(_, e)| walk_let_expression(v, e));
+            walk_let_expression(v, &f.body);
+        }
+    }
+}
+
+pub fn walk_tabular_expression<V: Visitor + ?Sized>(v: &mut V, t: &TabularExpression) {
+    v.visit_source(&t.source);
+    for o in &t.operators {
+        v.visit_operator(o);
+    }
+}
+
+pub fn walk_source<V: Visitor + ?Sized>(v: &mut V, s: &Source) {
+    match &s.kind {
+        SourceKind::Call(_, x) | SourceKind::Datatable(_, x) => x.iter().for_each(|e| v.visit_expr(e)),
+        SourceKind::Externaldata(..) | SourceKind::Reference(..) => {},
+        SourceKind::Find(_, s, e, _) | SourceKind::Search(_, s, e) => {
+            s.iter().flatten().for_each(|s| v.visit_source(s));
+            v.visit_expr(e);
+        },
+        SourceKind::Print(x) => x.iter().for_each(|(_, e)| v.visit_expr(e)),
+        SourceKind::Range(_, f, t, s) => {
+            v.visit_expr(f);
+            v.visit_expr(t);
+            v.visit_expr(s);
+        },
+        SourceKind::Union(_, s) => s.iter().for_each(|s| v.visit_tabular_expression(s))
+    }
+}
+
+pub fn walk_operator<V: Visitor + ?Sized>(v: &mut V, o: &Operator) {
+    match &o.kind {
+        OperatorKind::As(..) | OperatorKind::Consume(_) | OperatorKind::Count |
+        OperatorKind::Getschema | OperatorKind::ProjectAway(_) | OperatorKind::ProjectKeep(_) |
+        OperatorKind::ProjectRename(_) | OperatorKind::ProjectReorder(_) | OperatorKind::Render(..) |
+        OperatorKind::Sample(_) | OperatorKind::SampleDistinct(..) | OperatorKind::Take(_) => {},
+        OperatorKind::Evaluate(_, _, x) | OperatorKind::Invoke(_, x) => x.iter().for_each(|e| v.visit_expr(e)),
+        OperatorKind::Extend(x) | OperatorKind::Project(x) | OperatorKind::Serialize(x) => x.iter().for_each(|(_, e)| v.visit_expr(e)),
+        OperatorKind::Distinct(x) => x.iter().flatten().for_each(|(_, e)| v.visit_expr(e)),
+        OperatorKind::Facet(_, o) | OperatorKind::MvApply(_, o) => o.iter().for_each(|o| v.visit_operator(o)),
+        OperatorKind::Fork(f) => f.iter().flat_map(|(_, o)| o).for_each(|o| v.visit_operator(o)),
+        OperatorKind::Join(_, t, _) | OperatorKind::Lookup(_, t, _) => v.visit_tabular_expression(t),
+        OperatorKind::Parse(_, e, _) | OperatorKind::ParseWhere(_, e, _) | OperatorKind::ParseKV(e, _, _) |
+        OperatorKind::Reduce(_, e, _) | OperatorKind::Search(_, e) | OperatorKind::Top(_, e, _, _) | OperatorKind::Where(e) => v.visit_expr(e),
+        OperatorKind::Sort(x) => x.iter().for_each(|(e, _, _)| v.visit_expr(e)),
+        OperatorKind::MakeSeries(_, m) => {
+            m.aggregations.iter().for_each(|(_, e, d)| {
+                v.visit_expr(e);
+                d.iter().for_each(|d| v.visit_expr(d));
+            });
+            v.visit_expr(&m.on);
+            m.from.iter().chain(m.to.iter()).for_each(|e| v.visit_expr(e));
+            v.visit_expr(&m.step);
+            m.by.iter().for_each(|(_, e)| v.visit_expr(e));
+        },
+        OperatorKind::MvExpand(_, x, _) => x.iter().for_each(|(_, e, _)| v.visit_expr(e)),
+        OperatorKind::Scan(_, d, s) => {
+            d.iter().flat_map(|(_, _, e)| e).for_each(|e| v.visit_expr(e));
+            s.iter().for_each(|s| {
+                v.visit_expr(&s.condition);
+                s.assignments.iter().for_each(|(_, e)| v.visit_expr(e));
+            });
+        },
+        OperatorKind::TopHitters(_, (_, e), b) => {
+            v.visit_expr(e);
+            b.iter().for_each(|b| v.visit_expr(b));
+        },
+        OperatorKind::TopNested(l) => l.iter().for_each(|l| {
+            v.visit_expr(&l.expr);
+            l.others.iter().for_each(|o| v.visit_expr(o));
+            v.visit_expr(&l.aggregation);
+        }),
+        OperatorKind::Partition(_, _, s, o) => {
+            s.iter().for_each(|s| v.visit_source(s));
+            o.iter().for_each(|o| v.visit_operator(o));
+        },
+        OperatorKind::Summarize(x, by) => {
+            x.iter().for_each(|(_, e)| v.visit_expr(e));
+            by.iter().for_each(|e| v.visit_expr(e));
+        },
+        OperatorKind::Union(_, s) => s.iter().for_each(|s| v.visit_tabular_expression(s))
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, e: &Expr) {
+    match &e.kind {
+        ExprKind::Ident(_) => {},
+        ExprKind::Literal(l) => v.visit_literal(l),
+        ExprKind::Index(l, r) |
+        ExprKind::Equals(l, r) | ExprKind::NotEquals(l, r) | ExprKind::And(l, r) | ExprKind::Or(l, r) |
+        ExprKind::Add(l, r) | ExprKind::Substract(l, r) | ExprKind::Multiply(l, r) | ExprKind::Divide(l, r) | ExprKind::Modulo(l, r) |
+        ExprKind::Less(l, r) | ExprKind::Greater(l, r) | ExprKind::LessOrEqual(l, r) | ExprKind::GreaterOrEqual(l, r) |
+        ExprKind::EqualsCaseInsensitive(l, r) | ExprKind::NotEqualsCaseInsensitive(l, r) |
+        ExprKind::Contains(l, r) | ExprKind::NotContains(l, r) | ExprKind::ContainsCs(l, r) | ExprKind::NotContainsCs(l, r) |
+        ExprKind::Has(l, r) | ExprKind::NotHas(l, r) | ExprKind::HasCs(l, r) | ExprKind::NotHasCs(l, r) |
+        ExprKind::HasPrefix(l, r) | ExprKind::NotHasPrefix(l, r) | ExprKind::HasPrefixCs(l, r) | ExprKind::NotHasPrefixCs(l, r) |
+        ExprKind::HasSuffix(l, r) | ExprKind::NotHasSuffix(l, r) | ExprKind::HasSuffixCs(l, r) | ExprKind::NotHasSuffixCs(l, r) |
+        ExprKind::StartsWith(l, r) | ExprKind::NotStartsWith(l, r) | ExprKind::StartsWithCs(l, r) | ExprKind::NotStartsWithCs(l, r) |
+        ExprKind::EndsWith(l, r) | ExprKind::NotEndsWith(l, r) | ExprKind::EndsWithCs(l, r) | ExprKind::NotEndsWithCs(l, r) |
+        ExprKind::MatchesRegex(l, r) => {
+            v.visit_expr(l);
+            v.visit_expr(r);
+        },
+        ExprKind::In(x, l) | ExprKind::NotIn(x, l) | ExprKind::InCaseInsensitive(x, l) |
+        ExprKind::NotInCaseInsensitive(x, l) | ExprKind::HasAny(x, l) | ExprKind::HasAll(x, l) => {
+            v.visit_expr(x);
+            match l {
+                ExprList::Values(x) => x.iter().for_each(|e| v.visit_expr(e)),
+                ExprList::Tabular(t) => v.visit_tabular_expression(t)
+            }
+        },
+        ExprKind::Between(x, l, h) | ExprKind::NotBetween(x, l, h) => {
+            v.visit_expr(x);
+            v.visit_expr(l);
+            v.visit_expr(h);
+        },
+        ExprKind::Not(x) | ExprKind::Negate(x) => v.visit_expr(x),
+        ExprKind::Func(_, x) => x.iter().for_each(|e| v.visit_expr(e)),
+        ExprKind::ToScalar(q) => v.visit_tabular_expression(q)
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(v: &mut V, s: &mut Statement) {
+    match s {
+        Statement::TabularExpression(t) => v.visit_tabular_expression_mut(t),
+        Statement::Let(_, e) => walk_let_expression_mut(v, e),
+        Statement::QueryParameters(p) => p.iter_mut().flat_map(|(_, _, d)| d).for_each(|d| v.visit_expr_mut(d)),
+        Statement::Set(_, x) => x.iter_mut().for_each(|e| v.visit_expr_mut(e))
+    }
+}
+
+pub fn walk_let_expression_mut<V: VisitorMut + ?Sized>(v: &mut V, e: &mut LetExpression) {
+    match e {
+        LetExpression::Tabular(t) | LetExpression::Materialize(t) => v.visit_tabular_expression_mut(t),
+        LetExpression::Scalar(e) => v.visit_expr_mut(e),
+        LetExpression::Function(f) => {
+            f.parameters.iter_mut().flat_map(|(_, _, d)| d).for_each(|d| v.visit_expr_mut(d));
+            f.statements.iter_mut().for_each(|(_, e)| walk_let_expression_mut(v, e));
+            walk_let_expression_mut(v, &mut f.body);
+        }
+    }
+}
+
+pub fn walk_tabular_expression_mut<V: VisitorMut + ?Sized>(v: &mut V, t: &mut TabularExpression) {
+    v.visit_source_mut(&mut t.source);
+    for o in &mut t.operators {
+        v.visit_operator_mut(o);
+    }
+}
+
+pub fn walk_source_mut<V: VisitorMut + ?Sized>(v: &mut V, s: &mut Source) {
+    match &mut s.kind {
+        SourceKind::Call(_, x) | SourceKind::Datatable(_, x) => x.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+        SourceKind::Externaldata(..) | SourceKind::Reference(..) => {},
+        SourceKind::Find(_, s, e, _) | SourceKind::Search(_, s, e) => {
+            s.iter_mut().flatten().for_each(|s| v.visit_source_mut(s));
+            v.visit_expr_mut(e);
+        },
+        SourceKind::Print(x) => x.iter_mut().for_each(|(_, e)| v.visit_expr_mut(e)),
+        SourceKind::Range(_, f, t, s) => {
+            v.visit_expr_mut(f);
+            v.visit_expr_mut(t);
+            v.visit_expr_mut(s);
+        },
+        SourceKind::Union(_, s) => s.iter_mut().for_each(|s| v.visit_tabular_expression_mut(s))
+    }
+}
+
+pub fn walk_operator_mut<V: VisitorMut + ?Sized>(v: &mut V, o: &mut Operator) {
+    match &mut o.kind {
+        OperatorKind::As(..) | OperatorKind::Consume(_) | OperatorKind::Count |
+        OperatorKind::Getschema | OperatorKind::ProjectAway(_) | OperatorKind::ProjectKeep(_) |
+        OperatorKind::ProjectRename(_) | OperatorKind::ProjectReorder(_) | OperatorKind::Render(..) |
+        OperatorKind::Sample(_) | OperatorKind::SampleDistinct(..) | OperatorKind::Take(_) => {},
+        OperatorKind::Evaluate(_, _, x) | OperatorKind::Invoke(_, x) => x.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+        OperatorKind::Extend(x) | OperatorKind::Project(x) | OperatorKind::Serialize(x) => x.iter_mut().for_each(|(_, e)| v.visit_expr_mut(e)),
+        OperatorKind::Distinct(x) => x.iter_mut().flatten().for_each(|(_, e)| v.visit_expr_mut(e)),
+        OperatorKind::Facet(_, o) | OperatorKind::MvApply(_, o) => o.iter_mut().for_each(|o| v.visit_operator_mut(o)),
+        OperatorKind::Fork(f) => f.iter_mut().flat_map(|(_, o)| o).for_each(|o| v.visit_operator_mut(o)),
+        OperatorKind::Join(_, t, _) | OperatorKind::Lookup(_, t, _) => v.visit_tabular_expression_mut(t),
+        OperatorKind::Parse(_, e, _) | OperatorKind::ParseWhere(_, e, _) | OperatorKind::ParseKV(e, _, _) |
+        OperatorKind::Reduce(_, e, _) | OperatorKind::Search(_, e) | OperatorKind::Top(_, e, _, _) | OperatorKind::Where(e) => v.visit_expr_mut(e),
+        OperatorKind::Sort(x) => x.iter_mut().for_each(|(e, _, _)| v.visit_expr_mut(e)),
+        OperatorKind::MakeSeries(_, m) => {
+            m.aggregations.iter_mut().for_each(|(_, e, d)| {
+                v.visit_expr_mut(e);
+                d.iter_mut().for_each(|d| v.visit_expr_mut(d));
+            });
+            v.visit_expr_mut(&mut m.on);
+            m.from.iter_mut().chain(m.to.iter_mut()).for_each(|e| v.visit_expr_mut(e));
+            v.visit_expr_mut(&mut m.step);
+            m.by.iter_mut().for_each(|(_, e)| v.visit_expr_mut(e));
+        },
+        OperatorKind::MvExpand(_, x, _) => x.iter_mut().for_each(|(_, e, _)| v.visit_expr_mut(e)),
+        OperatorKind::Scan(_, d, s) => {
+            d.iter_mut().flat_map(|(_, _, e)| e).for_each(|e| v.visit_expr_mut(e));
+            s.iter_mut().for_each(|s| {
+                v.visit_expr_mut(&mut s.condition);
+                s.assignments.iter_mut().for_each(|(_, e)| v.visit_expr_mut(e));
+            });
+        },
+        OperatorKind::TopHitters(_, (_, e), b) => {
+            v.visit_expr_mut(e);
+            b.iter_mut().for_each(|b| v.visit_expr_mut(b));
+        },
+        OperatorKind::TopNested(l) => l.iter_mut().for_each(|l| {
+            v.visit_expr_mut(&mut l.expr);
+            l.others.iter_mut().for_each(|o| v.visit_expr_mut(o));
+            v.visit_expr_mut(&mut l.aggregation);
+        }),
+        OperatorKind::Partition(_, _, s, o) => {
+            s.iter_mut().for_each(|s| v.visit_source_mut(s));
+            o.iter_mut().for_each(|o| v.visit_operator_mut(o));
+        },
+        OperatorKind::Summarize(x, by) => {
+            x.iter_mut().for_each(|(_, e)| v.visit_expr_mut(e));
+            by.iter_mut().for_each(|e| v.visit_expr_mut(e));
+        },
+        OperatorKind::Union(_, s) => s.iter_mut().for_each(|s| v.visit_tabular_expression_mut(s))
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, e: &mut Expr) {
+    match &mut e.kind {
+        ExprKind::Ident(_) => {},
+        ExprKind::Literal(l) => v.visit_literal_mut(l),
+        ExprKind::Index(l, r) |
+        ExprKind::Equals(l, r) | ExprKind::NotEquals(l, r) | ExprKind::And(l, r) | ExprKind::Or(l, r) |
+        ExprKind::Add(l, r) | ExprKind::Substract(l, r) | ExprKind::Multiply(l, r) | ExprKind::Divide(l, r) | ExprKind::Modulo(l, r) |
+        ExprKind::Less(l, r) | ExprKind::Greater(l, r) | ExprKind::LessOrEqual(l, r) | ExprKind::GreaterOrEqual(l, r) |
+        ExprKind::EqualsCaseInsensitive(l, r) | ExprKind::NotEqualsCaseInsensitive(l, r) |
+        ExprKind::Contains(l, r) | ExprKind::NotContains(l, r) | ExprKind::ContainsCs(l, r) | ExprKind::NotContainsCs(l, r) |
+        ExprKind::Has(l, r) | ExprKind::NotHas(l, r) | ExprKind::HasCs(l, r) | ExprKind::NotHasCs(l, r) |
+        ExprKind::HasPrefix(l, r) | ExprKind::NotHasPrefix(l, r) | ExprKind::HasPrefixCs(l, r) | ExprKind::NotHasPrefixCs(l, r) |
+        ExprKind::HasSuffix(l, r) | ExprKind::NotHasSuffix(l, r) | ExprKind::HasSuffixCs(l, r) | ExprKind::NotHasSuffixCs(l, r) |
+        ExprKind::StartsWith(l, r) | ExprKind::NotStartsWith(l, r) | ExprKind::StartsWithCs(l, r) | ExprKind::NotStartsWithCs(l, r) |
+        ExprKind::EndsWith(l, r) | ExprKind::NotEndsWith(l, r) | ExprKind::EndsWithCs(l, r) | ExprKind::NotEndsWithCs(l, r) |
+        ExprKind::MatchesRegex(l, r) => {
+            v.visit_expr_mut(l);
+            v.visit_expr_mut(r);
+        },
+        ExprKind::In(x, l) | ExprKind::NotIn(x, l) | ExprKind::InCaseInsensitive(x, l) |
+        ExprKind::NotInCaseInsensitive(x, l) | ExprKind::HasAny(x, l) | ExprKind::HasAll(x, l) => {
+            v.visit_expr_mut(x);
+            match l {
+                ExprList::Values(x) => x.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+                ExprList::Tabular(t) => v.visit_tabular_expression_mut(t)
+            }
+        },
+        ExprKind::Between(x, l, h) | ExprKind::NotBetween(x, l, h) => {
+            v.visit_expr_mut(x);
+            v.visit_expr_mut(l);
+            v.visit_expr_mut(h);
+        },
+        ExprKind::Not(x) | ExprKind::Negate(x) => v.visit_expr_mut(x),
+        ExprKind::Func(_, x) => x.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+        ExprKind::ToScalar(q) => v.visit_tabular_expression_mut(q)
+    }
+}
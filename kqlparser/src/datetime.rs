@@ -1,11 +1,10 @@
 use nom::branch::alt;
-use nom::bytes::complete::tag;
 use nom::character::complete::{u32, alpha1, digit1, multispace0, multispace1, one_of};
 use nom::combinator::{map, map_res, opt, value};
 use nom::sequence::{pair, preceded, terminated, tuple};
-use nom::IResult;
 
 use crate::ast::DateTime;
+use crate::{tag, Input, IResult};
 
 struct ParsedDate {
     year: u32,
@@ -19,13 +18,13 @@ struct ParsedTime {
     second: u32
 }
 
-pub fn iso8601_datetime(input: &str) -> IResult<&str, DateTime> {
-    let (input, (date, _, time, timezone)) = tuple((
+pub fn iso8601_datetime(input: Input) -> IResult<Input, DateTime> {
+    let (input, (date, time, timezone)) = tuple((
         iso8601_date,
-        alt((multispace1, tag("T"))),
-        iso8601_time,
+        opt(preceded(alt((multispace1, tag("T"))), iso8601_time)),
         opt(preceded(multispace0, iso8601_timezone)),
     ))(input)?;
+    let time = time.unwrap_or(ParsedTime { hour: 0, minute: 0, second: 0 });
 
     Ok((
         input,
@@ -41,7 +40,7 @@ pub fn iso8601_datetime(input: &str) -> IResult<&str, DateTime> {
     ))
 }
 
-fn iso8601_date(input: &str) -> IResult<&str, ParsedDate> {
+fn iso8601_date(input: Input) -> IResult<Input, ParsedDate> {
     let (input, (year, _, month, _, day)) = tuple((
         u32,
         tag("-"),
@@ -60,7 +59,7 @@ fn iso8601_date(input: &str) -> IResult<&str, ParsedDate> {
     ))
 }
 
-fn iso8601_time(input: &str) -> IResult<&str, ParsedTime> {
+fn iso8601_time(input: Input) -> IResult<Input, ParsedTime> {
     map(tuple((
         u32,
         preceded(tag(":"), u32),
@@ -72,18 +71,18 @@ fn iso8601_time(input: &str) -> IResult<&str, ParsedTime> {
     })(input)
 }
 
-fn iso8601_timezone(input: &str) -> IResult<&str, String> {
+fn iso8601_timezone(input: Input) -> IResult<Input, String> {
     alt((
         map(pair(one_of("+-"), digit1), |(sign, value)| -> String {
             format!("{}{}", sign, value)
         }),
-        map_res(pair(opt(one_of("+-")), digit1), |(sign, value): (Option<char>, &str)| -> Result<String, nom::error::Error<&str>> {
+        map_res(pair(opt(one_of("+-")), digit1), |(sign, value): (Option<char>, Input)| -> Result<String, ()> {
             Ok(format!("{}{}", sign.unwrap_or('+'), value))
         }),
     ))(input)
 }
 
-fn rfc822_date(input: &str) -> IResult<&str, ParsedDate> {
+fn rfc822_date(input: Input) -> IResult<Input, ParsedDate> {
     map(tuple((
         u32,
         multispace1,
@@ -97,7 +96,7 @@ fn rfc822_date(input: &str) -> IResult<&str, ParsedDate> {
     })(input)
 }
 
-pub fn rfc822_datetime(input: &str) -> IResult<&str, DateTime> {
+pub fn rfc822_datetime(input: Input) -> IResult<Input, DateTime> {
     map(tuple((
         opt(terminated(alpha1, tag(","))), // Optional day name
         multispace0,
@@ -117,7 +116,7 @@ pub fn rfc822_datetime(input: &str) -> IResult<&str, DateTime> {
     })(input)
 }
 
-fn rfc822_timezone(input: &str) -> IResult<&str, String> {
+fn rfc822_timezone(input: Input) -> IResult<Input, String> {
     alt((
         map(pair(one_of("+-"), digit1), |(sign, value)| {
             format!("{}{}", sign, value)
@@ -128,7 +127,7 @@ fn rfc822_timezone(input: &str) -> IResult<&str, String> {
     ))(input)
 }
 
-pub fn rfc850_datetime(input: &str) -> IResult<&str, DateTime> {
+pub fn rfc850_datetime(input: Input) -> IResult<Input, DateTime> {
     let (input, (_, _, date, _, time, _, timezone)) = tuple((
         opt(terminated(alpha1, tag(","))), // Optional day name
         multispace0,
@@ -153,7 +152,7 @@ pub fn rfc850_datetime(input: &str) -> IResult<&str, DateTime> {
     ))
 }
 
-fn rfc850_date(input: &str) -> IResult<&str, ParsedDate> {
+fn rfc850_date(input: Input) -> IResult<Input, ParsedDate> {
     map(tuple((
         u32,
         tag("-"),
@@ -167,7 +166,7 @@ fn rfc850_date(input: &str) -> IResult<&str, ParsedDate> {
     })(input)
 }
 
-fn rfc850_timezone(input: &str) -> IResult<&str, String> {
+fn rfc850_timezone(input: Input) -> IResult<Input, String> {
     alt((
         map(pair(one_of("+-"), digit1), |(sign, value)| {
             format!("{}{}", sign, value)
@@ -178,7 +177,7 @@ fn rfc850_timezone(input: &str) -> IResult<&str, String> {
     ))(input)
 }
 
-fn time(input: &str) -> IResult<&str, ParsedTime> {
+fn time(input: Input) -> IResult<Input, ParsedTime> {
     map(tuple((
         u32,
         preceded(tag(":"), u32),
@@ -190,7 +189,7 @@ fn time(input: &str) -> IResult<&str, ParsedTime> {
     })(input)
 }
 
-fn month(input: &str) -> IResult<&str, u32> {
+fn month(input: Input) -> IResult<Input, u32> {
     alt((
         alt((
             value(1, tag("Jan")),
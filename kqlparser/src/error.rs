@@ -0,0 +1,108 @@
+use std::fmt;
+
+use nom::error::{ErrorKind, FromExternalError};
+use nom::InputLength;
+
+use crate::Input;
+
+/// Error returned when a KQL query can't be parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    /// Byte offset of the failing token in the query
+    pub offset: usize,
+    /// Line of the failing token, starting at 1
+    pub line: u32,
+    /// Column of the failing token in characters, starting at 1
+    pub column: usize,
+    /// Tokens that would have been accepted at the failing position
+    pub expected: Vec<String>,
+    /// Human-readable description of the error
+    pub message: String
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Error type used by the nom parsers, keeps track of the furthest position reached
+#[derive(Debug, PartialEq)]
+pub struct Error<I> {
+    pub input: I,
+    pub expected: Vec<String>
+}
+
+impl<I> Error<I> {
+    pub fn expected(input: I, token: impl Into<String>) -> Self {
+        Error { input, expected: vec![token.into()] }
+    }
+}
+
+impl<I: InputLength> nom::error::ParseError<I> for Error<I> {
+    fn from_error_kind(input: I, _kind: ErrorKind) -> Self {
+        Error { input, expected: Vec::new() }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: I, c: char) -> Self {
+        Error::expected(input, c)
+    }
+
+    fn or(mut self, other: Self) -> Self {
+        // Report the alternative which got furthest, merge expectations of alternatives failing at the same position
+        match self.input.input_len().cmp(&other.input.input_len()) {
+            std::cmp::Ordering::Less => self,
+            std::cmp::Ordering::Greater => other,
+            std::cmp::Ordering::Equal => {
+                for e in other.expected {
+                    if !self.expected.contains(&e) {
+                        self.expected.push(e);
+                    }
+                }
+                self
+            }
+        }
+    }
+}
+
+impl<I, E> FromExternalError<I, E> for Error<I> {
+    fn from_external_error(input: I, _kind: ErrorKind, _e: E) -> Self {
+        Error { input, expected: Vec::new() }
+    }
+}
+
+impl<'a> From<Error<Input<'a>>> for ParseError {
+    fn from(e: Error<Input<'a>>) -> Self {
+        let found = next_token(e.input.fragment());
+        let message = match e.expected.as_slice() {
+            [] => format!("unexpected {}", found),
+            [x] => format!("expected `{}`, found {}", x, found),
+            x => format!("expected one of {}, found {}", x.iter().map(|t| format!("`{}`", t)).collect::<Vec<_>>().join(", "), found)
+        };
+
+        ParseError {
+            offset: e.input.location_offset(),
+            line: e.input.location_line(),
+            column: e.input.get_utf8_column(),
+            expected: e.expected,
+            message
+        }
+    }
+}
+
+fn next_token(i: &str) -> String {
+    let token = match i.split_whitespace().next() {
+        Some(token) => token,
+        None => return "end of input".to_string()
+    };
+    match token.char_indices().nth(20) {
+        Some((n, _)) => format!("`{}...`", &token[..n]),
+        None => format!("`{}`", token)
+    }
+}
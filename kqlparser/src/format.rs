@@ -0,0 +1,1046 @@
+//! Formats an AST back into KQL text, which parses to an equal AST again
+
+use std::fmt::{self, Display, Write};
+
+use crate::ast::*;
+use crate::{is_kql_identifier, is_kql_wildcard_identifier, KEYWORDS};
+
+/// Options controlling the layout of formatted queries
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Put every operator of a top level tabular expression on its own line
+    pub operator_per_line: bool
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions { operator_per_line: true }
+    }
+}
+
+/// Formats a list of statements as a single query
+pub fn format(statements: &[Statement], options: &FormatOptions) -> String {
+    let mut out = String::new();
+    for (n, s) in statements.iter().enumerate() {
+        if n > 0 {
+            out.push_str(if options.operator_per_line { ";\n" } else { "; " });
+        }
+        write_statement(&mut out, s, options.operator_per_line).expect("writing to a String can't fail");
+    }
+    out
+}
+
+// Precedence of expressions, matching the levels used by the parser
+const PRECEDENCE_OR: u8 = 1;
+const PRECEDENCE_AND: u8 = 2;
+const PRECEDENCE_PREDICATE: u8 = 3;
+const PRECEDENCE_ADDITIVE: u8 = 4;
+const PRECEDENCE_MULTIPLICATIVE: u8 = 5;
+const PRECEDENCE_UNARY: u8 = 6;
+const PRECEDENCE_ATOM: u8 = 7;
+
+// Names which start a typed literal when followed by parentheses
+const LITERAL_FUNCTIONS: &[&str] = &["bool", "datetime", "decimal", "dynamic", "int", "long", "not", "real", "time", "timespan", "toscalar"];
+
+fn binary_operator(kind: &ExprKind) -> Option<(&'static str, u8, &Expr, &Expr)> {
+    let (op, precedence, l, r) = match kind {
+        ExprKind::Or(l, r) => ("or", PRECEDENCE_OR, l, r),
+        ExprKind::And(l, r) => ("and", PRECEDENCE_AND, l, r),
+        ExprKind::Equals(l, r) => ("==", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotEquals(l, r) => ("!=", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::Less(l, r) => ("<", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::Greater(l, r) => (">", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::LessOrEqual(l, r) => ("<=", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::GreaterOrEqual(l, r) => (">=", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::EqualsCaseInsensitive(l, r) => ("=~", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotEqualsCaseInsensitive(l, r) => ("!~", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::Contains(l, r) => ("contains", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotContains(l, r) => ("!contains", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::ContainsCs(l, r) => ("contains_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotContainsCs(l, r) => ("!contains_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::Has(l, r) => ("has", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotHas(l, r) => ("!has", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::HasCs(l, r) => ("has_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotHasCs(l, r) => ("!has_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::HasPrefix(l, r) => ("hasprefix", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotHasPrefix(l, r) => ("!hasprefix", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::HasPrefixCs(l, r) => ("hasprefix_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotHasPrefixCs(l, r) => ("!hasprefix_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::HasSuffix(l, r) => ("hassuffix", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotHasSuffix(l, r) => ("!hassuffix", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::HasSuffixCs(l, r) => ("hassuffix_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotHasSuffixCs(l, r) => ("!hassuffix_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::StartsWith(l, r) => ("startswith", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotStartsWith(l, r) => ("!startswith", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::StartsWithCs(l, r) => ("startswith_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotStartsWithCs(l, r) => ("!startswith_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::EndsWith(l, r) => ("endswith", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotEndsWith(l, r) => ("!endswith", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::EndsWithCs(l, r) => ("endswith_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::NotEndsWithCs(l, r) => ("!endswith_cs", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::MatchesRegex(l, r) => ("matches regex", PRECEDENCE_PREDICATE, l, r),
+        ExprKind::Add(l, r) => ("+", PRECEDENCE_ADDITIVE, l, r),
+        ExprKind::Substract(l, r) => ("-", PRECEDENCE_ADDITIVE, l, r),
+        ExprKind::Multiply(l, r) => ("*", PRECEDENCE_MULTIPLICATIVE, l, r),
+        ExprKind::Divide(l, r) => ("/", PRECEDENCE_MULTIPLICATIVE, l, r),
+        ExprKind::Modulo(l, r) => ("%", PRECEDENCE_MULTIPLICATIVE, l, r),
+        _ => return None
+    };
+    Some((op, precedence, l, r))
+}
+
+fn list_operator(kind: &ExprKind) -> Option<(&'static str, &Expr, &ExprList)> {
+    Some(match kind {
+        ExprKind::In(l, r) => ("in", l, r),
+        ExprKind::NotIn(l, r) => ("!in", l, r),
+        ExprKind::InCaseInsensitive(l, r) => ("in~", l, r),
+        ExprKind::NotInCaseInsensitive(l, r) => ("!in~", l, r),
+        ExprKind::HasAny(l, r) => ("has_any", l, r),
+        ExprKind::HasAll(l, r) => ("has_all", l, r),
+        _ => return None
+    })
+}
+
+fn precedence(e: &Expr) -> u8 {
+    match &e.kind {
+        ExprKind::In(..) | ExprKind::NotIn(..) | ExprKind::InCaseInsensitive(..) | ExprKind::NotInCaseInsensitive(..) |
+        ExprKind::HasAny(..) | ExprKind::HasAll(..) | ExprKind::Between(..) | ExprKind::NotBetween(..) => PRECEDENCE_PREDICATE,
+        ExprKind::Negate(_) => PRECEDENCE_UNARY,
+        k => binary_operator(k).map_or(PRECEDENCE_ATOM, |(_, p, _, _)| p)
+    }
+}
+
+/// Writes an expression, adding parentheses when it binds looser than `min`
+fn write_operand(out: &mut impl Write, e: &Expr, min: u8) -> fmt::Result {
+    if precedence(e) < min {
+        write!(out, "(")?;
+        write_expr(out, e)?;
+        write!(out, ")")
+    } else {
+        write_expr(out, e)
+    }
+}
+
+fn write_expr(out: &mut impl Write, e: &Expr) -> fmt::Result {
+    if let Some((op, precedence, l, r)) = binary_operator(&e.kind) {
+        write_operand(out, l, precedence)?;
+        write!(out, " {} ", op)?;
+        return write_operand(out, r, precedence + 1);
+    }
+    if let Some((op, l, r)) = list_operator(&e.kind) {
+        write_operand(out, l, PRECEDENCE_PREDICATE)?;
+        write!(out, " {} (", op)?;
+        match r {
+            ExprList::Values(v) => write_list(out, v, write_expr)?,
+            ExprList::Tabular(q) => write_query(out, q, false)?
+        }
+        return write!(out, ")");
+    }
+
+    match &e.kind {
+        ExprKind::Ident(i) => write_identifier(out, i),
+        ExprKind::Literal(l) => write_literal(out, l),
+        ExprKind::Func(n, a) => {
+            match LITERAL_FUNCTIONS.contains(&n.as_str()) {
+                true => write_quoted_identifier(out, n)?,
+                false => write_identifier(out, n)?
+            }
+            write!(out, "(")?;
+            write_list(out, a, write_expr)?;
+            write!(out, ")")
+        },
+        ExprKind::ToScalar(q) => {
+            write!(out, "toscalar(")?;
+            write_query(out, q, false)?;
+            write!(out, ")")
+        },
+        ExprKind::Index(x, i) => {
+            // Only identifiers, calls and other indexes can be indexed without parentheses
+            match &x.kind {
+                ExprKind::Ident(_) | ExprKind::Func(..) | ExprKind::ToScalar(_) | ExprKind::Index(..) => write_expr(out, x)?,
+                _ => {
+                    write!(out, "(")?;
+                    write_expr(out, x)?;
+                    write!(out, ")")?;
+                }
+            }
+            match &i.kind {
+                ExprKind::Ident(n) if is_plain_identifier(n) => write!(out, ".{}", n),
+                _ => {
+                    write!(out, "[")?;
+                    write_expr(out, i)?;
+                    write!(out, "]")
+                }
+            }
+        },
+        ExprKind::Between(x, l, h) | ExprKind::NotBetween(x, l, h) => {
+            let op = if matches!(e.kind, ExprKind::Between(..)) { "between" } else { "!between" };
+            write_operand(out, x, PRECEDENCE_PREDICATE)?;
+            write!(out, " {} (", op)?;
+            write_operand(out, l, PRECEDENCE_ADDITIVE)?;
+            write!(out, " .. ")?;
+            write_operand(out, h, PRECEDENCE_ADDITIVE)?;
+            write!(out, ")")
+        },
+        ExprKind::Not(x) => {
+            write!(out, "not(")?;
+            write_expr(out, x)?;
+            write!(out, ")")
+        },
+        ExprKind::Negate(x) => {
+            write!(out, "-")?;
+            match &x.kind {
+                // A negated number would be read back as a negative literal
+                ExprKind::Literal(_) => {
+                    write!(out, "(")?;
+                    write_expr(out, x)?;
+                    write!(out, ")")
+                },
+                _ => write_operand(out, x, PRECEDENCE_UNARY)
+            }
+        },
+        _ => unreachable!("binary and list operators are handled above")
+    }
+}
+
+fn write_list<T, W: Write>(out: &mut W, items: &[T], f: impl Fn(&mut W, &T) -> fmt::Result) -> fmt::Result {
+    for (n, item) in items.iter().enumerate() {
+        if n > 0 {
+            write!(out, ", ")?;
+        }
+        f(out, item)?;
+    }
+    Ok(())
+}
+
+fn is_plain_identifier(i: &str) -> bool {
+    let i = i.strip_prefix('$').unwrap_or(i);
+    i.chars().all(is_kql_identifier)
+        && i.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && !KEYWORDS.contains(&i)
+        && i != "true" && i != "false"
+}
+
+fn write_identifier(out: &mut impl Write, i: &str) -> fmt::Result {
+    match is_plain_identifier(i) {
+        true => write!(out, "{}", i),
+        false => write_quoted_identifier(out, i)
+    }
+}
+
+fn write_quoted_identifier(out: &mut impl Write, i: &str) -> fmt::Result {
+    write!(out, "[")?;
+    write_string(out, i)?;
+    write!(out, "]")
+}
+
+fn write_wildcard_identifier(out: &mut impl Write, i: &str) -> fmt::Result {
+    match !i.is_empty() && i.chars().all(is_kql_wildcard_identifier) && !KEYWORDS.contains(&i) {
+        true => write!(out, "{}", i),
+        false => write_quoted_identifier(out, i)
+    }
+}
+
+fn write_named_exprs(out: &mut impl Write, exprs: &[(Option<String>, Expr)]) -> fmt::Result {
+    write_list(out, exprs, |out, (n, e)| {
+        if let Some(n) = n {
+            write_identifier(out, n)?;
+            write!(out, " = ")?;
+        }
+        write_expr(out, e)
+    })
+}
+
+fn write_string(out: &mut impl Write, s: &str) -> fmt::Result {
+    write!(out, "\"")?;
+    for c in s.chars() {
+        match c {
+            '\\' => write!(out, "\\\\")?,
+            '"' => write!(out, "\\\"")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            c => write!(out, "{}", c)?
+        }
+    }
+    write!(out, "\"")
+}
+
+fn write_type(out: &mut impl Write, t: &Type) -> fmt::Result {
+    write!(out, "{}", match t {
+        Type::Bool => "bool",
+        Type::DateTime => "datetime",
+        Type::Decimal => "decimal",
+        Type::Dynamic => "dynamic",
+        Type::Int => "int",
+        Type::Long => "long",
+        Type::Real => "real",
+        Type::String => "string",
+        Type::Timespan => "timespan"
+    })
+}
+
+fn write_type_mapping(out: &mut impl Write, types: &[(String, Type)]) -> fmt::Result {
+    write!(out, "(")?;
+    write_list(out, types, |out, (n, t)| {
+        write_identifier(out, n)?;
+        write!(out, ":")?;
+        write_type(out, t)
+    })?;
+    write!(out, ")")
+}
+
+fn write_option<T: Display>(out: &mut impl Write, prefix: &str, v: &Option<T>) -> fmt::Result {
+    match v {
+        Some(v) => write!(out, "{}({})", prefix, v),
+        None => write!(out, "{}(null)", prefix)
+    }
+}
+
+fn write_real<T: fmt::Debug + Into<f64> + Copy>(out: &mut impl Write, prefix: &str, v: &Option<T>) -> fmt::Result {
+    match v.map(Into::into) {
+        Some(x) if x.is_nan() => write!(out, "{}(nan)", prefix),
+        Some(x) if x.is_infinite() => write!(out, "{}({}inf)", prefix, if x > 0.0 { "+" } else { "-" }),
+        Some(_) => write!(out, "{}({:?})", prefix, v.unwrap()),
+        None => write!(out, "{}(null)", prefix)
+    }
+}
+
+fn write_timespan(out: &mut impl Write, t: i64) -> fmt::Result {
+    const UNITS: [(i64, &str); 7] = [
+        (1000 * 1000 * 1000 * 60 * 60 * 24, "d"),
+        (1000 * 1000 * 1000 * 60 * 60, "h"),
+        (1000 * 1000 * 1000 * 60, "m"),
+        (1000 * 1000 * 1000, "s"),
+        (1000 * 1000, "ms"),
+        (1000, "micro"),
+        (100, "tick"),
+    ];
+    if t == 0 {
+        return write!(out, "0s");
+    }
+    match UNITS.iter().find(|(n, _)| t % n == 0) {
+        Some((n, unit)) => write!(out, "{}{}", t / n, unit),
+        None => write!(out, "{}{}.{:09}s", if t < 0 { "-" } else { "" }, (t / 1_000_000_000).abs(), (t % 1_000_000_000).abs())
+    }
+}
+
+fn write_datetime(out: &mut impl Write, d: &Option<DateTime>) -> fmt::Result {
+    match d {
+        Some(d) => write!(
+            out,
+            "datetime({:04}-{:02}-{:02} {:02}:{:02}:{:02}{})",
+            d.year, d.month, d.day, d.hour, d.minute, d.second, d.timezone.as_deref().unwrap_or("")
+        ),
+        None => write!(out, "datetime(null)")
+    }
+}
+
+fn write_dynamic(out: &mut impl Write, d: &Option<Dynamic>) -> fmt::Result {
+    match d {
+        None => write!(out, "null"),
+        Some(Dynamic::Array(a)) => {
+            write!(out, "[")?;
+            write_list(out, a, write_dynamic)?;
+            write!(out, "]")
+        },
+        Some(Dynamic::Dictionary(d)) => {
+            write!(out, "{{")?;
+            write_list(out, &d.iter().collect::<Vec<_>>(), |out, (k, v)| {
+                write_string(out, k)?;
+                write!(out, ": ")?;
+                write_dynamic(out, v)
+            })?;
+            write!(out, "}}")
+        },
+        Some(Dynamic::Bool(Some(b))) => write!(out, "{}", b),
+        Some(Dynamic::Bool(None)) => write!(out, "bool(null)"),
+        Some(Dynamic::DateTime(x)) => write_datetime(out, x),
+        Some(Dynamic::Decimal(x)) => write_real(out, "decimal", x),
+        Some(Dynamic::Int(x)) => write_option(out, "int", x),
+        Some(Dynamic::Long(Some(x))) => write!(out, "{}", x),
+        Some(Dynamic::Long(None)) => write!(out, "long(null)"),
+        Some(Dynamic::Real(Some(x))) => write!(out, "{:?}", x),
+        Some(Dynamic::Real(None)) => write!(out, "null"),
+        Some(Dynamic::String(s)) => write_string(out, s),
+        Some(Dynamic::Timespan(Some(t))) => write_timespan(out, *t),
+        Some(Dynamic::Timespan(None)) => write!(out, "timespan(null)")
+    }
+}
+
+fn write_literal(out: &mut impl Write, l: &Literal) -> fmt::Result {
+    match l {
+        Literal::Bool(Some(b)) => write!(out, "{}", b),
+        Literal::Bool(None) => write!(out, "bool(null)"),
+        Literal::DateTime(x) => write_datetime(out, x),
+        Literal::Decimal(x) => write_real(out, "decimal", x),
+        Literal::Dynamic(x) => {
+            write!(out, "dynamic(")?;
+            write_dynamic(out, x)?;
+            write!(out, ")")
+        },
+        Literal::Int(x) => write_option(out, "int", x),
+        Literal::Long(Some(x)) => write!(out, "{}", x),
+        Literal::Long(None) => write!(out, "long(null)"),
+        Literal::Real(Some(x)) if x.is_finite() => write!(out, "{:?}", x),
+        Literal::Real(x) => write_real(out, "real", x),
+        Literal::String(s, true) => {
+            write!(out, "h")?;
+            write_string(out, s)
+        },
+        Literal::String(s, false) => write_string(out, s),
+        Literal::Timespan(Some(t)) => write_timespan(out, *t),
+        Literal::Timespan(None) => write!(out, "timespan(null)")
+    }
+}
+
+fn write_option_literal(out: &mut impl Write, o: &OptionLiteral, quoted: bool) -> fmt::Result {
+    match o {
+        OptionLiteral::Bool(b) => write!(out, "{}", b),
+        OptionLiteral::Long(l) => write!(out, "{}", l),
+        OptionLiteral::String(s) if quoted => write_string(out, s),
+        OptionLiteral::String(s) | OptionLiteral::Identifier(s) => write!(out, "{}", s)
+    }
+}
+
+/// Writes space separated options like `kind=inner`
+fn write_options_list(out: &mut impl Write, options: &Options) -> fmt::Result {
+    write_list_with(out, &options.iter().collect::<Vec<_>>(), " ", |out, (k, v)| {
+        write_identifier(out, k)?;
+        write!(out, "=")?;
+        write_option_literal(out, v, false)
+    })
+}
+
+/// Writes options preceding the arguments of an operator, followed by a space when not empty
+fn write_options(out: &mut impl Write, options: &Options) -> fmt::Result {
+    if !options.is_empty() {
+        write_options_list(out, options)?;
+        write!(out, " ")?;
+    }
+    Ok(())
+}
+
+fn write_quoted_options(out: &mut impl Write, options: &Options) -> fmt::Result {
+    write_list(out, &options.iter().collect::<Vec<_>>(), |out, (k, v)| {
+        write_identifier(out, k)?;
+        write!(out, "=")?;
+        write_option_literal(out, v, true)
+    })
+}
+
+fn write_operators(out: &mut impl Write, operators: &[Operator]) -> fmt::Result {
+    write_list_with(out, operators, " | ", write_operator)
+}
+
+fn write_list_with<T, W: Write>(out: &mut W, items: &[T], separator: &str, f: impl Fn(&mut W, &T) -> fmt::Result) -> fmt::Result {
+    for (n, item) in items.iter().enumerate() {
+        if n > 0 {
+            write!(out, "{}", separator)?;
+        }
+        f(out, item)?;
+    }
+    Ok(())
+}
+
+fn write_pattern(out: &mut impl Write, pattern: &[PatternToken]) -> fmt::Result {
+    write_list_with(out, pattern, " ", |out, t| match t {
+        PatternToken::Wildcard => write!(out, "*"),
+        PatternToken::String(s) => write_string(out, s),
+        PatternToken::Column(n, t) => {
+            write_identifier(out, n)?;
+            match t {
+                Some(t) => {
+                    write!(out, ":")?;
+                    write_type(out, t)
+                },
+                None => Ok(())
+            }
+        }
+    })
+}
+
+fn write_union_sources(out: &mut impl Write, sources: &[TabularExpression]) -> fmt::Result {
+    write_list(out, sources, |out, q| match &q.source.kind {
+        SourceKind::Reference(..) if q.operators.is_empty() => write_source(out, &q.source),
+        _ => {
+            write!(out, "(")?;
+            write_query(out, q, false)?;
+            write!(out, ")")
+        }
+    })
+}
+
+fn write_operator(out: &mut impl Write, o: &Operator) -> fmt::Result {
+    match &o.kind {
+        OperatorKind::As(opts, n) => {
+            write!(out, "as ")?;
+            write_options(out, opts)?;
+            write_identifier(out, n)
+        },
+        OperatorKind::Consume(opts) => {
+            write!(out, "consume")?;
+            if !opts.is_empty() {
+                write!(out, " ")?;
+                write_options_list(out, opts)?;
+            }
+            Ok(())
+        },
+        OperatorKind::Count => write!(out, "count"),
+        OperatorKind::Distinct(None) => write!(out, "distinct *"),
+        OperatorKind::Distinct(Some(x)) => {
+            write!(out, "distinct ")?;
+            write_named_exprs(out, x)
+        },
+        OperatorKind::Evaluate(opts, n, a) => {
+            write!(out, "evaluate ")?;
+            write_options(out, opts)?;
+            write_identifier(out, n)?;
+            write!(out, "(")?;
+            write_list(out, a, write_expr)?;
+            write!(out, ")")
+        },
+        OperatorKind::Extend(x) => {
+            write!(out, "extend ")?;
+            write_named_exprs(out, x)
+        },
+        OperatorKind::Facet(c, ops) => {
+            write!(out, "facet by ")?;
+            write_list(out, c, |out, c| write_identifier(out, c))?;
+            if !ops.is_empty() {
+                write!(out, " with (")?;
+                write_operators(out, ops)?;
+                write!(out, ")")?;
+            }
+            Ok(())
+        },
+        OperatorKind::Fork(f) => {
+            write!(out, "fork ")?;
+            write_list(out, f, |out, (n, ops)| {
+                if let Some(n) = n {
+                    write_identifier(out, n)?;
+                    write!(out, " = ")?;
+                }
+                write!(out, "(")?;
+                write_operators(out, ops)?;
+                write!(out, ")")
+            })
+        },
+        OperatorKind::Getschema => write!(out, "getschema"),
+        OperatorKind::Invoke(n, a) => {
+            write!(out, "invoke ")?;
+            write_call(out, n, a)
+        },
+        OperatorKind::Join(opts, q, on) | OperatorKind::Lookup(opts, q, on) => {
+            write!(out, "{} ", if matches!(o.kind, OperatorKind::Join(..)) { "join" } else { "lookup" })?;
+            write_options(out, opts)?;
+            write!(out, "(")?;
+            write_query(out, q, false)?;
+            write!(out, ") on ")?;
+            write_list(out, on, |out, c| write_identifier(out, c))
+        },
+        OperatorKind::MakeSeries(opts, m) => {
+            write!(out, "make-series ")?;
+            write_options(out, opts)?;
+            write_list(out, &m.aggregations, |out, (n, e, d)| {
+                if let Some(n) = n {
+                    write_identifier(out, n)?;
+                    write!(out, " = ")?;
+                }
+                write_expr(out, e)?;
+                if let Some(d) = d {
+                    write!(out, " default = ")?;
+                    write_expr(out, d)?;
+                }
+                Ok(())
+            })?;
+            write!(out, " on ")?;
+            write_expr(out, &m.on)?;
+            if let Some(from) = &m.from {
+                write!(out, " from ")?;
+                write_expr(out, from)?;
+            }
+            if let Some(to) = &m.to {
+                write!(out, " to ")?;
+                write_expr(out, to)?;
+            }
+            write!(out, " step ")?;
+            write_expr(out, &m.step)?;
+            if !m.by.is_empty() {
+                write!(out, " by ")?;
+                write_named_exprs(out, &m.by)?;
+            }
+            Ok(())
+        },
+        OperatorKind::MvApply(x, ops) => {
+            write!(out, "mv-apply ")?;
+            write_list(out, x, |out, ((n, c), t)| {
+                write_identifier(out, n)?;
+                write!(out, " = ")?;
+                write_identifier(out, c)?;
+                if let Some(t) = t {
+                    write!(out, " to typeof(")?;
+                    write_type(out, t)?;
+                    write!(out, ")")?;
+                }
+                Ok(())
+            })?;
+            write!(out, " on (")?;
+            write_operators(out, ops)?;
+            write!(out, ")")
+        },
+        OperatorKind::MvExpand(opts, c, limit) => {
+            write!(out, "mv-expand ")?;
+            write_options(out, opts)?;
+            write_list(out, c, |out, (n, e, t)| {
+                if let Some(n) = n {
+                    write_identifier(out, n)?;
+                    write!(out, " = ")?;
+                }
+                write_expr(out, e)?;
+                if let Some(t) = t {
+                    write!(out, " to typeof(")?;
+                    write_type(out, t)?;
+                    write!(out, ")")?;
+                }
+                Ok(())
+            })?;
+            if let Some(limit) = limit {
+                write!(out, " limit {}", limit)?;
+            }
+            Ok(())
+        },
+        OperatorKind::Parse(opts, e, p) | OperatorKind::ParseWhere(opts, e, p) => {
+            write!(out, "{} ", if matches!(o.kind, OperatorKind::Parse(..)) { "parse" } else { "parse-where" })?;
+            write_options(out, opts)?;
+            write_expr(out, e)?;
+            write!(out, " with ")?;
+            write_pattern(out, p)
+        },
+        OperatorKind::ParseKV(e, t, opts) => {
+            write!(out, "parse-kv ")?;
+            write_expr(out, e)?;
+            write!(out, " as ")?;
+            write_type_mapping(out, t)?;
+            write!(out, " with (")?;
+            write_quoted_options(out, opts)?;
+            write!(out, ")")
+        },
+        OperatorKind::Partition(opts, c, s, ops) => {
+            write!(out, "partition ")?;
+            write_options(out, opts)?;
+            write!(out, "by ")?;
+            write_identifier(out, c)?;
+            match s {
+                Some(s) => {
+                    write!(out, " ")?;
+                    write_source(out, s)?;
+                    for op in ops {
+                        write!(out, " | ")?;
+                        write_operator(out, op)?;
+                    }
+                    Ok(())
+                },
+                None => {
+                    write!(out, " (")?;
+                    write_operators(out, ops)?;
+                    write!(out, ")")
+                }
+            }
+        },
+        OperatorKind::Project(x) => {
+            write!(out, "project ")?;
+            write_named_exprs(out, x)
+        },
+        OperatorKind::ProjectAway(c) => {
+            write!(out, "project-away ")?;
+            write_list(out, c, |out, c| write_identifier(out, c))
+        },
+        OperatorKind::ProjectKeep(c) => {
+            write!(out, "project-keep ")?;
+            write_list(out, c, |out, c| write_identifier(out, c))
+        },
+        OperatorKind::ProjectRename(c) => {
+            write!(out, "project-rename ")?;
+            write_list(out, c, |out, (n, o)| {
+                write_identifier(out, n)?;
+                write!(out, " = ")?;
+                write_identifier(out, o)
+            })
+        },
+        OperatorKind::ProjectReorder(c) => {
+            write!(out, "project-reorder ")?;
+            write_list(out, c, |out, (n, o)| {
+                write_wildcard_identifier(out, n)?;
+                match o {
+                    Some((true, false)) => write!(out, " asc"),
+                    Some((false, false)) => write!(out, " desc"),
+                    Some((true, true)) => write!(out, " granny-asc"),
+                    Some((false, true)) => write!(out, " granny-desc"),
+                    None => Ok(())
+                }
+            })
+        },
+        OperatorKind::Reduce(opts, e, w) => {
+            write!(out, "reduce ")?;
+            write_options(out, opts)?;
+            write!(out, "by ")?;
+            write_expr(out, e)?;
+            if let Some(w) = w {
+                write!(out, " with ")?;
+                write_quoted_options(out, w)?;
+            }
+            Ok(())
+        },
+        OperatorKind::Render(n, w) => {
+            write!(out, "render ")?;
+            write_identifier(out, n)?;
+            if let Some(w) = w {
+                write!(out, " with (")?;
+                write_quoted_options(out, w)?;
+                write!(out, ")")?;
+            }
+            Ok(())
+        },
+        OperatorKind::Sample(n) => write!(out, "sample {}", n),
+        OperatorKind::SampleDistinct(n, c) => {
+            write!(out, "sample-distinct {} of ", n)?;
+            write_identifier(out, c)
+        },
+        OperatorKind::Scan(opts, declare, steps) => {
+            write!(out, "scan ")?;
+            write_options(out, opts)?;
+            if !declare.is_empty() {
+                write!(out, "declare (")?;
+                write_list(out, declare, |out, (n, t, d)| {
+                    write_identifier(out, n)?;
+                    write!(out, ":")?;
+                    write_type(out, t)?;
+                    if let Some(d) = d {
+                        write!(out, " = ")?;
+                        write_expr(out, d)?;
+                    }
+                    Ok(())
+                })?;
+                write!(out, ") ")?;
+            }
+            write!(out, "with (")?;
+            write_list_with(out, steps, " ", |out, s| {
+                write!(out, "step ")?;
+                write_identifier(out, &s.name)?;
+                match s.output {
+                    ScanOutput::All => {},
+                    ScanOutput::Last => write!(out, " output=last")?,
+                    ScanOutput::None => write!(out, " output=none")?
+                }
+                write!(out, ": ")?;
+                write_expr(out, &s.condition)?;
+                if !s.assignments.is_empty() {
+                    write!(out, " => ")?;
+                    write_list(out, &s.assignments, |out, (n, e)| {
+                        write_identifier(out, n)?;
+                        write!(out, " = ")?;
+                        write_expr(out, e)
+                    })?;
+                }
+                write!(out, ";")
+            })?;
+            write!(out, ")")
+        },
+        OperatorKind::Search(opts, e) => {
+            write!(out, "search ")?;
+            write_options(out, opts)?;
+            write_expr(out, e)
+        },
+        OperatorKind::Serialize(x) => {
+            write!(out, "serialize")?;
+            if !x.is_empty() {
+                write!(out, " ")?;
+                write_named_exprs(out, x)?;
+            }
+            Ok(())
+        },
+        OperatorKind::Summarize(x, by) => {
+            write!(out, "summarize")?;
+            if !x.is_empty() {
+                write!(out, " ")?;
+                write_named_exprs(out, x)?;
+            }
+            if !by.is_empty() {
+                write!(out, " by ")?;
+                write_list(out, by, write_expr)?;
+            }
+            Ok(())
+        },
+        OperatorKind::Sort(c) => {
+            write!(out, "sort by ")?;
+            write_list(out, c, |out, (e, asc, nulls_first)| write_sort_key(out, e, *asc, *nulls_first))
+        },
+        OperatorKind::Take(n) => write!(out, "take {}", n),
+        OperatorKind::Top(n, e, asc, nulls_first) => {
+            write!(out, "top {} by ", n)?;
+            write_sort_key(out, e, *asc, *nulls_first)
+        },
+        OperatorKind::TopHitters(n, (name, e), by) => {
+            write!(out, "top-hitters {} of ", n)?;
+            if let Some(name) = name {
+                write_identifier(out, name)?;
+                write!(out, " = ")?;
+            }
+            write_expr(out, e)?;
+            if let Some(by) = by {
+                write!(out, " by ")?;
+                write_expr(out, by)?;
+            }
+            Ok(())
+        },
+        OperatorKind::TopNested(levels) => write_list(out, levels, |out, l| {
+            write!(out, "top-nested ")?;
+            if let Some(n) = l.count {
+                write!(out, "{} ", n)?;
+            }
+            write!(out, "of ")?;
+            if let Some(n) = &l.name {
+                write_identifier(out, n)?;
+                write!(out, " = ")?;
+            }
+            write_expr(out, &l.expr)?;
+            if let Some(o) = &l.others {
+                write!(out, " with others = ")?;
+                write_expr(out, o)?;
+            }
+            write!(out, " by ")?;
+            if let Some(n) = &l.aggregation_name {
+                write_identifier(out, n)?;
+                write!(out, " = ")?;
+            }
+            write_expr(out, &l.aggregation)?;
+            write!(out, " {}", if l.asc { "asc" } else { "desc" })
+        }),
+        OperatorKind::Union(opts, s) => {
+            write!(out, "union ")?;
+            write_options(out, opts)?;
+            write_union_sources(out, s)
+        },
+        OperatorKind::Where(e) => {
+            write!(out, "where ")?;
+            write_expr(out, e)
+        }
+    }
+}
+
+fn write_sort_key(out: &mut impl Write, e: &Expr, asc: bool, nulls_first: bool) -> fmt::Result {
+    write_expr(out, e)?;
+    write!(out, " {} nulls {}", if asc { "asc" } else { "desc" }, if nulls_first { "first" } else { "last" })
+}
+
+fn write_source(out: &mut impl Write, s: &Source) -> fmt::Result {
+    match &s.kind {
+        SourceKind::Call(n, a) => write_call(out, n, a),
+        SourceKind::Datatable(t, v) => {
+            write!(out, "datatable ")?;
+            write_type_mapping(out, t)?;
+            write!(out, " [")?;
+            write_list(out, v, write_expr)?;
+            write!(out, "]")
+        },
+        SourceKind::Externaldata(t, u) => {
+            write!(out, "externaldata ")?;
+            write_type_mapping(out, t)?;
+            write!(out, " [")?;
+            write_list(out, u, |out, u| write_string(out, u))?;
+            write!(out, "]")
+        },
+        SourceKind::Find(opts, sources, e, p) => {
+            write!(out, "find ")?;
+            write_options(out, opts)?;
+            if let Some(sources) = sources {
+                write!(out, "in (")?;
+                write_list(out, sources, write_source)?;
+                write!(out, ") where ")?;
+            }
+            write_expr(out, e)?;
+            match p {
+                FindProjection::ProjectSmart => write!(out, " project-smart"),
+                FindProjection::Project(c) => {
+                    write!(out, " project ")?;
+                    write_list(out, c, |out, c| write_identifier(out, c))
+                }
+            }
+        },
+        SourceKind::Search(opts, sources, e) => {
+            write!(out, "search ")?;
+            write_options(out, opts)?;
+            if let Some(sources) = sources {
+                write!(out, "in (")?;
+                write_list(out, sources, write_source)?;
+                write!(out, ") ")?;
+            }
+            write_expr(out, e)
+        },
+        SourceKind::Print(x) => {
+            write!(out, "print ")?;
+            write_named_exprs(out, x)
+        },
+        SourceKind::Range(c, f, t, s) => {
+            write!(out, "range ")?;
+            write_identifier(out, c)?;
+            write!(out, " from ")?;
+            write_expr(out, f)?;
+            write!(out, " to ")?;
+            write_expr(out, t)?;
+            write!(out, " step ")?;
+            write_expr(out, s)
+        },
+        SourceKind::Reference(c, d, t) => {
+            if let Some(c) = c {
+                write!(out, "cluster(")?;
+                write_string(out, c)?;
+                write!(out, ").")?;
+            }
+            if let Some(d) = d {
+                write!(out, "database(")?;
+                write_string(out, d)?;
+                write!(out, ").")?;
+            }
+            write_identifier(out, t)
+        },
+        SourceKind::Union(opts, s) => {
+            write!(out, "union ")?;
+            write_options(out, opts)?;
+            write_union_sources(out, s)
+        }
+    }
+}
+
+fn write_call(out: &mut impl Write, name: &str, args: &[Expr]) -> fmt::Result {
+    write_identifier(out, name)?;
+    write!(out, "(")?;
+    write_list(out, args, write_expr)?;
+    write!(out, ")")
+}
+
+fn write_let_expression(out: &mut impl Write, e: &LetExpression, operator_per_line: bool) -> fmt::Result {
+    match e {
+        LetExpression::Scalar(e) => write_expr(out, e),
+        LetExpression::Tabular(q) => write_query(out, q, operator_per_line),
+        LetExpression::Materialize(q) => {
+            write!(out, "materialize(")?;
+            write_query(out, q, false)?;
+            write!(out, ")")
+        },
+        LetExpression::Function(f) => {
+            if f.view {
+                write!(out, "view ")?;
+            }
+            write!(out, "(")?;
+            write_list(out, &f.parameters, |out, (n, t, d)| {
+                write_identifier(out, n)?;
+                write!(out, ":")?;
+                match t {
+                    ParameterType::Scalar(t) => write_type(out, t)?,
+                    ParameterType::Tabular(Some(c)) => write_type_mapping(out, c)?,
+                    ParameterType::Tabular(None) => write!(out, "(*)")?
+                }
+                if let Some(d) = d {
+                    write!(out, " = ")?;
+                    write_expr(out, d)?;
+                }
+                Ok(())
+            })?;
+            write!(out, ") {{ ")?;
+            for (n, e) in &f.statements {
+                write_let(out, n, e, false)?;
+                write!(out, "; ")?;
+            }
+            write_let_expression(out, &f.body, false)?;
+            write!(out, " }}")
+        }
+    }
+}
+
+fn write_let(out: &mut impl Write, name: &str, e: &LetExpression, operator_per_line: bool) -> fmt::Result {
+    write!(out, "let ")?;
+    write_identifier(out, name)?;
+    write!(out, " = ")?;
+    write_let_expression(out, e, operator_per_line)
+}
+
+fn write_query(out: &mut impl Write, q: &TabularExpression, operator_per_line: bool) -> fmt::Result {
+    write_source(out, &q.source)?;
+    for op in &q.operators {
+        write!(out, "{}", if operator_per_line { "\n| " } else { " | " })?;
+        write_operator(out, op)?;
+    }
+    Ok(())
+}
+
+fn write_statement(out: &mut impl Write, s: &Statement, operator_per_line: bool) -> fmt::Result {
+    match s {
+        Statement::TabularExpression(q) => write_query(out, q, operator_per_line),
+        Statement::Let(n, e) => write_let(out, n, e, operator_per_line),
+        Statement::Set(n, v) => {
+            write!(out, "set ")?;
+            write_identifier(out, n)?;
+            match v {
+                Some(v) => {
+                    write!(out, " = ")?;
+                    write_expr(out, v)
+                },
+                None => Ok(())
+            }
+        },
+        Statement::QueryParameters(p) => {
+            write!(out, "declare query_parameters(")?;
+            write_list(out, p, |out, (n, t, d)| {
+                write_identifier(out, n)?;
+                write!(out, ":")?;
+                write_type(out, t)?;
+                if let Some(d) = d {
+                    write!(out, " = ")?;
+                    write_expr(out, d)?;
+                }
+                Ok(())
+            })?;
+            write!(out, ")")
+        }
+    }
+}
+
+// The alternate flag (`{:#}`) puts every operator on its own line
+
+impl Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_statement(f, self, f.alternate())
+    }
+}
+
+impl Display for TabularExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_query(f, self, f.alternate())
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_source(f, self)
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_operator(f, self)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_expr(f, self)
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_literal(f, self)
+    }
+}
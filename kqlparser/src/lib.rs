@@ -1,4 +1,6 @@
 pub mod ast;
+pub mod error;
+pub mod format;
 pub mod parser;
 
 mod datetime;
@@ -1,18 +1,19 @@
-use std::str::{self, FromStr};
+#![allow(clippy::type_complexity)]
 
 use nom::branch::alt;
-use nom::bytes::complete::{tag, tag_no_case, take_while1, escaped, is_a};
-use nom::character::complete::{digit1, i32, i64, multispace0, multispace1, none_of, one_of, u32, u64, hex_digit1};
-use nom::combinator::{map, opt, recognize, value};
+use nom::bytes::complete::{escaped_transform, is_not, take_until, take_while1};
+use nom::character::complete::{digit1, i32, i64, one_of, u32, u64, hex_digit1};
+use nom::combinator::{cut, eof, map, map_opt, not, opt, peek, recognize, value, verify};
 use nom::multi::{many0, separated_list0, separated_list1, fold_many0, many1};
 use nom::sequence::{tuple, preceded, delimited, separated_pair, terminated, pair};
-use nom::IResult;
+use nom::{InputTake, Parser};
 
 use super::ast::*;
 use super::datetime::{iso8601_datetime, rfc822_datetime, rfc850_datetime};
-use super::{dec_to_i64, decimal_number, is_kql_wildcard_identifier, take_identifier, trim};
+use super::error::{Error, ParseError};
+use super::{checked, dec_to_i64, decimal_number, Decimal, is_kql_wildcard_identifier, keyword, multispace0, multispace1, spanned, tag, tag_no_case, take_identifier, trim, Input, IResult};
 
-fn type_tag(i: &str) -> IResult<&str, Type> {
+fn type_tag(i: Input) -> IResult<Input, Type> {
     alt((
         map(tag("bool"), |_| Type::Bool),
         value(Type::DateTime, alt((tag("datetime"), tag("date")))),
@@ -26,34 +27,35 @@ fn type_tag(i: &str) -> IResult<&str, Type> {
     ))(i)
 }
 
-fn option_literal(i: &str) -> IResult<&str, OptionLiteral> {
+fn option_literal(i: Input) -> IResult<Input, OptionLiteral> {
     alt((
         value(OptionLiteral::Bool(true), tag("true")),
         value(OptionLiteral::Bool(false), tag("false")),
-        map(i64, |x| OptionLiteral::Long(x)),
-        map(take_while1(|c: char| !c.is_whitespace()), |s: &str| OptionLiteral::String(s.to_string())),
+        map(i64, OptionLiteral::Long),
+        map(take_while1(|c: char| !c.is_whitespace()), |s: Input| OptionLiteral::String(s.fragment().to_string())),
     ))(i)
 }
 
-fn option_quoted_literal(i: &str) -> IResult<&str, OptionLiteral> {
+fn option_quoted_literal(i: Input) -> IResult<Input, OptionLiteral> {
     alt((
         value(OptionLiteral::Bool(true), tag("true")),
         value(OptionLiteral::Bool(false), tag("false")),
-        map(i64, |x| OptionLiteral::Long(x)),
-        map(string, |s| OptionLiteral::String(s)),
-        map(identifier, |s| OptionLiteral::Identifier(s))
+        map(i64, OptionLiteral::Long),
+        map(string, OptionLiteral::String),
+        map(identifier, OptionLiteral::Identifier)
     ))(i)
 }
 
-fn options(i: &str) -> IResult<&str, Options> {
+fn options(i: Input) -> IResult<Input, Options> {
+    // `a == b` starts an expression rather than an option
     map(separated_list0(multispace1, separated_pair(
         identifier,
-        trim(tag("=")),
+        trim(terminated(tag("="), not(one_of("=~")))),
         option_literal
     )), |x| x.into_iter().collect())(i)
 }
 
-fn options_with_comma_and_quoted(i: &str) -> IResult<&str, Options> {
+fn options_with_comma_and_quoted(i: Input) -> IResult<Input, Options> {
     map(separated_list0(tag(","), separated_pair(
         trim(identifier),
         tag("="),
@@ -61,10 +63,10 @@ fn options_with_comma_and_quoted(i: &str) -> IResult<&str, Options> {
     )), |x| x.into_iter().collect())(i)
 }
 
-fn pattern(i: &str) -> IResult<&str, Vec<PatternToken>> {
+fn pattern(i: Input) -> IResult<Input, Vec<PatternToken>> {
     many1(trim(alt((
         map(tag("*"), |_| PatternToken::Wildcard),
-        map(string, |s| PatternToken::String(s)),
+        map(string, PatternToken::String),
         map(
             pair(identifier, opt(preceded(trim(tag(":")), type_tag))),
             |(n, t)| PatternToken::Column(n, t)
@@ -72,7 +74,7 @@ fn pattern(i: &str) -> IResult<&str, Vec<PatternToken>> {
     ))))(i)
 }
 
-fn type_mapping(i: &str) -> IResult<&str, Vec<(String, Type)>> {
+fn type_mapping(i: Input) -> IResult<Input, Vec<(String, Type)>> {
     separated_list1(tag(","), separated_pair(
         trim(identifier),
         tag(":"),
@@ -80,22 +82,88 @@ fn type_mapping(i: &str) -> IResult<&str, Vec<(String, Type)>> {
     ))(i)
 }
 
-fn identifier(i: &str) -> IResult<&str, String> {
-    map(take_identifier, |i| i.to_string())(i)
+/// Matches an identifier quoted like `['name']` or `["name"]`, which may contain any character
+fn quoted_identifier(i: Input) -> IResult<Input, String> {
+    delimited(tag("["), trim(alt((quoted_string("'"), quoted_string("\"")))), tag("]"))(i)
+}
+
+fn identifier(i: Input) -> IResult<Input, String> {
+    alt((
+        map(take_identifier, |i| i.fragment().to_string()),
+        quoted_identifier
+    ))(i)
 }
 
-fn wildcard_identifier(i: &str) -> IResult<&str, String> {
-    map(take_while1(is_kql_wildcard_identifier), |i: &str| i.to_string())(i)
+fn wildcard_identifier(i: Input) -> IResult<Input, String> {
+    alt((
+        map(take_while1(is_kql_wildcard_identifier), |i: Input| i.fragment().to_string()),
+        quoted_identifier
+    ))(i)
 }
 
-fn string(i: &str) -> IResult<&str, String> {
-    map(alt((
-        delimited(tag("\""), alt((escaped(none_of::<&str, _, _>("\\\""), '\\', tag("\"")), tag(""))), tag("\"")),
-        delimited(tag("'"), alt((escaped(none_of::<&str, _, _>("\\'"), '\\', tag("'")), tag(""))), tag("'"))
-    )), |s| s.to_string())(i)
+fn quoted_string(quote: &'static str) -> impl Fn(Input) -> IResult<Input, String> {
+    move |i| preceded(tag(quote), cut(terminated(alt((
+        escaped_transform(
+            map(is_not(if quote == "\"" { "\\\"" } else { "\\'" }), |s: Input| *s.fragment()),
+            '\\',
+            alt((
+                value("\\", tag("\\")),
+                value("\"", tag("\"")),
+                value("'", tag("'")),
+                value("\n", tag("n")),
+                value("\r", tag("r")),
+                value("\t", tag("t")),
+            ))
+        ),
+        map(peek(tag(quote)), |_| String::new())
+    )), tag(quote))))(i)
 }
 
-fn boolean(i: &str) -> IResult<&str, Option<bool>> {
+fn verbatim_string(quote: &'static str) -> impl Fn(Input) -> IResult<Input, String> {
+    move |i| preceded(pair(tag("@"), tag(quote)), cut(terminated(fold_many0(
+        alt((
+            map(is_not(quote), |s: Input| *s.fragment()),
+            value(quote, pair(tag(quote), tag(quote))),
+        )),
+        String::new,
+        |mut acc, s| { acc.push_str(s); acc }
+    ), tag(quote))))(i)
+}
+
+fn multiline_string(fence: &'static str) -> impl Fn(Input) -> IResult<Input, String> {
+    move |i| {
+        let (i, _) = tag(fence)(i)?;
+        match take_until::<_, _, ()>(fence)(i) {
+            Ok((rest, s)) => map(tag(fence), |_| s.fragment().to_string())(rest),
+            Err(_) => Err(nom::Err::Failure(Error::expected(i.take_split(i.fragment().len()).0, fence)))
+        }
+    }
+}
+
+fn string_literal(i: Input) -> IResult<Input, (String, bool)> {
+    map(pair(opt(terminated(one_of("hH"), peek(one_of("\"'@`~")))), alt((
+        quoted_string("\""),
+        quoted_string("'"),
+        verbatim_string("\""),
+        verbatim_string("'"),
+        multiline_string("```"),
+        multiline_string("~~~"),
+    ))), |(h, s)| (s, h.is_some()))(i)
+}
+
+/// String and whether it is obfuscated, adjacent string literals are concatenated
+/// and the result is obfuscated if any of them is
+fn obfuscated_string(i: Input) -> IResult<Input, (String, bool)> {
+    let (i, first) = string_literal(i)?;
+    fold_many0(preceded(multispace0, string_literal), move || first.clone(), |(acc, h), (s, o)| (acc + &s, h || o))(i)
+}
+
+fn string(i: Input) -> IResult<Input, String> {
+    // Obfuscated strings are only hidden in logs, so the prefix doesn't change the value
+    map(obfuscated_string, |(s, _)| s)(i)
+}
+
+fn boolean(i: Input) -> IResult<Input, Option<bool>> {
     alt((
         map(tag_no_case("true"), |_| Some(true)),
         map(tag_no_case("false"), |_| Some(false)),
@@ -104,26 +172,26 @@ fn boolean(i: &str) -> IResult<&str, Option<bool>> {
     ))(i)
 }
 
-fn date(i: &str) -> IResult<&str, Option<DateTime>> {
+fn date(i: Input) -> IResult<Input, Option<DateTime>> {
     alt((
-        map(iso8601_datetime, |x| Some(x)),
-        map(rfc822_datetime, |x| Some(x)),
-        map(rfc850_datetime, |x| Some(x)),
+        map(iso8601_datetime, Some),
+        map(rfc822_datetime, Some),
+        map(rfc850_datetime, Some),
         map(tag("null"), |_| None)
     ))(i)
 }
 
-fn decimal(i: &str) -> IResult<&str, Option<f64>> {
+fn decimal(i: Input) -> IResult<Input, Option<f64>> {
     alt((
-        map(recognize(tuple((opt(tag("-")), digit1, opt(pair(tag("."), digit1)), opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: &str| Some(x.parse().unwrap())),
+        map(recognize(tuple((opt(tag("-")), digit1, opt(pair(tag("."), digit1)), opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: Input| Some(x.parse().unwrap())),
         value(Some(f64::INFINITY), tag("+inf")),
-        value(Some(f64::INFINITY), tag("-inf")),
+        value(Some(f64::NEG_INFINITY), tag("-inf")),
         value(Some(f64::NAN), tag("nan")),
         value(None, tag("null")),
     ))(i)
 }
 
-fn dynamic(i: &str) -> IResult<&str, Option<Dynamic>> {
+fn dynamic(i: Input) -> IResult<Input, Option<Dynamic>> {
     alt((
         map(delimited(tag("["), separated_list0(tag(","), trim(dynamic)), tag("]")), |x| Some(Dynamic::Array(x))),
         map(delimited(tag("{"), separated_list0(tag(","), separated_pair(trim(string), tag(":"), trim(dynamic))), tag("}")), |x| Some(Dynamic::Dictionary(x.into_iter().collect()))),
@@ -133,102 +201,130 @@ fn dynamic(i: &str) -> IResult<&str, Option<Dynamic>> {
         map(preceded(tag("int"), delimited(tag("("), trim(integer), tag(")"))), |x| Some(Dynamic::Int(x))),
         map(preceded(tag("long"), delimited(tag("("), trim(long), tag(")"))), |x| Some(Dynamic::Long(x))),
         map(preceded(alt((tag("timespan"), tag("time"))), delimited(tag("("), trim(timespan), tag(")"))), |x| Some(Dynamic::Timespan(x))),
-        map(preceded(tag_no_case("0x"), hex_digit1), |x| Some(Dynamic::Long(Some(i64::from_str_radix(x, 16).unwrap())))),
-        map(terminated(decimal_number, alt((tag("days"), tag("day"), tag("d")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60 * 24))))),
-        map(terminated(decimal_number, alt((tag("hours"), tag("hour"), tag("h")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60))))),
-        map(terminated(decimal_number, alt((tag("minutes"), tag("minute"), tag("m")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60))))),
-        map(terminated(decimal_number, alt((tag("seconds"), tag("second"), tag("s")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000))))),
-        map(terminated(decimal_number, alt((tag("milliseconds"), tag("millisecond"), tag("milli"), tag("ms")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 1000 * 1000))))),
-        map(terminated(decimal_number, alt((tag("microseconds"), tag("microsecond"), tag("micro")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 1000))))),
-        map(terminated(decimal_number, alt((tag("ticks"), tag("tick")))), |x| Some(Dynamic::Timespan(Some(dec_to_i64(x, 100))))),
-        map(recognize(tuple((opt(tag("-")), digit1, tag("."), digit1, opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: &str| Some(Dynamic::Real(Some(x.parse().unwrap())))),
-        map(recognize(tuple((opt(tag("-")), digit1, tag("e"), opt(tag("-")), digit1))), |x: &str| Some(Dynamic::Real(Some(x.parse().unwrap())))),
+        map(hex_long, |x| Some(Dynamic::Long(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("days"), keyword("day"), keyword("d")))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60 * 24)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("hours"), keyword("hour"), keyword("h")))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("minutes"), keyword("minute"), keyword("m")))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("seconds"), keyword("second"), keyword("s")))), |x| dec_to_i64(x, 1000 * 1000 * 1000)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("milliseconds"), keyword("millisecond"), keyword("milli"), keyword("ms")))), |x| dec_to_i64(x, 1000 * 1000)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("microseconds"), keyword("microsecond"), keyword("micro")))), |x| dec_to_i64(x, 1000)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(checked(terminated(decimal_number, alt((keyword("ticks"), keyword("tick")))), |x| dec_to_i64(x, 100)), |x| Some(Dynamic::Timespan(Some(x)))),
+        map(recognize(tuple((opt(tag("-")), digit1, tag("."), digit1, opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: Input| Some(Dynamic::Real(Some(x.parse().unwrap())))),
+        map(recognize(tuple((opt(tag("-")), digit1, tag("e"), opt(tag("-")), digit1))), |x: Input| Some(Dynamic::Real(Some(x.parse().unwrap())))),
         map(i64, |x| Some(Dynamic::Long(Some(x)))),
         map(string, |s| Some(Dynamic::String(s))),
         alt((
-            value(Some(Dynamic::Bool(Some(true))), tag("true")),
-            value(Some(Dynamic::Bool(Some(false))), tag("false")),
+            value(Some(Dynamic::Bool(Some(true))), keyword("true")),
+            value(Some(Dynamic::Bool(Some(false))), keyword("false")),
             value(None, tag("null"))
         ))
     ))(i)
 }
 
-fn integer(i: &str) -> IResult<&str, Option<i32>> {
+/// Hexadecimal number of up to 32 bits, larger values than `0x7FFFFFFF` are negative as in two's complement
+fn hex_int(i: Input) -> IResult<Input, i32> {
+    preceded(tag_no_case("0x"), cut(map_opt(hex_digit1, |x: Input| u32::from_str_radix(x.fragment(), 16).ok().map(|x| x as i32))))(i)
+}
+
+/// Hexadecimal number of up to 64 bits, larger values than `0x7FFFFFFFFFFFFFFF` are negative as in two's complement
+fn hex_long(i: Input) -> IResult<Input, i64> {
+    preceded(tag_no_case("0x"), cut(map_opt(hex_digit1, |x: Input| u64::from_str_radix(x.fragment(), 16).ok().map(|x| x as i64))))(i)
+}
+
+fn integer(i: Input) -> IResult<Input, Option<i32>> {
     alt((
-        map(preceded(tag_no_case("0x"), hex_digit1), |x| Some(i32::from_str_radix(x, 16).unwrap())),
-        map(i32, |x| Some(x)),
+        map(hex_int, Some),
+        map(i32, Some),
         map(tag("null"), |_| None)
     ))(i)
 }
 
-fn long(i: &str) -> IResult<&str, Option<i64>> {
+fn long(i: Input) -> IResult<Input, Option<i64>> {
     alt((
-        map(preceded(tag_no_case("0x"), hex_digit1), |x| Some(i64::from_str_radix(x, 16).unwrap())),
-        map(i64, |x| Some(x)),
+        map(hex_long, Some),
+        map(i64, Some),
         map(tag("null"), |_| None)
     ))(i)
 }
 
-fn real(i: &str) -> IResult<&str, Option<f32>> {
+fn real(i: Input) -> IResult<Input, Option<f32>> {
     alt((
-        map(recognize(tuple((opt(tag("-")), digit1, opt(pair(tag("."), digit1)), opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: &str| Some(x.parse().unwrap())),
+        map(recognize(tuple((opt(tag("-")), digit1, opt(pair(tag("."), digit1)), opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: Input| Some(x.parse().unwrap())),
         value(Some(f32::INFINITY), tag("+inf")),
-        value(Some(f32::INFINITY), tag("-inf")),
+        value(Some(f32::NEG_INFINITY), tag("-inf")),
         value(Some(f32::NAN), tag("nan")),
         value(None, tag("null")),
     ))(i)
 }
 
-fn timespan(i: &str) -> IResult<&str, Option<i64>> {
+/// Nanoseconds of a timespan like `hh:mm[:ss]`, `None` if it doesn't fit in an `i64`
+fn clock_to_i64(hours: i64, minutes: u64, seconds: Option<Decimal>) -> Option<i64> {
+    let minutes = hours.checked_mul(60)?.checked_add(i64::try_from(minutes).ok()?)?;
+    minutes.checked_mul(1000 * 1000 * 1000 * 60)?.checked_add(seconds.map_or(Some(0), |x| dec_to_i64(x, 1000 * 1000 * 1000))?)
+}
+
+fn timespan(i: Input) -> IResult<Input, Option<i64>> {
     alt((
-        map(terminated(decimal_number, pair(multispace0, alt((tag("days"), tag("day"), tag("d"))))), |x| Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60 * 24))),
-        map(terminated(decimal_number, pair(multispace0, alt((tag("hours"), tag("hour"), tag("h"))))), |x| Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60))),
-        map(terminated(decimal_number, pair(multispace0, alt((tag("minutes"), tag("minute"), tag("m"))))), |x| Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60))),
-        map(terminated(decimal_number, pair(multispace0, alt((tag("seconds"), tag("second"), tag("s"))))), |x| Some(dec_to_i64(x, 1000 * 1000 * 1000))),
-        map(terminated(decimal_number, pair(multispace0, alt((tag("milliseconds"), tag("millisecond"), tag("milli"), tag("ms"))))), |x| Some(dec_to_i64(x, 1000 * 1000))),
-        map(terminated(decimal_number, pair(multispace0, alt((tag("microseconds"), tag("microsecond"), tag("micro"))))), |x| Some(dec_to_i64(x, 1000))),
-        map(terminated(decimal_number, pair(multispace0, alt((tag("ticks"), tag("tick"))))), |x| Some(dec_to_i64(x, 100))),
-        map(
-            tuple((separated_pair(i64, tag("."), separated_pair(u64, tag(":"), u64)), opt(preceded(tag(":"), decimal_number)))),
-            |((d, (h, m)), s)| Some(((d * 24 + h as i64) * 60 + m as i64) * (1000 * 1000 * 1000 * 60) + s.map(|x| dec_to_i64(x, 1000 * 1000 * 1000)).unwrap_or(0) as i64)
-        ),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("days"), keyword("day"), keyword("d"))))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60 * 24)), Some),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("hours"), keyword("hour"), keyword("h"))))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60)), Some),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("minutes"), keyword("minute"), keyword("m"))))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60)), Some),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("seconds"), keyword("second"), keyword("s"))))), |x| dec_to_i64(x, 1000 * 1000 * 1000)), Some),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("milliseconds"), keyword("millisecond"), keyword("milli"), keyword("ms"))))), |x| dec_to_i64(x, 1000 * 1000)), Some),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("microseconds"), keyword("microsecond"), keyword("micro"))))), |x| dec_to_i64(x, 1000)), Some),
+        map(checked(terminated(decimal_number, pair(multispace0, alt((keyword("ticks"), keyword("tick"))))), |x| dec_to_i64(x, 100)), Some),
         map(
-            tuple((separated_pair(u64, tag(":"), u64), opt(preceded(tag(":"), decimal_number)))),
-            |((h, m), s)| Some((h as i64 * 60 + m as i64) * (1000 * 1000 * 1000 * 60) + s.map(|x| dec_to_i64(x, 1000 * 1000 * 1000)).unwrap_or(0) as i64)
+            checked(
+                tuple((separated_pair(i64, tag("."), separated_pair(u64, tag(":"), u64)), opt(preceded(tag(":"), decimal_number)))),
+                |((d, (h, m)), s)| clock_to_i64(d.checked_mul(24)?.checked_add(i64::try_from(h).ok()?)?, m, s)
+            ),
+            Some
         ),
+        map(checked(tuple((separated_pair(u64, tag(":"), u64), opt(preceded(tag(":"), decimal_number)))), |((h, m), s)| clock_to_i64(i64::try_from(h).ok()?, m, s)), Some),
         map(tag("null"), |_| None)
     ))(i)
 }
 
-fn literal(i: &str) -> IResult<&str, Literal> {
+fn literal(i: Input) -> IResult<Input, Literal> {
     alt((
-        map(preceded(tag("bool"), delimited(tag("("), trim(boolean), tag(")"))), |x| Literal::Bool(x)),
-        map(preceded(tag("datetime"), delimited(tag("("), trim(date), tag(")"))), |x| Literal::DateTime(x)),
-        map(preceded(tag("dynamic"), delimited(tag("("), trim(dynamic), tag(")"))), |x| Literal::Dynamic(x)),
-        map(preceded(tag("int"), delimited(tag("("), trim(integer), tag(")"))), |x| Literal::Int(x)),
-        map(preceded(tag("long"), delimited(tag("("), trim(long), tag(")"))), |x| Literal::Long(x)),
-        map(preceded(tag("real"), delimited(tag("("), trim(real), tag(")"))), |x| Literal::Real(x)),
-        map(preceded(alt((tag("timespan"), tag("time"))), delimited(tag("("), trim(timespan), tag(")"))), |x| Literal::Timespan(x)),
-        map(preceded(tag_no_case("0x"), hex_digit1), |x| Literal::Long(Some(i64::from_str_radix(x, 16).unwrap()))),
-        map(terminated(decimal_number, alt((tag("days"), tag("day"), tag("d")))), |x| Literal::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60 * 24)))),
-        map(terminated(decimal_number, alt((tag("hours"), tag("hour"), tag("h")))), |x| Literal::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60)))),
-        map(terminated(decimal_number, alt((tag("minutes"), tag("minute"), tag("m")))), |x| Literal::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000 * 60)))),
-        map(terminated(decimal_number, alt((tag("seconds"), tag("second"), tag("s")))), |x| Literal::Timespan(Some(dec_to_i64(x, 1000 * 1000 * 1000)))),
-        map(terminated(decimal_number, alt((tag("milliseconds"), tag("millisecond"), tag("milli"), tag("ms")))), |x| Literal::Timespan(Some(dec_to_i64(x, 1000 * 1000)))),
-        map(terminated(decimal_number, alt((tag("microseconds"), tag("microsecond"), tag("micro")))), |x| Literal::Timespan(Some(dec_to_i64(x, 1000)))),
-        map(terminated(decimal_number, alt((tag("ticks"), tag("tick")))), |x| Literal::Timespan(Some(dec_to_i64(x, 100)))),
-        map(recognize(tuple((opt(tag("-")), digit1, tag("."), digit1, opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: &str| Literal::Real(Some(x.parse().unwrap()))),
-        map(recognize(tuple((opt(tag("-")), digit1, tag("e"), opt(tag("-")), digit1))), |x: &str| Literal::Real(Some(x.parse().unwrap()))),
+        alt((
+            map(preceded(tag("bool"), delimited(tag("("), trim(boolean), tag(")"))), Literal::Bool),
+            map(preceded(tag("datetime"), delimited(tag("("), trim(date), tag(")"))), Literal::DateTime),
+            map(preceded(tag("decimal"), delimited(tag("("), trim(decimal), tag(")"))), Literal::Decimal),
+            map(preceded(tag("dynamic"), delimited(tag("("), trim(dynamic), tag(")"))), Literal::Dynamic),
+            map(preceded(tag("int"), delimited(tag("("), trim(integer), tag(")"))), Literal::Int),
+            map(preceded(tag("long"), delimited(tag("("), trim(long), tag(")"))), Literal::Long),
+            map(preceded(tag("real"), delimited(tag("("), trim(real), tag(")"))), Literal::Real),
+            map(preceded(alt((tag("timespan"), tag("time"))), delimited(tag("("), trim(timespan), tag(")"))), Literal::Timespan),
+        )),
+        map(hex_long, |x| Literal::Long(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("days"), keyword("day"), keyword("d")))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60 * 24)), |x| Literal::Timespan(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("hours"), keyword("hour"), keyword("h")))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60 * 60)), |x| Literal::Timespan(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("minutes"), keyword("minute"), keyword("m")))), |x| dec_to_i64(x, 1000 * 1000 * 1000 * 60)), |x| Literal::Timespan(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("seconds"), keyword("second"), keyword("s")))), |x| dec_to_i64(x, 1000 * 1000 * 1000)), |x| Literal::Timespan(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("milliseconds"), keyword("millisecond"), keyword("milli"), keyword("ms")))), |x| dec_to_i64(x, 1000 * 1000)), |x| Literal::Timespan(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("microseconds"), keyword("microsecond"), keyword("micro")))), |x| dec_to_i64(x, 1000)), |x| Literal::Timespan(Some(x))),
+        map(checked(terminated(decimal_number, alt((keyword("ticks"), keyword("tick")))), |x| dec_to_i64(x, 100)), |x| Literal::Timespan(Some(x))),
+        map(recognize(tuple((opt(tag("-")), digit1, tag("."), digit1, opt(tuple((tag("e"), opt(tag("-")), digit1)))))), |x: Input| Literal::Real(Some(x.parse().unwrap()))),
+        map(recognize(tuple((opt(tag("-")), digit1, tag("e"), opt(tag("-")), digit1))), |x: Input| Literal::Real(Some(x.parse().unwrap()))),
         map(i64, |x| Literal::Long(Some(x))),
-        map(string, |s| Literal::String(s)),
-        map(tag("true"), |_| Literal::Bool(Some(true))),
-        map(tag("false"), |_| Literal::Bool(Some(false))),
+        map(obfuscated_string, |(s, h)| Literal::String(s, h)),
+        map(keyword("true"), |_| Literal::Bool(Some(true))),
+        map(keyword("false"), |_| Literal::Bool(Some(false))),
     ))(i)
 }
 
-fn ident_expr(i: &str) -> IResult<&str, Expr> {
-    alt((
-        map(literal, |l| Expr::Literal(l)),
+fn binary_expr(kind: fn(Box<Expr>, Box<Expr>) -> ExprKind, left: Expr, right: Expr) -> Expr {
+    let span = Span::new(left.span.start, right.span.end);
+    Expr::new(kind(Box::new(left), Box::new(right)), span)
+}
+
+fn ident_expr(i: Input) -> IResult<Input, Expr> {
+    map(spanned(alt((
+        map(literal, ExprKind::Literal),
+        map(
+            preceded(pair(keyword("toscalar"), multispace0), delimited(tag("("), trim(parse_query), tag(")"))),
+            |q| ExprKind::ToScalar(Box::new(q))
+        ),
         map(
             separated_pair(
                 identifier,
@@ -238,101 +334,220 @@ fn ident_expr(i: &str) -> IResult<&str, Expr> {
                     trim(expr),
                 ), tag(")"))
             ),
-            |(n, x)| Expr::Func(n, x),
+            |(n, x)| ExprKind::Func(n, x),
         ),
-        map(identifier, |i| Expr::Ident(i)),
-    ))(i)
+        map(identifier, ExprKind::Ident),
+    ))), |(e, s)| Expr::new(e, s))(i)
 }
 
-fn delim_expr(i: &str) -> IResult<&str, Expr> {
+fn delim_expr(i: Input) -> IResult<Input, Expr> {
     let (i, ident) = alt((
-        delimited(tag("("), trim(or_expr), tag(")")),
+        delimited(tag("("), trim(expr), tag(")")),
         ident_expr,
     ))(i)?;
 
-    fold_many0(alt((
+    fold_many0(spanned(alt((
         trim(preceded(opt(trim(tag("."))), delimited(
             tag("["),
             trim(expr),
             tag("]"),
         ))),
-        map(preceded(trim(tag(".")), identifier), |i| Expr::Ident(i)),
-    )), move || ident.clone(), |f, i| Expr::Index(Box::new(f), Box::new(i)))(i)
+        map(preceded(trim(tag(".")), spanned(identifier)), |(i, s)| Expr::new(ExprKind::Ident(i), s)),
+    ))), move || ident.clone(), |f, (i, s)| {
+        let span = Span::new(f.span.start, s.end);
+        Expr::new(ExprKind::Index(Box::new(f), Box::new(i)), span)
+    })(i)
 }
 
-fn muldiv_expr(i: &str) -> IResult<&str, Expr> {
-    let (i, initial) = delim_expr(i)?;
-    fold_many0(pair(trim(one_of("*/%")), delim_expr), move || initial.clone(), |acc, (o, g)| match o {
-        '*' => Expr::Multiply(Box::new(acc), Box::new(g)),
-        '/' => Expr::Divide(Box::new(acc), Box::new(g)),
-        '%' => Expr::Modulo(Box::new(acc), Box::new(g)),
-        _ => unreachable!()
-    })(i)
+fn unary_expr(i: Input) -> IResult<Input, Expr> {
+    alt((
+        map(
+            spanned(preceded(pair(keyword("not"), multispace0), delimited(tag("("), trim(expr), tag(")")))),
+            |(e, s)| Expr::new(ExprKind::Not(Box::new(e)), s)
+        ),
+        delim_expr,
+        map(
+            spanned(preceded(pair(tag("-"), multispace0), unary_expr)),
+            |(e, s)| Expr::new(ExprKind::Negate(Box::new(e)), s)
+        ),
+    ))(i).map_err(|e| match e {
+        nom::Err::Error(e) if e.input.location_offset() == i.location_offset() => nom::Err::Error(Error::expected(i, "expression")),
+        e => e
+    })
 }
 
-fn addsub_expr(i: &str) -> IResult<&str, Expr> {
-    let (i, initial) = muldiv_expr(i)?;
-    fold_many0(pair(trim(one_of("+-")), muldiv_expr), move || initial.clone(), |acc, (o, g)| match o {
-        '+' => Expr::Add(Box::new(acc), Box::new(g)),
-        '-' => Expr::Substract(Box::new(acc), Box::new(g)),
-        _ => unreachable!()
-    })(i)
+type BinaryOperator = fn(Box<Expr>, Box<Expr>) -> ExprKind;
+
+fn binary_operator<'a, F>(f: F, kind: BinaryOperator) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, BinaryOperator>
+where
+    F: Parser<Input<'a>, Input<'a>, Error<Input<'a>>>,
+{
+    value(kind, f)
 }
 
-fn predicate(i: &str) -> IResult<&str, Expr> {
-    let (i, initial) = addsub_expr(i)?;
-    let (i, e) = fold_many0(pair(trim(is_a("!=<>")), addsub_expr), move || Ok(initial.clone()), |acc, (o, g)| acc.and_then(|acc| Ok(match o {
-        "==" => Expr::Equals(Box::new(acc), Box::new(g)),
-        "!=" => Expr::NotEquals(Box::new(acc), Box::new(g)),
-        "<" => Expr::Less(Box::new(acc), Box::new(g)),
-        ">" => Expr::Greater(Box::new(acc), Box::new(g)),
-        "<=" => Expr::LessOrEqual(Box::new(acc), Box::new(g)),
-        ">=" => Expr::GreaterOrEqual(Box::new(acc), Box::new(g)),
-        _ => return Err(nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::Tag)))
-    })))(i)?;
-    Ok((i, e?))
+fn predicate_operator(i: Input) -> IResult<Input, BinaryOperator> {
+    alt((
+        alt((
+            binary_operator(tag("=="), ExprKind::Equals),
+            binary_operator(tag("!="), ExprKind::NotEquals),
+            binary_operator(tag("=~"), ExprKind::EqualsCaseInsensitive),
+            binary_operator(tag("!~"), ExprKind::NotEqualsCaseInsensitive),
+            binary_operator(tag("<="), ExprKind::LessOrEqual),
+            binary_operator(tag(">="), ExprKind::GreaterOrEqual),
+            binary_operator(tag("<"), ExprKind::Less),
+            binary_operator(tag(">"), ExprKind::Greater),
+            binary_operator(recognize(tuple((keyword("matches"), multispace1, keyword("regex")))), ExprKind::MatchesRegex),
+        )),
+        alt((
+            binary_operator(keyword("contains"), ExprKind::Contains),
+            binary_operator(keyword("!contains"), ExprKind::NotContains),
+            binary_operator(keyword("contains_cs"), ExprKind::ContainsCs),
+            binary_operator(keyword("!contains_cs"), ExprKind::NotContainsCs),
+            binary_operator(keyword("has"), ExprKind::Has),
+            binary_operator(keyword("!has"), ExprKind::NotHas),
+            binary_operator(keyword("has_cs"), ExprKind::HasCs),
+            binary_operator(keyword("!has_cs"), ExprKind::NotHasCs),
+            binary_operator(keyword("hasprefix"), ExprKind::HasPrefix),
+            binary_operator(keyword("!hasprefix"), ExprKind::NotHasPrefix),
+            binary_operator(keyword("hasprefix_cs"), ExprKind::HasPrefixCs),
+            binary_operator(keyword("!hasprefix_cs"), ExprKind::NotHasPrefixCs),
+            binary_operator(keyword("hassuffix"), ExprKind::HasSuffix),
+            binary_operator(keyword("!hassuffix"), ExprKind::NotHasSuffix),
+            binary_operator(keyword("hassuffix_cs"), ExprKind::HasSuffixCs),
+            binary_operator(keyword("!hassuffix_cs"), ExprKind::NotHasSuffixCs),
+        )),
+        alt((
+            binary_operator(keyword("startswith"), ExprKind::StartsWith),
+            binary_operator(keyword("!startswith"), ExprKind::NotStartsWith),
+            binary_operator(keyword("startswith_cs"), ExprKind::StartsWithCs),
+            binary_operator(keyword("!startswith_cs"), ExprKind::NotStartsWithCs),
+            binary_operator(keyword("endswith"), ExprKind::EndsWith),
+            binary_operator(keyword("!endswith"), ExprKind::NotEndsWith),
+            binary_operator(keyword("endswith_cs"), ExprKind::EndsWithCs),
+            binary_operator(keyword("!endswith_cs"), ExprKind::NotEndsWithCs),
+        ))
+    ))(i)
 }
 
-fn and_expr(i: &str) -> IResult<&str, Expr> {
+type ListOperator = fn(Box<Expr>, ExprList) -> ExprKind;
+
+fn list_operator(i: Input) -> IResult<Input, ListOperator> {
     alt((
-        map(
-            separated_pair(delim_expr, trim(tag("and")), or_expr),
-            |(first, second)| Expr::And(Box::new(first), Box::new(second)),
-        ),
-        predicate,
+        value(ExprKind::InCaseInsensitive as ListOperator, tag("in~")),
+        value(ExprKind::NotInCaseInsensitive as ListOperator, tag("!in~")),
+        value(ExprKind::In as ListOperator, keyword("in")),
+        value(ExprKind::NotIn as ListOperator, keyword("!in")),
+        value(ExprKind::HasAny as ListOperator, keyword("has_any")),
+        value(ExprKind::HasAll as ListOperator, keyword("has_all")),
     ))(i)
 }
 
-fn or_expr(i: &str) -> IResult<&str, Expr> {
+fn expr_list(i: Input) -> IResult<Input, ExprList> {
+    delimited(tag("("), alt((
+        map(terminated(separated_list1(tag(","), trim(expr)), peek(tag(")"))), ExprList::Values),
+        map(trim(parse_query), |q| ExprList::Tabular(Box::new(q)))
+    )), tag(")"))(i)
+}
+
+type BetweenOperator = fn(Box<Expr>, Box<Expr>, Box<Expr>) -> ExprKind;
+
+fn between_operator(i: Input) -> IResult<Input, BetweenOperator> {
     alt((
-        map(
-            separated_pair(and_expr, trim(tag("or")), or_expr),
-            |(first, second)| Expr::Or(Box::new(first), Box::new(second)),
-        ),
-        and_expr,
+        value(ExprKind::Between as BetweenOperator, keyword("between")),
+        value(ExprKind::NotBetween as BetweenOperator, keyword("!between")),
     ))(i)
 }
 
-pub fn expr(i: &str) -> IResult<&str, Expr> {
-    or_expr(i)
+fn additive_expr(i: Input) -> IResult<Input, Expr> {
+    expr_with_precedence(i, PRECEDENCE_ADDITIVE)
+}
+
+fn between_range(i: Input) -> IResult<Input, (Expr, Expr)> {
+    delimited(tag("("), separated_pair(trim(additive_expr), tag(".."), trim(additive_expr)), tag(")"))(i)
+}
+
+// Precedence levels of infix operators, from loosest to tightest binding
+const PRECEDENCE_OR: u8 = 1;
+const PRECEDENCE_AND: u8 = 2;
+const PRECEDENCE_PREDICATE: u8 = 3;
+const PRECEDENCE_ADDITIVE: u8 = 4;
+const PRECEDENCE_MULTIPLICATIVE: u8 = 5;
+
+enum InfixOperator {
+    Binary(BinaryOperator),
+    List(ListOperator),
+    Between(BetweenOperator)
 }
 
-fn as_operator(i: &str) -> IResult<&str, (Options, String)> {
+fn infix_operator(i: Input) -> IResult<Input, (InfixOperator, u8)> {
+    alt((
+        map(keyword("or"), |_| (InfixOperator::Binary(ExprKind::Or), PRECEDENCE_OR)),
+        map(keyword("and"), |_| (InfixOperator::Binary(ExprKind::And), PRECEDENCE_AND)),
+        map(list_operator, |o| (InfixOperator::List(o), PRECEDENCE_PREDICATE)),
+        map(between_operator, |o| (InfixOperator::Between(o), PRECEDENCE_PREDICATE)),
+        map(predicate_operator, |o| (InfixOperator::Binary(o), PRECEDENCE_PREDICATE)),
+        map(one_of("+-"), |o| (InfixOperator::Binary(match o {
+            '+' => ExprKind::Add,
+            _ => ExprKind::Substract
+        }), PRECEDENCE_ADDITIVE)),
+        map(one_of("*/%"), |o| (InfixOperator::Binary(match o {
+            '*' => ExprKind::Multiply,
+            '/' => ExprKind::Divide,
+            _ => ExprKind::Modulo
+        }), PRECEDENCE_MULTIPLICATIVE)),
+    ))(i)
+}
+
+/// Parses an expression by precedence climbing, only operators binding at least as tight as `min` are consumed
+fn expr_with_precedence(i: Input, min: u8) -> IResult<Input, Expr> {
+    let (mut i, mut acc) = unary_expr(i)?;
+    loop {
+        let (rest, (operator, precedence)) = match trim(infix_operator)(i) {
+            Ok((_, (_, precedence))) if precedence < min => return Ok((i, acc)),
+            Ok(x) => x,
+            Err(nom::Err::Error(_)) => return Ok((i, acc)),
+            Err(e) => return Err(e)
+        };
+
+        (i, acc) = match operator {
+            InfixOperator::Binary(o) => {
+                let (rest, right) = expr_with_precedence(rest, precedence + 1)?;
+                (rest, binary_expr(o, acc, right))
+            },
+            InfixOperator::List(o) => {
+                let (rest, (list, s)) = cut(spanned(expr_list))(rest)?;
+                let span = Span::new(acc.span.start, s.end);
+                (rest, Expr::new(o(Box::new(acc), list), span))
+            },
+            InfixOperator::Between(o) => {
+                let (rest, ((from, to), s)) = cut(spanned(between_range))(rest)?;
+                let span = Span::new(acc.span.start, s.end);
+                (rest, Expr::new(o(Box::new(acc), Box::new(from), Box::new(to)), span))
+            }
+        };
+    }
+}
+
+fn expr(i: Input) -> IResult<Input, Expr> {
+    expr_with_precedence(i, PRECEDENCE_OR)
+}
+
+fn as_operator(i: Input) -> IResult<Input, (Options, String)> {
     preceded(terminated(tag("as"), multispace1), map(
         pair(opt(terminated(options, multispace1)), identifier),
         |(o, a)| (o.unwrap_or_default(), a)
     ))(i)
 }
 
-fn consume_operator(i: &str) -> IResult<&str, Options> {
-    preceded(tag("consume"), options)(i)
+fn consume_operator(i: Input) -> IResult<Input, Options> {
+    preceded(keyword("consume"), map(opt(preceded(multispace1, options)), Option::unwrap_or_default))(i)
 }
 
-fn count_operator(i: &str) -> IResult<&str, ()> {
+fn count_operator(i: Input) -> IResult<Input, ()> {
     map(tag("count"), |_| ())(i)
 }
 
-fn datatable_operator(i: &str) -> IResult<&str, (Vec<(String, Type)>, Vec<Expr>)> {
+fn datatable_operator(i: Input) -> IResult<Input, (Vec<(String, Type)>, Vec<Expr>)> {
     preceded(terminated(tag("datatable"), multispace1), separated_pair(
         delimited(tag("("), type_mapping, tag(")")),
         multispace0,
@@ -340,29 +555,29 @@ fn datatable_operator(i: &str) -> IResult<&str, (Vec<(String, Type)>, Vec<Expr>)
     ))(i)
 }
 
-fn distinct_operator(i: &str) -> IResult<&str, Vec<String>> {
-    preceded(terminated(tag("distinct"), multispace1), separated_list1(
-        tag(","),
-        trim(identifier)
-    ))(i)
+fn distinct_operator(i: Input) -> IResult<Input, Option<Vec<(Option<String>, Expr)>>> {
+    preceded(terminated(tag("distinct"), multispace1), alt((
+        value(None, tag("*")),
+        map(separated_list1(tag(","), trim(named_expr)), Some)
+    )))(i)
 }
 
-fn evaluate_operator(i: &str) -> IResult<&str, (Options, String, Vec<Expr>)> {
+fn evaluate_operator(i: Input) -> IResult<Input, (Options, String, Vec<Expr>)> {
     preceded(terminated(tag("evaluate"), multispace1), tuple((
-        terminated(options, multispace1),
+        terminated(options, multispace0),
         terminated(identifier, multispace0),
         delimited(tag("("), separated_list0(tag(","), trim(expr)), tag(")"))
     )))(i)
 }
 
-fn extend_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
+fn extend_operator(i: Input) -> IResult<Input, Vec<(Option<String>, Expr)>> {
     preceded(terminated(tag("extend"), multispace1), separated_list0(
         tuple((multispace0, tag(","), multispace0)),
         map(separated_pair(identifier, trim(tag("=")), expr), |(n, e)| (Some(n), e)),
     ))(i)
 }
 
-fn externaldata_operator(i: &str) -> IResult<&str, (Vec<(String, Type)>, Vec<String>)> {
+fn externaldata_operator(i: Input) -> IResult<Input, (Vec<(String, Type)>, Vec<String>)> {
     preceded(terminated(tag("externaldata"), multispace1), separated_pair(
         delimited(tag("("), type_mapping, tag(")")),
         multispace0,
@@ -370,7 +585,7 @@ fn externaldata_operator(i: &str) -> IResult<&str, (Vec<(String, Type)>, Vec<Str
     ))(i)
 }
 
-fn facet_operator(i: &str) -> IResult<&str, (Vec<String>, Vec<Operator>)> {
+fn facet_operator(i: Input) -> IResult<Input, (Vec<String>, Vec<Operator>)> {
     preceded(terminated(separated_pair(tag("facet"), multispace1, tag_no_case("by")), multispace1), pair(
         separated_list0(tag(","), trim(identifier)),
         map(opt(preceded(terminated(tag("with"), multispace0), delimited(
@@ -381,28 +596,36 @@ fn facet_operator(i: &str) -> IResult<&str, (Vec<String>, Vec<Operator>)> {
     ))(i)
 }
 
-fn find_operator(i: &str) -> IResult<&str, (Options, (Option<Vec<Source>>, Expr), FindProjection)> {
+fn find_operator(i: Input) -> IResult<Input, (Options, (Option<Vec<Source>>, Expr), FindProjection)> {
     preceded(terminated(tag("find"), multispace1), tuple((
         terminated(options, multispace0),
         alt((
             map(separated_pair(
-                preceded(
-                    terminated(tag("in"), multispace1), 
-                    delimited(tag("("), separated_list1(tag(","), trim(source)), tag(")"))
-                ),
+                preceded(terminated(tag("in"), multispace1), table_list),
                 multispace1,
                 preceded(terminated(tag("where"), multispace1), expr)
             ), |(s, e)| (Some(s), e)),
-            map(expr, |e| (None, e))
+            map(preceded(opt(terminated(keyword("where"), multispace1)), expr), |e| (None, e))
         )),
         map(opt(preceded(multispace1, alt((
             map(tag("project-smart"), |_| FindProjection::ProjectSmart),
-            map(preceded(terminated(tag("project"), multispace1), separated_list1(trim(tag(",")), identifier)), |c| FindProjection::Project(c))
+            map(preceded(terminated(tag("project"), multispace1), separated_list1(trim(tag(",")), identifier)), FindProjection::Project)
         )))), |x| x.unwrap_or(FindProjection::ProjectSmart))
     )))(i)
 }
 
-fn fork_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Vec<Operator>)>> {
+/// Tables searched by `find` and `search`, names may contain wildcards like `T*`
+fn table_list(i: Input) -> IResult<Input, Vec<Source>> {
+    delimited(tag("("), separated_list1(tag(","), trim(alt((
+        map(
+            spanned(verify(wildcard_identifier, |n: &str| n.contains('*'))),
+            |(n, s)| Source::new(SourceKind::Reference(None, None, n), s)
+        ),
+        source
+    )))), tag(")"))(i)
+}
+
+fn fork_operator(i: Input) -> IResult<Input, Vec<(Option<String>, Vec<Operator>)>> {
     preceded(terminated(tag("fork"), multispace1), separated_list1(
         tag(","),
         trim(alt((
@@ -416,11 +639,19 @@ fn fork_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Vec<Operator>)>>
     ))(i)
 }
 
-fn getschema_operator(i: &str) -> IResult<&str, ()> {
-    map(terminated(tag("getschema"), multispace1), |_| ())(i)
+fn getschema_operator(i: Input) -> IResult<Input, ()> {
+    map(keyword("getschema"), |_| ())(i)
 }
 
-fn join_operator(i: &str) -> IResult<&str, (Options, TabularExpression, Vec<String>)> {
+fn invoke_operator(i: Input) -> IResult<Input, (String, Vec<Expr>)> {
+    preceded(terminated(keyword("invoke"), multispace1), separated_pair(
+        identifier,
+        multispace0,
+        delimited(tag("("), separated_list0(tag(","), trim(expr)), tag(")"))
+    ))(i)
+}
+
+fn join_operator(i: Input) -> IResult<Input, (Options, TabularExpression, Vec<String>)> {
     preceded(terminated(tag("join"), multispace1), tuple((
         terminated(options, multispace0),
         terminated(delimited(tag("("), parse_query, tag(")")), multispace0),
@@ -431,7 +662,7 @@ fn join_operator(i: &str) -> IResult<&str, (Options, TabularExpression, Vec<Stri
     )))(i)
 }
 
-fn lookup_operator(i: &str) -> IResult<&str, (Options, TabularExpression, Vec<String>)> {
+fn lookup_operator(i: Input) -> IResult<Input, (Options, TabularExpression, Vec<String>)> {
     preceded(terminated(tag("lookup"), multispace1), tuple((
         terminated(options, multispace0),
         terminated(delimited(tag("("), parse_query, tag(")")), multispace0),
@@ -442,12 +673,12 @@ fn lookup_operator(i: &str) -> IResult<&str, (Options, TabularExpression, Vec<St
     )))(i)
 }
 
-fn mv_apply_operator(i: &str) -> IResult<&str, (Vec<((String, String), Option<Type>)>, Vec<Operator>)> {
+fn mv_apply_operator(i: Input) -> IResult<Input, (Vec<((String, String), Option<Type>)>, Vec<Operator>)> {
     preceded(terminated(tag("mv-apply"), multispace1), tuple((
         separated_list1(tag(","), trim(pair(
             separated_pair(trim(identifier), tag("="), trim(identifier)),
             opt(preceded(
-                tuple((multispace1, tag("to"), multispace1, tag("typeof"), multispace0)),
+                tuple((multispace0, keyword("to"), multispace1, tag("typeof"), multispace0)),
                 delimited(tag("("), trim(type_tag), tag(")"))
             ))
         ))),
@@ -458,11 +689,60 @@ fn mv_apply_operator(i: &str) -> IResult<&str, (Vec<((String, String), Option<Ty
     )))(i)
 }
 
-fn mv_expand_operator(i: &str) -> IResult<&str, String> {
-    preceded(terminated(tag("mv-expand"), multispace1), identifier)(i)
+/// Options limited to `names`, for operators where `name = expr` starts a column
+fn known_options<'a>(names: &'a [&'a str]) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, Options> {
+    map(many0(terminated(separated_pair(
+        verify(identifier, |n: &str| names.contains(&n)),
+        trim(tag("=")),
+        option_literal
+    ), multispace1)), |x| x.into_iter().collect())
+}
+
+fn named_expr(i: Input) -> IResult<Input, (Option<String>, Expr)> {
+    pair(opt(terminated(identifier, trim(terminated(tag("="), not(one_of("=~")))))), expr)(i)
 }
 
-fn parse_operator(i: &str) -> IResult<&str, (Options, Expr, Vec<PatternToken>)> {
+fn make_series_operator(i: Input) -> IResult<Input, (Options, MakeSeries)> {
+    map(preceded(terminated(tag("make-series"), multispace1), tuple((
+        known_options(&["kind"]),
+        separated_list1(tag(","), trim(pair(
+            named_expr,
+            opt(preceded(tuple((multispace0, keyword("default"), trim(tag("=")))), expr))
+        ))),
+        preceded(pair(keyword("on"), multispace1), expr),
+        opt(preceded(tuple((multispace0, keyword("from"), multispace1)), expr)),
+        opt(preceded(tuple((multispace0, keyword("to"), multispace1)), expr)),
+        preceded(tuple((multispace0, keyword("step"), multispace1)), expr),
+        map(opt(preceded(
+            tuple((multispace0, keyword("by"), multispace1)),
+            separated_list1(tag(","), trim(named_expr))
+        )), |b| b.unwrap_or_default())
+    ))), |(o, a, on, from, to, step, by)| (o, MakeSeries {
+        aggregations: a.into_iter().map(|((n, e), d)| (n, e, d)).collect(),
+        on,
+        from,
+        to,
+        step,
+        by
+    }))(i)
+}
+
+fn mv_expand_operator(i: Input) -> IResult<Input, (Options, Vec<(Option<String>, Expr, Option<Type>)>, Option<u32>)> {
+    preceded(terminated(tag("mv-expand"), multispace1), tuple((
+        known_options(&["bagexpansion", "with_itemindex"]),
+        separated_list1(tag(","), trim(tuple((
+            opt(terminated(identifier, trim(terminated(tag("="), not(one_of("=~")))))),
+            expr,
+            opt(preceded(
+                tuple((multispace0, keyword("to"), multispace1, tag("typeof"), multispace0)),
+                delimited(tag("("), trim(type_tag), tag(")"))
+            ))
+        )))),
+        opt(preceded(tuple((keyword("limit"), multispace1)), u32))
+    )))(i)
+}
+
+fn parse_operator(i: Input) -> IResult<Input, (Options, Expr, Vec<PatternToken>)> {
     preceded(terminated(tag("parse"), multispace1), tuple((
         terminated(options, multispace0),
         terminated(expr, multispace0),
@@ -470,7 +750,7 @@ fn parse_operator(i: &str) -> IResult<&str, (Options, Expr, Vec<PatternToken>)>
     )))(i)
 }
 
-fn parse_where_operator(i: &str) -> IResult<&str, (Options, Expr, Vec<PatternToken>)> {
+fn parse_where_operator(i: Input) -> IResult<Input, (Options, Expr, Vec<PatternToken>)> {
     preceded(terminated(tag("parse-where"), multispace1), tuple((
         terminated(options, multispace0),
         terminated(expr, multispace0),
@@ -478,7 +758,7 @@ fn parse_where_operator(i: &str) -> IResult<&str, (Options, Expr, Vec<PatternTok
     )))(i)
 }
 
-fn parse_kv_operator(i: &str) -> IResult<&str, (Expr, Vec<(String, Type)>, Options)> {
+fn parse_kv_operator(i: Input) -> IResult<Input, (Expr, Vec<(String, Type)>, Options)> {
     preceded(terminated(tag("parse-kv"), multispace1), tuple((
         terminated(expr, multispace0),
         terminated(preceded(terminated(tag("as"), multispace0), delimited(tag("("), type_mapping, tag(")"))), multispace0),
@@ -486,18 +766,18 @@ fn parse_kv_operator(i: &str) -> IResult<&str, (Expr, Vec<(String, Type)>, Optio
     ))(i)
 }
 
-fn partition_operator(i: &str) -> IResult<&str, (Options, String, (Option<Source>, Vec<Operator>))> {
+fn partition_operator(i: Input) -> IResult<Input, (Options, String, (Option<Source>, Vec<Operator>))> {
     preceded(terminated(tag("partition"), multispace1), tuple((
         terminated(options, multispace0),
         preceded(terminated(tag("by"), multispace1), identifier),
-        alt((
-            map(preceded(multispace0, delimited(tag("("), separated_list0(tag("|"), trim(operator)), tag(")"))), |o| (None, o)),
+        preceded(multispace0, alt((
+            map(delimited(tag("("), separated_list0(tag("|"), trim(operator)), tag(")")), |o| (None, o)),
             map(separated_pair(source, multispace0, many0(preceded(tag("|"), trim(operator)))), |(s, o)| (Some(s), o))
-        ))
+        )))
     )))(i)
 }
 
-fn print_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
+fn print_operator(i: Input) -> IResult<Input, Vec<(Option<String>, Expr)>> {
     preceded(terminated(tag("print"), multispace0), separated_list0(
         tag(","),
         trim(alt((
@@ -507,7 +787,7 @@ fn print_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
     ))(i)
 }
 
-fn project_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
+fn project_operator(i: Input) -> IResult<Input, Vec<(Option<String>, Expr)>> {
     preceded(terminated(tag("project"), multispace1), separated_list0(
         tag(","),
         trim(alt((
@@ -517,28 +797,28 @@ fn project_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
     ))(i)
 }
 
-fn project_away_operator(i: &str) -> IResult<&str, Vec<String>> {
+fn project_away_operator(i: Input) -> IResult<Input, Vec<String>> {
     preceded(terminated(tag("project-away"), multispace1), separated_list1(
         tag(","),
         trim(identifier)
     ))(i)
 }
 
-fn project_keep_operator(i: &str) -> IResult<&str, Vec<String>> {
+fn project_keep_operator(i: Input) -> IResult<Input, Vec<String>> {
     preceded(terminated(tag("project-keep"), multispace1), separated_list1(
         tag(","),
         trim(identifier)
     ))(i)
 }
 
-fn project_rename_operator(i: &str) -> IResult<&str, Vec<(String, String)>> {
+fn project_rename_operator(i: Input) -> IResult<Input, Vec<(String, String)>> {
     preceded(terminated(tag("project-rename"), multispace1), separated_list1(
         tag(","),
         separated_pair(trim(identifier), tag("="), trim(identifier))
     ))(i)
 }
 
-fn project_reorder_operator(i: &str) -> IResult<&str, Vec<(String, Option<(bool, bool)>)>> {
+fn project_reorder_operator(i: Input) -> IResult<Input, Vec<(String, Option<(bool, bool)>)>> {
     preceded(terminated(tag("project-reorder"), multispace1), separated_list1(
         tag(","),
         trim(pair(wildcard_identifier, opt(preceded(multispace1, alt((
@@ -550,11 +830,11 @@ fn project_reorder_operator(i: &str) -> IResult<&str, Vec<(String, Option<(bool,
     ))(i)
 }
 
-fn where_operator(i: &str) -> IResult<&str, Expr> {
+fn where_operator(i: Input) -> IResult<Input, Expr> {
     preceded(terminated(tag("where"), multispace1), expr)(i)
 }
 
-fn range_operator(i: &str) -> IResult<&str, (String, Expr, Expr, Expr)> {
+fn range_operator(i: Input) -> IResult<Input, (String, Expr, Expr, Expr)> {
     preceded(terminated(tag("range"), multispace1), tuple((
         terminated(identifier, multispace1),
         terminated(preceded(terminated(tag("from"), multispace1), expr), multispace1),
@@ -563,7 +843,7 @@ fn range_operator(i: &str) -> IResult<&str, (String, Expr, Expr, Expr)> {
     )))(i)
 }
 
-fn reduce_operator(i: &str) -> IResult<&str, (Options, Expr, Option<Options>)> {
+fn reduce_operator(i: Input) -> IResult<Input, (Options, Expr, Option<Options>)> {
     preceded(terminated(tag("reduce"), multispace1), tuple((
         terminated(options, multispace0),
         terminated(preceded(terminated(tag("by"), multispace1), expr), multispace0),
@@ -571,33 +851,112 @@ fn reduce_operator(i: &str) -> IResult<&str, (Options, Expr, Option<Options>)> {
     )))(i)
 }
 
-fn render_operator(i: &str) -> IResult<&str, (String, Option<Options>)> {
+fn render_operator(i: Input) -> IResult<Input, (String, Option<Options>)> {
     preceded(terminated(tag("render"), multispace1), tuple((
         terminated(identifier, multispace0),
         opt(preceded(terminated(tag("with"), multispace1), delimited(tag("("), options_with_comma_and_quoted, tag(")")))))
     ))(i)
 }
 
-fn sample_operator(i: &str) -> IResult<&str, u32> {
+fn sample_operator(i: Input) -> IResult<Input, u32> {
     preceded(
         terminated(tag("sample"), multispace1),
         u32
     )(i)
 }
 
-fn sample_distinct_operator(i: &str) -> IResult<&str, (u32, String)> {
+fn sample_distinct_operator(i: Input) -> IResult<Input, (u32, String)> {
     preceded(
         terminated(tag("sample-distinct"), multispace1),
         separated_pair(
             u32,
-            delimited(multispace1, tag("by"), multispace1),
+            delimited(multispace1, alt((keyword("of"), keyword("by"))), multispace1),
             identifier
         )
     )(i)
 }
 
-fn serialize_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
-    preceded(terminated(tag("serialize"), multispace1), separated_list0(
+fn scan_step(i: Input) -> IResult<Input, ScanStep> {
+    map(tuple((
+        preceded(pair(keyword("step"), multispace1), identifier),
+        opt(preceded(tuple((multispace1, keyword("output"), trim(tag("=")))), alt((
+            value(ScanOutput::All, keyword("all")),
+            value(ScanOutput::Last, keyword("last")),
+            value(ScanOutput::None, keyword("none"))
+        )))),
+        preceded(trim(tag(":")), expr),
+        map(opt(preceded(
+            trim(tag("=>")),
+            separated_list1(tag(","), trim(separated_pair(identifier, trim(tag("=")), expr)))
+        )), |a| a.unwrap_or_default())
+    )), |(name, output, condition, assignments)| ScanStep {
+        name,
+        output: output.unwrap_or_default(),
+        condition,
+        assignments
+    })(i)
+}
+
+fn scan_operator(i: Input) -> IResult<Input, (Options, Vec<(String, Type, Option<Expr>)>, Vec<ScanStep>)> {
+    preceded(terminated(tag("scan"), multispace1), tuple((
+        known_options(&["with_match_id"]),
+        map(opt(terminated(preceded(pair(keyword("declare"), multispace0), delimited(
+            tag("("),
+            separated_list1(tag(","), trim(tuple((
+                identifier,
+                preceded(trim(tag(":")), type_tag),
+                opt(preceded(trim(tag("=")), expr))
+            )))),
+            tag(")")
+        )), multispace0)), |d| d.unwrap_or_default()),
+        preceded(pair(keyword("with"), multispace0), delimited(
+            tag("("),
+            trim(terminated(separated_list1(tag(";"), trim(scan_step)), opt(tag(";")))),
+            tag(")")
+        ))
+    )))(i)
+}
+
+/// Term of a search predicate, `Col:"term"` is a shorthand for `Col has "term"`
+fn search_term(i: Input) -> IResult<Input, Expr> {
+    alt((
+        map(
+            spanned(separated_pair(spanned(identifier), trim(tag(":")), spanned(string))),
+            |(((c, cs), (t, ts)), s)| Expr::new(ExprKind::Has(
+                Box::new(Expr::new(ExprKind::Ident(c), cs)),
+                Box::new(Expr::new(ExprKind::Literal(Literal::String(t, false)), ts))
+            ), s)
+        ),
+        |i| expr_with_precedence(i, PRECEDENCE_PREDICATE),
+        delimited(tag("("), trim(search_predicate), tag(")"))
+    ))(i)
+}
+
+fn search_predicate(i: Input) -> IResult<Input, Expr> {
+    let conjunction = |i| map(
+        separated_list1(trim(keyword("and")), search_term),
+        |t| t.into_iter().reduce(|a, b| binary_expr(ExprKind::And, a, b)).unwrap()
+    )(i);
+    map(
+        separated_list1(trim(keyword("or")), conjunction),
+        |t| t.into_iter().reduce(|a, b| binary_expr(ExprKind::Or, a, b)).unwrap()
+    )(i)
+}
+
+fn search_operator(i: Input) -> IResult<Input, (Options, Expr)> {
+    preceded(terminated(keyword("search"), multispace1), pair(known_options(&["kind"]), search_predicate))(i)
+}
+
+fn search_source(i: Input) -> IResult<Input, (Options, Option<Vec<Source>>, Expr)> {
+    preceded(terminated(keyword("search"), multispace1), tuple((
+        known_options(&["kind"]),
+        opt(terminated(preceded(pair(keyword("in"), multispace0), table_list), multispace0)),
+        search_predicate
+    )))(i)
+}
+
+fn serialize_operator(i: Input) -> IResult<Input, Vec<(Option<String>, Expr)>> {
+    preceded(keyword("serialize"), separated_list0(
         tag(","),
         trim(map(
             separated_pair(identifier, trim(tag("=")), expr),
@@ -606,7 +965,7 @@ fn serialize_operator(i: &str) -> IResult<&str, Vec<(Option<String>, Expr)>> {
     ))(i)
 }
 
-fn summarize_operator(i: &str) -> IResult<&str, (Vec<(Option<String>, Expr)>, Vec<Expr>)> {
+fn summarize_operator(i: Input) -> IResult<Input, (Vec<(Option<String>, Expr)>, Vec<Expr>)> {
     preceded(terminated(tag("summarize"), multispace1), pair(
         separated_list0(tag(","), trim(alt((
             map(separated_pair(identifier, trim(tag("=")), expr), |(n, e)| (Some(n), e)),
@@ -619,49 +978,95 @@ fn summarize_operator(i: &str) -> IResult<&str, (Vec<(Option<String>, Expr)>, Ve
     ))(i)
 }
 
-fn sort_operator(i: &str) -> IResult<&str, Vec<String>> {
-    preceded(tuple((tag("sort"), multispace1, tag("by"))), separated_list1(
+/// Sort key like `a desc nulls last`, descending by default with nulls first only when ascending
+fn sort_key(i: Input) -> IResult<Input, (Expr, bool, bool)> {
+    map(tuple((
+        expr,
+        opt(preceded(multispace0, alt((
+            value(true, keyword("asc")),
+            value(false, keyword("desc"))
+        )))),
+        opt(preceded(tuple((multispace0, keyword("nulls"), multispace1)), alt((
+            value(true, keyword("first")),
+            value(false, keyword("last"))
+        ))))
+    )), |(e, s, o)| (e, s.unwrap_or(false), o.unwrap_or(s.unwrap_or(false))))(i)
+}
+
+fn sort_operator(i: Input) -> IResult<Input, Vec<(Expr, bool, bool)>> {
+    preceded(tuple((alt((keyword("sort"), keyword("order"))), multispace1, keyword("by"))), separated_list1(
         tag(","),
-        trim(identifier)
+        trim(sort_key)
     ))(i)
 }
 
-fn take_operator(i: &str) -> IResult<&str, u32> {
+fn take_operator(i: Input) -> IResult<Input, u32> {
     preceded(
         terminated(alt((tag("take"), tag("limit"))), multispace1),
         u32
     )(i)
 }
 
-fn top_operator(i: &str) -> IResult<&str, (u32, Expr, bool, bool)> {
+fn top_operator(i: Input) -> IResult<Input, (u32, Expr, bool, bool)> {
     map(preceded(
         terminated(tag("top"), multispace1),
         tuple((
             terminated(u32, multispace1),
-            preceded(terminated(tag("by"), multispace1), trim(expr)),
-            opt(terminated(alt((
-                value(true, tag("asc")),
-                value(false, tag("desc"))
-            )), multispace0)),
-            opt(preceded(terminated(tag("nulls"), multispace1), alt((
-                value(true, tag("first")),
-                value(false, tag("last"))
-            )))),
+            preceded(terminated(tag("by"), multispace1), trim(sort_key))
         ))
-    ), |(n, e, s, o)| (n, e, s.unwrap_or(false), o.unwrap_or(s.unwrap_or(false))))(i)
+    ), |(n, (e, s, o))| (n, e, s, o))(i)
 }
 
-fn union_operator(i: &str) -> IResult<&str, (Options, Vec<Source>)> {
+fn top_hitters_operator(i: Input) -> IResult<Input, (u32, (Option<String>, Expr), Option<Expr>)> {
+    preceded(terminated(tag("top-hitters"), multispace1), tuple((
+        terminated(u32, multispace1),
+        preceded(pair(keyword("of"), multispace1), named_expr),
+        opt(preceded(tuple((multispace0, keyword("by"), multispace1)), expr))
+    )))(i)
+}
+
+fn top_nested_others(i: Input) -> IResult<Input, Expr> {
+    preceded(tuple((multispace0, keyword("with"), multispace1, keyword("others"), trim(tag("=")))), expr)(i)
+}
+
+fn top_nested_operator(i: Input) -> IResult<Input, Vec<TopNested>> {
+    // the others value may precede or follow the aggregation
+    separated_list1(tag(","), trim(map(tuple((
+        preceded(terminated(tag("top-nested"), multispace1), opt(terminated(u32, multispace1))),
+        preceded(pair(keyword("of"), multispace1), named_expr),
+        opt(top_nested_others),
+        preceded(tuple((multispace0, keyword("by"), multispace1)), named_expr),
+        opt(preceded(multispace1, alt((
+            value(true, keyword("asc")),
+            value(false, keyword("desc"))
+        )))),
+        opt(top_nested_others)
+    )), |(count, (name, expr), others, (aggregation_name, aggregation), asc, others_after)| TopNested {
+        count,
+        name,
+        expr,
+        others: others.or(others_after),
+        aggregation_name,
+        aggregation,
+        asc: asc.unwrap_or(false)
+    })))(i)
+}
+
+fn union_operator(i: Input) -> IResult<Input, (Options, Vec<TabularExpression>)> {
     preceded(terminated(tag("union"), multispace1), tuple((
         terminated(options, multispace0),
         separated_list1(trim(tag(",")), alt((
-            delimited(tag("("), trim(source), tag(")")),
-            map(table_reference, |(c, d, t)| Source::Reference(c, d, t))
+            delimited(tag("("), trim(parse_query), tag(")")),
+            map(spanned(table_reference), |((c, d, t), span)| TabularExpression {
+                source: Source::new(SourceKind::Reference(c, d, t), span),
+                operators: Vec::new(),
+                span
+            })
         )))
     )))(i)
 }
 
-fn table_reference(i: &str) -> IResult<&str, (Option<String>, Option<String>, String)> {
+fn table_reference(i: Input) -> IResult<Input, (Option<String>, Option<String>, String)> {
     let (i, cluster) = opt(delimited(
         tag_no_case("cluster"),
         delimited(tag("("), trim(string), tag(")")),
@@ -674,103 +1079,212 @@ fn table_reference(i: &str) -> IResult<&str, (Option<String>, Option<String>, St
     ))(i)?;
     let (i, table) = alt((
         map(preceded(tag_no_case("table"), delimited(tag("("), trim(string), tag(")"))), |t| t),
-        map(identifier, |t| t)
+        // a name followed by arguments is a function call instead
+        terminated(identifier, not(pair(multispace0, tag("("))))
     ))(i)?;
     Ok((i, (cluster, database, table)))
 }
 
-fn operator(i: &str) -> IResult<&str, Operator> {
-    alt((
+fn operator(i: Input) -> IResult<Input, Operator> {
+    map(spanned(alt((
         alt((
-            map(as_operator, |(o, a)| Operator::As(o, a)),
-            map(consume_operator, |o| Operator::Consume(o)),
-            map(count_operator, |_| Operator::Count),
-            map(distinct_operator, |c| Operator::Distinct(c)),
-            map(evaluate_operator, |(o, n, x)| Operator::Evaluate(o, n, x)),
-            map(extend_operator, |e| Operator::Extend(e)),
-            map(facet_operator, |(a, g)| Operator::Facet(a, g)),
-            map(fork_operator, |f| Operator::Fork(f)),
-            map(getschema_operator, |_| Operator::Getschema),
-            map(join_operator, |(o, a, g)| Operator::Join(o, a, g)),
-            map(lookup_operator, |(o, a, g)| Operator::Lookup(o, a, g)),
+            map(as_operator, |(o, a)| OperatorKind::As(o, a)),
+            map(consume_operator, OperatorKind::Consume),
+            map(count_operator, |_| OperatorKind::Count),
+            map(distinct_operator, OperatorKind::Distinct),
+            map(evaluate_operator, |(o, n, x)| OperatorKind::Evaluate(o, n, x)),
+            map(extend_operator, OperatorKind::Extend),
+            map(facet_operator, |(a, g)| OperatorKind::Facet(a, g)),
+            map(fork_operator, OperatorKind::Fork),
+            map(getschema_operator, |_| OperatorKind::Getschema),
+            map(invoke_operator, |(n, a)| OperatorKind::Invoke(n, a)),
+            map(join_operator, |(o, a, g)| OperatorKind::Join(o, a, g)),
+            map(lookup_operator, |(o, a, g)| OperatorKind::Lookup(o, a, g)),
         )),
         alt((
-            map(mv_apply_operator, |(a, g)| Operator::MvApply(a, g)),
-            map(mv_expand_operator, |e| Operator::MvExpand(e)),
+            map(make_series_operator, |(o, m)| OperatorKind::MakeSeries(o, m)),
+            map(mv_apply_operator, |(a, g)| OperatorKind::MvApply(a, g)),
+            map(mv_expand_operator, |(o, c, l)| OperatorKind::MvExpand(o, c, l)),
         )),
         alt((
-            map(project_operator, |p| Operator::Project(p)),
-            map(project_away_operator, |p| Operator::ProjectAway(p)),
-            map(project_keep_operator, |p| Operator::ProjectKeep(p)),
-            map(project_rename_operator, |p| Operator::ProjectRename(p)),
-            map(project_reorder_operator, |p| Operator::ProjectReorder(p))
+            map(project_operator, OperatorKind::Project),
+            map(project_away_operator, OperatorKind::ProjectAway),
+            map(project_keep_operator, OperatorKind::ProjectKeep),
+            map(project_rename_operator, OperatorKind::ProjectRename),
+            map(project_reorder_operator, OperatorKind::ProjectReorder)
         )),
         alt((
-            map(parse_operator, |(o, e, p)| Operator::Parse(o, e, p)),
-            map(parse_where_operator, |(o, e, p)| Operator::ParseWhere(o, e, p)),
-            map(parse_kv_operator, |(e, t, o)| Operator::ParseKV(e, t, o)),
+            map(parse_operator, |(o, e, p)| OperatorKind::Parse(o, e, p)),
+            map(parse_where_operator, |(o, e, p)| OperatorKind::ParseWhere(o, e, p)),
+            map(parse_kv_operator, |(e, t, o)| OperatorKind::ParseKV(e, t, o)),
         )),
-        map(partition_operator, |(o, a, (s, g))| Operator::Partition(o, a, s, g)),
-        map(reduce_operator, |(o, e, p)| Operator::Reduce(o, e, p)),
-        map(render_operator, |(v, p)| Operator::Render(v, p)),
+        map(partition_operator, |(o, a, (s, g))| OperatorKind::Partition(o, a, s, g)),
+        map(reduce_operator, |(o, e, p)| OperatorKind::Reduce(o, e, p)),
+        map(render_operator, |(v, p)| OperatorKind::Render(v, p)),
         alt((
-            map(sample_operator, |s| Operator::Sample(s)),
-            map(sample_distinct_operator, |(s, c)| Operator::SampleDistinct(s, c))
+            map(sample_operator, OperatorKind::Sample),
+            map(sample_distinct_operator, |(s, c)| OperatorKind::SampleDistinct(s, c))
         )),
         alt((
-            map(serialize_operator, |e| Operator::Serialize(e)),
-            map(summarize_operator, |(a, g)| Operator::Summarize(a, g)),
-            map(sort_operator, |o| Operator::Sort(o)),
+            map(scan_operator, |(o, d, s)| OperatorKind::Scan(o, d, s)),
+            map(search_operator, |(o, e)| OperatorKind::Search(o, e))
         )),
         alt((
-            map(take_operator, |t| Operator::Take(t)),
-            map(top_operator, |(n, e, s, o)| Operator::Top(n, e, s, o))
+            map(serialize_operator, OperatorKind::Serialize),
+            map(summarize_operator, |(a, g)| OperatorKind::Summarize(a, g)),
+            map(sort_operator, OperatorKind::Sort),
         )),
-        map(union_operator, |(o, s)| Operator::Union(o, s)),
-        map(where_operator, |e| Operator::Where(e))
-    ))(i)
+        alt((
+            map(take_operator, OperatorKind::Take),
+            map(top_operator, |(n, e, s, o)| OperatorKind::Top(n, e, s, o)),
+            map(top_hitters_operator, |(n, e, b)| OperatorKind::TopHitters(n, e, b)),
+            map(top_nested_operator, OperatorKind::TopNested)
+        )),
+        map(union_operator, |(o, s)| OperatorKind::Union(o, s)),
+        map(where_operator, OperatorKind::Where)
+    ))), |(o, s)| Operator::new(o, s))(i)
+}
+
+fn call_source(i: Input) -> IResult<Input, (String, Vec<Expr>)> {
+    let (rest, name) = identifier(i)?;
+    // the syntax of other sources and table references takes precedence
+    if ["cluster", "database", "datatable", "externaldata", "print", "table"].contains(&name.to_lowercase().as_str()) {
+        return Err(nom::Err::Error(Error::expected(i, "function")));
+    }
+    preceded(multispace0, delimited(tag("("), separated_list0(tag(","), trim(expr)), tag(")")))(rest)
+        .map(|(rest, args)| (rest, (name, args)))
+}
+
+fn source(i: Input) -> IResult<Input, Source> {
+    map(spanned(alt((
+        map(call_source, |(n, a)| SourceKind::Call(n, a)),
+        map(datatable_operator, |(a, g)| SourceKind::Datatable(a, g)),
+        map(externaldata_operator, |(t, c)| SourceKind::Externaldata(t, c)),
+        map(find_operator, |(o, (s, e), p)| SourceKind::Find(o, s, e, p)),
+        map(print_operator, SourceKind::Print),
+        map(range_operator, |(c, f, t, s)| SourceKind::Range(c, f, t, s)),
+        map(search_source, |(o, s, e)| SourceKind::Search(o, s, e)),
+        map(union_operator, |(o, s)| SourceKind::Union(o, s)),
+        map(table_reference, |(c, d, t)| SourceKind::Reference(c, d, t))
+    ))), |(x, s)| Source::new(x, s))(i)
+}
+
+fn parse_query(i: Input) -> IResult<Input, TabularExpression> {
+    map(spanned(separated_pair(source, multispace0, many0(preceded(tag("|"), trim(operator))))),
+    |((source, operators), span)| TabularExpression {
+        source,
+        operators,
+        span
+    })(i)
 }
 
-fn source(i: &str) -> IResult<&str, Source> {
-    alt((
-        map(datatable_operator, |(a, g)| Source::Datatable(a, g)),
-        map(externaldata_operator, |(t, c)| Source::Externaldata(t, c)),
-        map(find_operator, |(o, (s, e), p)| Source::Find(o, s, e, p)),
-        map(print_operator, |e| Source::Print(e)),
-        map(range_operator, |(c, f, t, s)| Source::Range(c, f, t, s)),
-        map(union_operator, |(o, s)| Source::Union(o, s)),
-        map(table_reference, |(c, d, t)| Source::Reference(c, d, t))
+fn parameter(i: Input) -> IResult<Input, (String, ParameterType, Option<Expr>)> {
+    tuple((
+        identifier,
+        preceded(trim(tag(":")), alt((
+            map(delimited(tag("("), trim(tag("*")), tag(")")), |_| ParameterType::Tabular(None)),
+            map(delimited(tag("("), type_mapping, tag(")")), |t| ParameterType::Tabular(Some(t))),
+            map(type_tag, ParameterType::Scalar)
+        ))),
+        opt(preceded(trim(tag("=")), expr))
     ))(i)
 }
 
-pub fn parse_query(i: &str) -> IResult<&str, TabularExpression> {
-    map(separated_pair(source, multispace0, many0(preceded(tag("|"), trim(operator)))),
-    |(source, operators)| TabularExpression {
-        source,
-        operators
+fn function(i: Input) -> IResult<Input, Function> {
+    map(tuple((
+        opt(terminated(keyword("view"), multispace0)),
+        delimited(tag("("), separated_list0(tag(","), trim(parameter)), tag(")")),
+        preceded(multispace0, delimited(tag("{"), trim(pair(
+            many0(terminated(parse_let, trim(tag(";")))),
+            terminated(alt((
+                map(terminated(expr, peek(pair(multispace0, alt((tag(";"), tag("}")))))), LetExpression::Scalar),
+                map(parse_query, LetExpression::Tabular),
+            )), opt(preceded(multispace0, tag(";"))))
+        )), tag("}")))
+    )), |(view, parameters, (statements, body))| Function {
+        parameters,
+        statements,
+        body: Box::new(body),
+        view: view.is_some()
     })(i)
 }
 
-fn parse_let(i: &str) -> IResult<&str, (String, LetExpression)> {
+fn parse_let(i: Input) -> IResult<Input, (String, LetExpression)> {
     preceded(
         terminated(tag("let"), multispace1),
         separated_pair(
             trim(identifier),
             tag("="),
             trim(alt((
-                map(expr, |e| LetExpression::Scalar(e)),
-                map(parse_query, |e| LetExpression::Tabular(e)),
+                map(function, LetExpression::Function),
+                map(
+                    terminated(
+                        preceded(pair(keyword("materialize"), multispace0), delimited(tag("("), trim(parse_query), tag(")"))),
+                        peek(pair(multispace0, alt((tag(";"), tag("}"), eof))))
+                    ),
+                    LetExpression::Materialize
+                ),
+                map(terminated(expr, peek(pair(multispace0, alt((tag(";"), eof))))), LetExpression::Scalar),
+                map(parse_query, LetExpression::Tabular),
             )))
         )
     )(i)
 }
 
-pub fn parse(i: &str) -> IResult<&str, Vec<Statement>> {
-    separated_list1(
-        tag(";"),
-        trim(alt((
-            map(parse_let, |(n, e)| Statement::Let(n, e)),
-            map(parse_query, |e| Statement::TabularExpression(e)),
-        ))),
+fn parse_query_parameters(i: Input) -> IResult<Input, Vec<(String, Type, Option<Expr>)>> {
+    preceded(
+        tuple((keyword("declare"), multispace1, tag("query_parameters"), multispace0)),
+        delimited(tag("("), separated_list1(tag(","), trim(tuple((
+            identifier,
+            preceded(trim(tag(":")), type_tag),
+            opt(preceded(trim(tag("=")), expr))
+        )))), tag(")"))
+    )(i)
+}
+
+fn parse_set(i: Input) -> IResult<Input, (String, Option<Expr>)> {
+    preceded(
+        pair(keyword("set"), multispace1),
+        pair(identifier, opt(preceded(trim(tag("=")), expr)))
+    )(i)
+}
+
+fn statement(i: Input) -> IResult<Input, Statement> {
+    alt((
+        map(parse_let, |(n, e)| Statement::Let(n, e)),
+        map(parse_query_parameters, Statement::QueryParameters),
+        map(parse_set, |(n, v)| Statement::Set(n, v)),
+        map(parse_query, Statement::TabularExpression),
+    ))(i)
+}
+
+fn statements(i: Input) -> IResult<Input, Vec<Statement>> {
+    terminated(
+        separated_list1(tag(";"), trim(statement)),
+        opt(terminated(tag(";"), multispace0))
     )(i)
 }
+
+/// Parses the remainder of a query after the last complete statement to find the cause of the failure
+fn remainder(i: Input) -> IResult<Input, ()> {
+    alt((
+        value((), preceded(tag("|"), trim(operator))),
+        value((), preceded(tag(";"), trim(statement)))
+    ))(i)
+}
+
+pub fn parse(i: &str) -> Result<Vec<Statement>, ParseError> {
+    let (rest, statements) = statements(Input::new(i)).map_err(|e| match e {
+        nom::Err::Error(e) | nom::Err::Failure(e) => ParseError::from(e),
+        nom::Err::Incomplete(_) => unreachable!("complete parsers never return incomplete")
+    })?;
+
+    if rest.fragment().is_empty() {
+        return Ok(statements);
+    }
+
+    Err(match remainder(rest) {
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => ParseError::from(e),
+        _ => ParseError::from(Error { input: rest, expected: Vec::new() })
+    })
+}
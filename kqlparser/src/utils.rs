@@ -1,15 +1,27 @@
-use nom::bytes::complete::{tag, take_while1};
-use nom::character::complete::{i64, multispace0, u64};
-use nom::combinator::{map, opt, consumed};
-use nom::error::ParseError;
-use nom::sequence::{delimited, pair, preceded};
-use nom::{IResult, InputLength, Parser, InputTake, InputIter, InputTakeAtPosition, AsChar};
+use nom::branch::alt;
+use nom::bytes::complete::take_while1;
+use nom::character::complete::{char, not_line_ending, u64};
+use nom::combinator::{map, opt, consumed, recognize};
+use nom::multi::{many0_count, many1_count};
+use nom::sequence::{delimited, pair, preceded, tuple};
+use nom::error::{ErrorKind, ParseError};
+use nom::Parser;
+use nom_locate::LocatedSpan;
 
-type Decimal = (i64, Option<(usize, u64)>);
+use crate::ast::Span;
+use crate::error::Error;
 
-pub fn dec_to_i64(dec: Decimal, precision: u64) -> i64 {
-    let fracional = dec.1.unwrap_or((0, 0));
-    dec.0 * precision as i64 + (fracional.1 * precision / 10_u64.pow(fracional.0 as u32)) as i64
+pub type Input<'a> = LocatedSpan<&'a str>;
+pub type IResult<I, O, E = Error<I>> = nom::IResult<I, O, E>;
+
+pub type Decimal = (bool, u64, Option<(usize, u64)>);
+
+/// Converts a decimal to an integer number of `1 / precision` units, `None` if it doesn't fit in an `i64`
+pub fn dec_to_i64(dec: Decimal, precision: u64) -> Option<i64> {
+    let (digits, fractional) = dec.2.unwrap_or((0, 0));
+    let fractional = fractional as u128 * precision as u128 / 10_u128.checked_pow(digits as u32)?;
+    let value = i64::try_from((dec.1 as u128).checked_mul(precision as u128)?.checked_add(fractional)?).ok()?;
+    Some(if dec.0 { -value } else { value })
 }
 
 #[inline]
@@ -22,27 +34,161 @@ pub fn is_kql_wildcard_identifier(chr: char) -> bool {
     is_kql_identifier(chr) || chr == '*'
 }
 
-pub fn take_identifier(i: &str) -> IResult<&str, &str> {
-    let (input, identifier) = take_while1(is_kql_identifier)(i)?;
+pub fn take_identifier(i: Input) -> IResult<Input, Input> {
+    // a leading `$` marks names generated by operators, like the `$table` column of `search`
+    let (input, identifier) = recognize::<_, _, (), _>(pair(opt(char('$')), take_while1(is_kql_identifier)))(i)
+        .map_err(|_| nom::Err::Error(Error::expected(i, "identifier")))?;
 
-    // exclude reserved keywords
-    if identifier == "by" {
-        return Err(nom::Err::Error(nom::error::Error::new(i, nom::error::ErrorKind::Tag)));
+    // exclude reserved keywords, these have to be quoted like `['by']`
+    if KEYWORDS.contains(identifier.fragment()) {
+        return Err(nom::Err::Error(Error::expected(i, "identifier")));
     }
     Ok((input, identifier))
 }
 
-pub fn decimal_number(i: &str) -> IResult<&str, Decimal> {
-    pair(i64, opt(preceded(tag("."), map(consumed(u64::<&str, _>), |(i, x)| (i.len(), x)))))(i)
+/// Keywords which can't be used as a bare identifier, because they would be ambiguous where an
+/// optional expression precedes them like in `summarize by x` or `top-nested of x`
+pub const KEYWORDS: &[&str] = &["by", "of"];
+
+pub fn decimal_number(i: Input) -> IResult<Input, Decimal> {
+    tuple((
+        map(opt(tag("-")), |s| s.is_some()),
+        u64,
+        opt(preceded(tag("."), map(consumed(u64::<Input, _>), |(i, x)| (i.len(), x))))
+    ))(i)
+}
+
+/// Matches a token like `nom::bytes::complete::tag`, but reports the token as expected on failure
+pub fn tag<'a>(token: &'static str) -> impl Fn(Input<'a>) -> IResult<Input<'a>, Input<'a>> {
+    move |i| nom::bytes::complete::tag::<_, _, ()>(token)(i)
+        .map_err(|_| nom::Err::Error(Error::expected(i, token)))
+}
+
+/// Matches a keyword, which may not be directly followed by another identifier character
+pub fn keyword<'a>(token: &'static str) -> impl Fn(Input<'a>) -> IResult<Input<'a>, Input<'a>> {
+    move |i| {
+        let (rest, k) = tag(token)(i)?;
+        match rest.fragment().chars().next() {
+            Some(c) if is_kql_identifier(c) => Err(nom::Err::Error(Error::expected(i, token))),
+            _ => Ok((rest, k))
+        }
+    }
+}
+
+/// Case insensitive variant of [`tag`]
+pub fn tag_no_case<'a>(token: &'static str) -> impl Fn(Input<'a>) -> IResult<Input<'a>, Input<'a>> {
+    move |i| nom::bytes::complete::tag_no_case::<_, _, ()>(token)(i)
+        .map_err(|_| nom::Err::Error(Error::expected(i, token)))
+}
+
+/// Maps the output of the parser, `None` fails without backtracking, like for a number out of range
+pub fn checked<'a, O, P, F, G>(mut f: F, g: G) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, P>
+where
+    F: Parser<Input<'a>, O, Error<Input<'a>>>,
+    G: Fn(O) -> Option<P>,
+{
+    move |i: Input<'a>| {
+        let (rest, o) = f.parse(i)?;
+        match g(o) {
+            Some(p) => Ok((rest, p)),
+            None => Err(nom::Err::Failure(Error::from_error_kind(i, ErrorKind::TooLarge)))
+        }
+    }
+}
+
+/// Runs the parser and returns the span of the consumed input, without trailing whitespace and comments
+pub fn spanned<'a, O, F>(mut f: F) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, (O, Span)>
+where
+    F: Parser<Input<'a>, O, Error<Input<'a>>>,
+{
+    move |i: Input<'a>| {
+        let (rest, o) = f.parse(i)?;
+        let consumed = &i.fragment()[..rest.location_offset() - i.location_offset()];
+        let start = i.location_offset();
+        Ok((rest, (o, Span::new(start, start + trim_end_trivia(consumed).len()))))
+    }
+}
+
+/// Removes trailing whitespace and comments
+fn trim_end_trivia(s: &str) -> &str {
+    &s[..trivia_start(s)]
+}
+
+/// Finds the start of the trailing whitespace and comments, skipping over string literals
+/// the same way the string parsers read them
+fn trivia_start(s: &str) -> usize {
+    let mut end = 0;
+    let mut n = 0;
+    while let Some(c) = s[n..].chars().next() {
+        let rest = &s[n..];
+        n = if rest.starts_with("//") {
+            rest.find('\n').map_or(s.len(), |m| n + m)
+        } else if c.is_whitespace() {
+            n + c.len_utf8()
+        } else {
+            end = if rest.starts_with("```") || rest.starts_with("~~~") {
+                rest[3..].find(&rest[..3]).map_or(s.len(), |m| n + m + 6)
+            } else if rest.starts_with("@\"") || rest.starts_with("@'") {
+                n + 1 + verbatim_len(&rest[1..])
+            } else if c == '"' || c == '\'' {
+                n + quoted_len(rest)
+            } else {
+                n + c.len_utf8()
+            };
+            end
+        };
+    }
+    end
+}
+
+/// Length of a quoted string with backslash escapes, starting at its opening quote
+fn quoted_len(s: &str) -> usize {
+    let quote = s.as_bytes()[0];
+    let mut bytes = s.bytes().enumerate().skip(1);
+    while let Some((n, b)) = bytes.next() {
+        match b {
+            b'\\' => { bytes.next(); },
+            b if b == quote => return n + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+/// Length of a verbatim string where quotes are escaped by doubling them, starting at its opening quote
+fn verbatim_len(s: &str) -> usize {
+    let quote = s.as_bytes()[0];
+    let mut bytes = s.bytes().enumerate().skip(1).peekable();
+    while let Some((n, b)) = bytes.next() {
+        if b == quote {
+            match bytes.peek() {
+                Some((_, b)) if *b == quote => { bytes.next(); },
+                _ => return n + 1
+            }
+        }
+    }
+    s.len()
+}
+
+/// Matches a `//` comment up to the end of the line
+fn comment(i: Input) -> IResult<Input, Input> {
+    recognize(pair(nom::bytes::complete::tag("//"), not_line_ending))(i)
+}
+
+/// Matches zero or more whitespace characters and comments
+pub fn multispace0(i: Input) -> IResult<Input, Input> {
+    recognize(many0_count(alt((nom::character::complete::multispace1, comment))))(i)
+}
+
+/// Matches one or more whitespace characters and comments
+pub fn multispace1(i: Input) -> IResult<Input, Input> {
+    recognize(many1_count(alt((nom::character::complete::multispace1, comment))))(i)
 }
 
-pub fn trim<I, O, E, F>(f: F) -> impl FnMut(I) -> IResult<I, O, E>
+/// Runs the parser with surrounding whitespace and comments removed
+pub fn trim<'a, O, F>(f: F) -> impl FnMut(Input<'a>) -> IResult<Input<'a>, O>
 where
-    I: Clone + InputLength + InputTake + InputIter,
-    I: InputTakeAtPosition,
-    <I as InputTakeAtPosition>::Item: AsChar + Clone,
-    F: Parser<I, O, E>,
-    E: ParseError<I>,
+    F: Parser<Input<'a>, O, Error<Input<'a>>>,
 {
     delimited(multispace0, f, multispace0)
 }
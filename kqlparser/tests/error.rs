@@ -0,0 +1,43 @@
+use kqlparser::parser::parse;
+
+#[test]
+fn trailing_input() {
+    let e = parse("T | where a == 1 )").unwrap_err();
+    assert_eq!((e.offset, e.line, e.column), (17, 1, 18));
+    assert_eq!(e.expected, ["|", ";"]);
+    assert_eq!(e.message, "expected one of `|`, `;`, found `)`");
+    assert_eq!(e.to_string(), "expected one of `|`, `;`, found `)` at line 1, column 18");
+}
+
+#[test]
+fn expected_tokens() {
+    let e = parse("T | wher a").unwrap_err();
+    assert_eq!((e.line, e.column), (1, 5));
+    assert!(e.expected.iter().any(|t| t == "where"));
+    assert!(e.expected.iter().any(|t| t == "summarize"));
+    assert!(e.message.starts_with("expected one of `as`, "));
+    assert!(e.message.ends_with(", found `wher`"));
+
+    let e = parse("T | take").unwrap_err();
+    assert_eq!(e.message, "unexpected end of input");
+}
+
+#[test]
+fn multi_line() {
+    let e = parse("T\n| where a ==\n| project b").unwrap_err();
+    assert_eq!((e.offset, e.line, e.column), (15, 3, 1));
+    assert_eq!(e.expected, ["expression"]);
+    assert_eq!(e.message, "expected `expression`, found `|`");
+}
+
+#[test]
+fn out_of_range() {
+    let e = parse("print 99999999999999d").unwrap_err();
+    assert_eq!((e.line, e.column), (1, 7));
+    assert_eq!(e.message, "unexpected `99999999999999d`");
+    assert!(parse("print 106751d").is_ok());
+    assert!(parse("print 106752d").is_err());
+    assert!(parse("print timespan(3000000000:00)").is_err());
+    assert!(parse("print timespan(99999999999999.00:00)").is_err());
+    assert!(parse("print dynamic([99999999999999d])").is_err());
+}
@@ -0,0 +1,109 @@
+use kqlparser::format::{format, FormatOptions};
+use kqlparser::parser::parse;
+
+const QUERIES: &[&str] = &[
+    "T",
+    "cluster('c').database('d').T | take 10",
+    "T | where a == 1 and b != 2 or not(c) | project a, d = b * (c + 1) - -x",
+    "T | where -(5) < x and x == -5 and x between (1 .. 2 + 3) and y !between (ago(1d) .. now())",
+    "T | where a in (1, 2) and b !in~ ('x') and c has_any ('a', 'b') and d in (U | project d)",
+    "T | where s contains 'a\\'b\"c\\n' and s !has_cs @'C:\\x' and s matches regex '[a-z]+'",
+    "T | where ['by'] == ['a b'] and x.y[0].z == d['k'] and (-a).b == 1 and f(x)[1] == 2",
+    "T | where a - (b - c) == a - b - c and (a or b) and c",
+    "print 1, 2.5, 1e-7, int(3), long(null), real(-inf), decimal(1.25), true, bool(null)",
+    "print 5d, 90m, 5ms, 1.5s, -2h, 150tick, 0.0000001s, timespan(null), timespan(1.02:03:04)",
+    "print datetime(2020-01-02 03:04:05), datetime(2020-01-02T03:04+0100), datetime(null)",
+    "print dynamic([1, 'a', {'k': [true, null]}, 5d, int(2), decimal(1.5), datetime(2020-01-02)]), dynamic(null)",
+    "print ['bool'](1), ['not'](2), ```x``` 'y'",
+    "T | as materialized=true U | consume decodeblocks=false | count | getschema",
+    "T | distinct a, b | evaluate bag_unpack(d) | extend x = 1, y = 'a'",
+    "T | distinct * | distinct a, n = strlen(b), tolower(c)",
+    "T | facet by a, b with (top 1 by c) | fork (take 1), f = (count | take 2)",
+    "T | join kind=inner (U | where x > 1) on a, b | lookup (U) on c",
+    "T | make-series count() default=0, x = avg(a) on ts from ago(7d) to now() step 1h by Host, b = c.d | make-series kind=nonempty sum(a) on t step 5",
+    "T | mv-apply a = b to typeof(long) on (take 1) | mv-expand c",
+    "T | mv-expand bagexpansion=array with_itemindex=i a, b to typeof(string), c = d.e to typeof(long) limit 5 | mv-expand ['limit']",
+    "T | parse kind=regex a with * 'x' b:long 'y' c | parse-where a with 'a' b | parse-kv a as (x:long, y:string) with (pair_delimiter=',', quote='\"')",
+    "T | partition by a (take 1) | partition strategy=native by b (count)",
+    "T | partition by a U | where b > 1",
+    "T | project-away a | project-keep b | project-rename c = d | project-reorder a* asc, b granny-desc, c",
+    "T | reduce by a with threshold=5, characters='x' | render timechart with (title='t', ysplit=panels)",
+    "T | sample 5 | sample-distinct 3 by a | sample-distinct 2 of b | serialize | serialize n = row_number()",
+    "T | summarize count(), x = avg(a) by b, bin(c, 1h) | summarize by a | summarize sum(a)",
+    "T | scan with_match_id=m declare (x:long = 0, s:string) with (step s1: a == 'start' => x = 1, s = b; step s2 output=last: a > s1.a => x = s1.x + 1; step s3 output=none: true)",
+    "T | scan with (step s: true)",
+    "T | search kind=case_sensitive 'err*' and not(a == 1) | summarize count() by $table",
+    "T | sort by a, b | take 5 | top 3 by a * 2 | top 3 by a asc nulls last",
+    "T | order by a asc nulls first, strlen(b) desc, c nulls first | sort by ['asc'] asc, d desc nulls last",
+    "T | top-hitters 10 of Url | top-hitters 5 of u = tolower(Url) by Bytes * 2",
+    "T | top-nested 3 of Country by count(), top-nested of c = strlen(City) with others = -1 by s = sum(Bytes) asc, top-nested 2 of d by max(e) desc",
+    "T | top-nested 2 of City by sum(Bytes) with others = \"Other\", top-nested of Host by count() desc with others = \"\"",
+    "T | union kind=outer U, (datatable (a:long) [1, 2])",
+    "datatable (a:long, b:string) [1, 'x', 2, 'y'] | where a == 1",
+    "externaldata (a:long, b:datetime) ['https://x/y.csv']",
+    "find in (T, U) where a == 1 project a, b",
+    "find x == 1",
+    "find withsource=S in (T*, U) where a == 1 project-smart | project S, pack_",
+    "find where x > 1 project a",
+    "T | where a == h'secret' and b has H@'C:\\x' and c != h\"a\" 'b'",
+    "search 'foo'",
+    "search in (T, U) (Col:'bar' or 'b*z') and x > 1",
+    "range x from 1 to 10 step 2 | extend y = x % 3",
+    "union T, U",
+    "let T = range x from 1 to 3 step 1; union withsource=t (T | where x > 1), (T), U",
+    "let x = 1; let t = T | where a > x; t | count",
+    "let f = (a:long, b:string = 'x') { strcat(b, a) }; print f(1), f(2, 'y')",
+    "let f = view (T:(a:long, b:string), U:(*), n:int = 5) { let m = n * 2; let V = T | where a > m; V | union U }; f(A, B) | invoke f(C)",
+    "let f = () { T | where a > 1 }; f() | count",
+    "let t = materialize(T | summarize count() by a); let n = toscalar(t | count); t | where a > n and b == ['toscalar'](c)",
+    "let f = () { let m = materialize(T); m }; print x = toscalar(f() | take 1) + 1, toscalar(U).b",
+    "set notruncation; set query_now = datetime(2020-01-01); set ['a b'] = 'x'; print now()",
+    "declare query_parameters(from:datetime, user:string = 'x', n:long = 10); T | where user == ['user'] and ts > from | take 5",
+];
+
+fn assert_round_trip(query: &str, options: &FormatOptions) {
+    let ast = parse(query).unwrap_or_else(|e| panic!("failed to parse `{}`: {:?}", query, e));
+    let formatted = format(&ast, options);
+    let reparsed = parse(&formatted).unwrap_or_else(|e| panic!("failed to parse formatted `{}`: {:?}", formatted, e));
+    assert_eq!(ast, reparsed, "`{}` was formatted as `{}`", query, formatted);
+}
+
+#[test]
+fn round_trip() {
+    for query in QUERIES {
+        assert_round_trip(query, &FormatOptions { operator_per_line: false });
+        assert_round_trip(query, &FormatOptions { operator_per_line: true });
+    }
+}
+
+#[test]
+fn layout() {
+    let ast = parse("T|where a==1|project  b ; print x=1").unwrap();
+    assert_eq!(format(&ast, &FormatOptions { operator_per_line: true }), "T\n| where a == 1\n| project b;\nprint x = 1");
+    assert_eq!(format(&ast, &FormatOptions { operator_per_line: false }), "T | where a == 1 | project b; print x = 1");
+    assert_eq!(ast[0].to_string(), "T | where a == 1 | project b");
+    assert_eq!(format!("{:#}", ast[0]), "T\n| where a == 1\n| project b");
+}
+
+#[test]
+fn parentheses() {
+    let formatted = |q: &str| format(&parse(q).unwrap(), &FormatOptions::default());
+    assert_eq!(formatted("print (a + b) * c, a + (b * c), a - (b - c), -(5), -(a)"), "print (a + b) * c, a + b * c, a - (b - c), -(5), -a");
+    assert_eq!(formatted("print real(nan), decimal(+inf)"), "print real(nan), decimal(+inf)");
+    assert_eq!(formatted("print (a or b) and c, (a == b) == c, x between ((a) .. (b + 1))"), "print (a or b) and c, a == b == c, x between (a .. b + 1)");
+}
+
+#[test]
+fn obfuscated() {
+    let formatted = |q: &str| format(&parse(q).unwrap(), &FormatOptions::default());
+    assert_eq!(formatted("print h'a', H@'C:\\x', 'b' h'c', 'd'"), "print h\"a\", h\"C:\\\\x\", h\"bc\", \"d\"");
+}
+
+#[test]
+fn keywords() {
+    // words which are only keywords after an expression remain valid column names
+    let formatted = |q: &str| format(&parse(q).unwrap(), &FormatOptions { operator_per_line: false });
+    assert_eq!(formatted("T | project on, asc, with, set, let, has, nulls | sort by asc desc"), "T | project on, asc, with, set, let, has, nulls | sort by asc desc nulls last");
+    assert_eq!(formatted("T | summarize count() by ['by'], ['of']"), "T | summarize count() by [\"by\"], [\"of\"]");
+    assert!(parse("T | project by").is_err());
+}
@@ -0,0 +1,89 @@
+use kqlparser::ast::{Dynamic, Expr, ExprKind, LetExpression, Literal, OperatorKind, SourceKind, Statement, Type};
+use kqlparser::parser::parse;
+
+fn expr(e: &str) -> Expr {
+    let statement = parse(&format!("print {}", e)).unwrap().remove(0);
+    match statement {
+        Statement::TabularExpression(t) => match t.source.kind {
+            SourceKind::Print(mut values) => values.remove(0).1,
+            _ => panic!("expected print source")
+        },
+        _ => panic!("expected tabular expression")
+    }
+}
+
+fn operator(query: &str) -> OperatorKind {
+    match parse(query).unwrap().remove(0) {
+        Statement::TabularExpression(mut t) => t.operators.remove(0).kind,
+        _ => panic!("expected tabular expression")
+    }
+}
+
+#[test]
+fn special_values() {
+    assert!(matches!(expr("real(-inf)").kind, ExprKind::Literal(Literal::Real(Some(x))) if x == f32::NEG_INFINITY));
+    assert!(matches!(expr("real(+inf)").kind, ExprKind::Literal(Literal::Real(Some(x))) if x == f32::INFINITY));
+    assert!(matches!(expr("decimal(-inf)").kind, ExprKind::Literal(Literal::Decimal(Some(x))) if x == f64::NEG_INFINITY));
+    assert!(matches!(expr("decimal(1.5)").kind, ExprKind::Literal(Literal::Decimal(Some(x))) if x == 1.5));
+    assert!(matches!(expr("decimal(null)").kind, ExprKind::Literal(Literal::Decimal(None))));
+
+    // without a type, `inf` is a column
+    assert!(matches!(expr("-inf").kind, ExprKind::Negate(e) if matches!(e.kind, ExprKind::Ident(ref i) if i == "inf")));
+}
+
+#[test]
+fn word_boundaries() {
+    assert!(matches!(expr("1day").kind, ExprKind::Literal(Literal::Timespan(Some(86_400_000_000_000)))));
+    assert!(matches!(expr("-0.5h").kind, ExprKind::Literal(Literal::Timespan(Some(-1_800_000_000_000)))));
+    assert!(matches!(expr("timespan(2 h)").kind, ExprKind::Literal(Literal::Timespan(Some(7_200_000_000_000)))));
+    assert!(parse("print 1dx").is_err());
+    assert!(parse("print 1d2").is_err());
+
+    assert!(matches!(expr("true").kind, ExprKind::Literal(Literal::Bool(Some(true)))));
+    assert!(matches!(expr("truex").kind, ExprKind::Ident(ref i) if i == "truex"));
+    assert!(matches!(expr("falsehood").kind, ExprKind::Ident(ref i) if i == "falsehood"));
+    assert_eq!(expr("dynamic([1d, true])").kind, ExprKind::Literal(Literal::Dynamic(Some(Dynamic::Array(vec![
+        Some(Dynamic::Timespan(Some(86_400_000_000_000))),
+        Some(Dynamic::Bool(Some(true)))
+    ])))));
+}
+
+#[test]
+fn options() {
+    // an option is never followed by another `=` or `~`, as that starts a predicate
+    match parse("find a == 1").unwrap().remove(0) {
+        Statement::TabularExpression(t) => match t.source.kind {
+            SourceKind::Find(options, _, e, _) => {
+                assert!(options.is_empty());
+                assert!(matches!(e.kind, ExprKind::Equals(..)));
+            },
+            _ => panic!("expected find source")
+        },
+        _ => panic!("expected tabular expression")
+    }
+    assert!(matches!(operator(r#"T | parse a =~ "x" with * "y" b"#), OperatorKind::Parse(o, e, _) if o.is_empty() && matches!(e.kind, ExprKind::EqualsCaseInsensitive(..))));
+    assert!(matches!(operator(r#"T | parse kind=simple a with * "y" b"#), OperatorKind::Parse(o, e, _) if o.len() == 1 && matches!(e.kind, ExprKind::Ident(_))));
+}
+
+#[test]
+fn operator_whitespace() {
+    assert!(matches!(operator("T | consume"), OperatorKind::Consume(o) if o.is_empty()));
+    assert!(matches!(operator("T | consume decodeblocks=true"), OperatorKind::Consume(o) if o.len() == 1));
+    assert!(matches!(operator("T | getschema"), OperatorKind::Getschema));
+    assert!(parse("T | getschemax").is_err());
+    assert!(matches!(operator("T | serialize"), OperatorKind::Serialize(v) if v.is_empty()));
+    assert!(matches!(operator("T | serialize x = 1"), OperatorKind::Serialize(v) if v.len() == 1));
+    assert!(matches!(operator("T | evaluate bag_unpack(d)"), OperatorKind::Evaluate(o, f, _) if o.is_empty() && f == "bag_unpack"));
+    assert!(matches!(operator("T | evaluate a=1 bag_unpack(d)"), OperatorKind::Evaluate(o, _, _) if o.len() == 1));
+    assert!(matches!(operator("T | mv-apply a = b to typeof(long) on (take 1)"), OperatorKind::MvApply(v, _) if v[0].1 == Some(Type::Long)));
+    assert!(matches!(operator("T | mv-apply a = b on (take 1)"), OperatorKind::MvApply(v, _) if v[0].1.is_none()));
+}
+
+#[test]
+fn scalar_let() {
+    // a scalar is only taken when the statement ends after it
+    assert!(matches!(parse("let x = 1; T").unwrap().remove(0), Statement::Let(_, LetExpression::Scalar(_))));
+    assert!(matches!(parse("let x = 1").unwrap().remove(0), Statement::Let(_, LetExpression::Scalar(_))));
+    assert!(matches!(parse("let x = T | take 1; x").unwrap().remove(0), Statement::Let(_, LetExpression::Tabular(_))));
+    assert!(!matches!(parse("let x = 1 | take 1").map(|mut s| s.remove(0)), Ok(Statement::Let(_, LetExpression::Scalar(_)))));
+}
@@ -0,0 +1,91 @@
+use kqlparser::ast::{Expr, ExprKind, Literal, SourceKind, Span, Statement};
+use kqlparser::parser::parse;
+
+fn expr(e: &str) -> Expr {
+    let statement = parse(&format!("print {}", e)).unwrap().remove(0);
+    match statement {
+        Statement::TabularExpression(t) => match t.source.kind {
+            SourceKind::Print(mut values) => values.remove(0).1,
+            _ => panic!("expected print source")
+        },
+        _ => panic!("expected tabular expression")
+    }
+}
+
+/// Parenthesized and unparenthesized forms parse to the same tree as spans are ignored by comparison
+fn assert_same(e: &str, explicit: &str) {
+    assert_eq!(expr(e), expr(explicit), "`{}` should parse as `{}`", e, explicit);
+}
+
+#[test]
+fn arithmetic() {
+    assert_same("1 + 2 * 3", "1 + (2 * 3)");
+    assert_same("1 * 2 + 3", "(1 * 2) + 3");
+    assert_same("a - b - c", "(a - b) - c");
+    assert_same("a / b * c % d", "((a / b) * c) % d");
+    assert_same("a + b * c - d / e", "(a + (b * c)) - (d / e)");
+}
+
+#[test]
+fn comparison() {
+    assert_same("a + 1 > b * 2", "(a + 1) > (b * 2)");
+    assert_same("a == b + c", "a == (b + c)");
+    assert_same("a - 1 <= b", "(a - 1) <= b");
+    assert_same("a contains b + \"x\"", "a contains (b + \"x\")");
+    assert_same("a + 1 in (1, 2)", "(a + 1) in (1, 2)");
+    assert_same("a * 2 between (b .. c + 1)", "(a * 2) between (b .. (c + 1))");
+}
+
+#[test]
+fn boolean() {
+    assert_same("a == 1 and b == 2", "(a == 1) and (b == 2)");
+    assert_same("a == 1 or b == 2 and c == 3", "(a == 1) or ((b == 2) and (c == 3))");
+    assert_same("a and b or c and d", "(a and b) or (c and d)");
+    assert_same("a or b or c", "(a or b) or c");
+    assert_same("a > 1 + 2 and b in (1) or c has \"x\"", "((a > (1 + 2)) and (b in (1))) or (c has \"x\")");
+}
+
+#[test]
+fn unary() {
+    assert_same("-a * b", "(-a) * b");
+    assert_same("a - -b", "a - (-b)");
+    assert_same("not(a) and b", "(not(a)) and b");
+    assert_same("not(a == 1 or b == 2)", "not((a == 1) or (b == 2))");
+
+    assert!(matches!(expr("-a").kind, ExprKind::Negate(_)));
+    assert!(matches!(expr("not(a)").kind, ExprKind::Not(_)));
+    assert!(matches!(expr("-1").kind, ExprKind::Literal(Literal::Long(Some(-1)))));
+    assert!(matches!(expr("notes").kind, ExprKind::Ident(ref i) if i == "notes"));
+}
+
+#[test]
+fn spans() {
+    let e = expr("a == 1 and b == 2");
+    assert_eq!(e.span, Span::new(6, 23));
+    match e.kind {
+        ExprKind::And(l, r) => {
+            assert_eq!(l.span, Span::new(6, 12));
+            assert_eq!(r.span, Span::new(17, 23));
+        },
+        _ => panic!("expected and")
+    }
+}
+
+#[test]
+fn hex() {
+    assert!(matches!(expr("0xFF").kind, ExprKind::Literal(Literal::Long(Some(255)))));
+    assert!(matches!(expr("long(0xFFFFFFFFFFFFFFFF)").kind, ExprKind::Literal(Literal::Long(Some(-1)))));
+    assert!(matches!(expr("int(0x80000000)").kind, ExprKind::Literal(Literal::Int(Some(i32::MIN)))));
+
+    let e = parse("print 0x1FFFFFFFFFFFFFFFF").unwrap_err();
+    assert_eq!((e.line, e.column), (1, 9));
+    assert!(parse("print int(0x100000000)").is_err());
+}
+
+#[test]
+fn spans_before_comments() {
+    assert_eq!(expr("a == @'C:\\' // note\n").span, Span::new(6, 17));
+    assert_eq!(expr("a == @\"x\"\"//\" // note\n").span, Span::new(6, 19));
+    assert_eq!(expr("a == ```x // y``` // note\n").span, Span::new(6, 23));
+    assert_eq!(expr("a == 'x\\'//' // note\n").span, Span::new(6, 18));
+}
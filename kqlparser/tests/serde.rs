@@ -0,0 +1,24 @@
+#![cfg(feature = "serde")]
+
+use kqlparser::ast::Statement;
+use kqlparser::parser::parse;
+
+#[test]
+fn round_trip() {
+    let statements = parse("T | join kind=inner remote=auto (U) on a | where b == dynamic({'z': 1, 'a': [2d, null]})").unwrap();
+    let json = serde_json::to_string(&statements).unwrap();
+    let deserialized: Vec<Statement> = serde_json::from_str(&json).unwrap();
+    assert_eq!(statements, deserialized);
+}
+
+#[test]
+fn deterministic() {
+    let json = |q: &str| serde_json::to_string(&parse(q).unwrap()).unwrap();
+    let a = json("T | join remote=auto kind=inner (U) on a | extend x = dynamic({'z': 1, 'a': 2})");
+    let b = json("T | join kind=inner remote=auto (U) on a | extend x = dynamic({'a': 2, 'z': 1})");
+
+    // spans differ but options and dictionaries are serialized in key order
+    assert!(a.contains(r#"{"kind":{"String":"inner"},"remote":{"String":"auto"}}"#));
+    assert!(a.contains(r#"{"a":{"Long":2},"z":{"Long":1}}"#));
+    assert_eq!(a.replace(char::is_numeric, ""), b.replace(char::is_numeric, ""));
+}
@@ -0,0 +1,51 @@
+use kqlparser::ast::*;
+use kqlparser::format::{format, FormatOptions};
+use kqlparser::parser::parse;
+
+#[derive(Default)]
+struct Tables(Vec<String>);
+
+impl Visitor for Tables {
+    fn visit_source(&mut self, s: &Source) {
+        if let SourceKind::Reference(_, _, t) = &s.kind {
+            self.0.push(t.clone());
+        }
+        walk_source(self, s)
+    }
+}
+
+struct Redact;
+
+impl VisitorMut for Redact {
+    fn visit_literal_mut(&mut self, l: &mut Literal) {
+        if let Literal::String(s, _) = l {
+            *s = "***".into();
+        }
+    }
+}
+
+#[test]
+fn table_usage() {
+    let query = "let t = A | count; B \
+        | join (C | where a in (D | project a)) on a \
+        | lookup (E) on b \
+        | fork (union F, (G)), (facet by a with (join (H) on a)) \
+        | mv-apply v = w on (join (I) on v) \
+        | partition by a J";
+
+    let mut tables = Tables::default();
+    for s in parse(query).unwrap() {
+        tables.visit_statement(&s);
+    }
+    assert_eq!(tables.0, ["A", "B", "C", "D", "E", "F", "G", "H", "I", "J"]);
+}
+
+#[test]
+fn redact_literals() {
+    let mut statements = parse("T | where a == 'secret' and b in ('x', 1) | extend c = strcat(d, \"y\")").unwrap();
+    statements.iter_mut().for_each(|s| Redact.visit_statement_mut(s));
+    assert_eq!(
+        format(&statements, &FormatOptions { operator_per_line: false }),
+        "T | where a == \"***\" and b in (\"***\", 1) | extend c = strcat(d, \"***\")"
+    );
+}